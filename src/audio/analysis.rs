@@ -0,0 +1,160 @@
+use std::collections::VecDeque;
+
+use crate::audio::fft::spectrum;
+
+const BASS: (f32, f32) = (20.0, 250.0);
+const MID: (f32, f32) = (250.0, 4000.0);
+const TREBLE: (f32, f32) = (4000.0, 16000.0);
+
+/// Analysed frames a beat is judged against, about two seconds at the usual frame rate.
+const HISTORY: usize = 40;
+/// How far above its recent average the bass has to jump to count as a beat.
+const BEAT_THRESHOLD: f32 = 1.5;
+/// Frames after a beat before another can be called, so one kick isn't counted twice.
+const REFRACTORY: u32 = 3;
+/// How quickly the loudness a band is scaled against falls back after a peak, per frame.
+const PEAK_DECAY: f32 = 0.98;
+
+/// Energy in the bass, mid and treble ranges.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Bands {
+    pub bass: f32,
+    pub mid: f32,
+    pub treble: f32,
+}
+
+/// What one window of audio sounds like: band energies scaled to `0.0..=1.0` against their
+/// recent peaks, and whether it lands on a beat.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Features {
+    pub bands: Bands,
+    pub beat: bool,
+}
+
+/// Turns successive windows of audio into [`Features`].
+#[derive(Debug, Clone)]
+pub struct Analyzer {
+    sample_rate: u32,
+    peaks: Bands,
+    history: VecDeque<f32>,
+    since_beat: u32,
+}
+
+impl Analyzer {
+    pub fn new(sample_rate: u32) -> Analyzer {
+        Analyzer {
+            sample_rate,
+            peaks: Bands::default(),
+            history: VecDeque::with_capacity(HISTORY),
+            since_beat: REFRACTORY,
+        }
+    }
+
+    /// Mean spectral energy of `window` in each range.
+    pub fn bands(&self, window: &[f32]) -> Bands {
+        let spectrum = spectrum(window);
+        let hz_per_bin = self.sample_rate as f32 / (2 * spectrum.len()) as f32;
+        let energy = |(low, high): (f32, f32)| {
+            let bins: Vec<f32> = spectrum
+                .iter()
+                .enumerate()
+                .filter(|(k, _)| (low..high).contains(&(*k as f32 * hz_per_bin)))
+                .map(|(_, m)| m * m)
+                .collect();
+            if bins.is_empty() { 0.0 } else { bins.iter().sum::<f32>() / bins.len() as f32 }
+        };
+
+        Bands { bass: energy(BASS), mid: energy(MID), treble: energy(TREBLE) }
+    }
+
+    pub fn analyze(&mut self, window: &[f32]) -> Features {
+        let raw = self.bands(window);
+
+        let average = self.history.iter().sum::<f32>() / self.history.len().max(1) as f32;
+        self.since_beat += 1;
+        let beat = self.history.len() >= HISTORY / 4
+            && raw.bass > average * BEAT_THRESHOLD
+            && raw.bass > 1e-6
+            && self.since_beat > REFRACTORY;
+        if beat {
+            self.since_beat = 0;
+        }
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(raw.bass);
+
+        let scale = |peak: &mut f32, value: f32| {
+            *peak = (*peak * PEAK_DECAY).max(value);
+            if *peak > 0.0 { value / *peak } else { 0.0 }
+        };
+        let bands = Bands {
+            bass: scale(&mut self.peaks.bass, raw.bass),
+            mid: scale(&mut self.peaks.mid, raw.mid),
+            treble: scale(&mut self.peaks.treble, raw.treble),
+        };
+
+        Features { bands, beat }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+    use rstest::rstest;
+
+    const RATE: u32 = 22050;
+
+    fn tone(hz: f32, seconds: f32) -> Vec<f32> {
+        (0..(seconds * RATE as f32) as usize)
+            .map(|i| (2.0 * PI * hz * i as f32 / RATE as f32).sin())
+            .collect()
+    }
+
+    /// A 60 Hz kick lasting 50 ms every half second, over quiet hiss.
+    fn kicks(seconds: f32) -> Vec<f32> {
+        (0..(seconds * RATE as f32) as usize)
+            .map(|i| {
+                let t = i as f32 / RATE as f32;
+                let hiss = 0.01 * (i as f32 * 12.9898).sin().fract();
+                if t % 0.5 < 0.05 { (2.0 * PI * 60.0 * t).sin() + hiss } else { hiss }
+            })
+            .collect()
+    }
+
+    #[rstest]
+    #[case(60.0, "bass")]
+    #[case(1000.0, "mid")]
+    #[case(8000.0, "treble")]
+    fn test_tone_lands_in_its_band(#[case] hz: f32, #[case] band: &str) {
+        let b = Analyzer::new(RATE).bands(&tone(hz, 0.1)[..2048]);
+
+        let loudest = if b.bass > b.mid && b.bass > b.treble {
+            "bass"
+        } else if b.mid > b.treble {
+            "mid"
+        } else {
+            "treble"
+        };
+        assert_eq!(loudest, band);
+    }
+
+    #[rstest]
+    fn test_beats_follow_kicks() {
+        let audio = kicks(4.0);
+        let mut analyzer = Analyzer::new(RATE);
+        let hop = RATE as usize / 20;
+
+        let beats = (1..80)
+            .filter(|k| {
+                let end = k * hop;
+                analyzer.analyze(&audio[end.saturating_sub(1024)..end]).beat
+            })
+            .count();
+
+        // eight kicks, less the ones heard before there's enough history to judge by
+        assert!((5..=8).contains(&beats), "{}", beats);
+    }
+}
@@ -0,0 +1,86 @@
+use std::f32::consts::PI;
+
+/// In-place radix-2 FFT of the complex signal `re + i·im`, whose length must be a power of two.
+pub fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    assert!(n.is_power_of_two() && im.len() == n, "fft needs two buffers of the same power-of-two length");
+
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_im, w_re) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Magnitudes of bins `0..n/2` of `samples` under a Hann window, zero-padded up to a power of
+/// two `n`. Bin `k` is centred on `k * sample_rate / n` Hz.
+pub fn spectrum(samples: &[f32]) -> Vec<f32> {
+    let n = samples.len().max(2).next_power_of_two();
+    let last = (samples.len().max(2) - 1) as f32;
+
+    let mut re: Vec<f32> = (0..n)
+        .map(|i| match samples.get(i) {
+            Some(s) => s * (0.5 - 0.5 * (2.0 * PI * i as f32 / last).cos()),
+            None => 0.0,
+        })
+        .collect();
+    let mut im = vec![0.0; n];
+    fft(&mut re, &mut im);
+
+    re.iter().zip(im.iter()).take(n / 2).map(|(r, i)| (r * r + i * i).sqrt()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_impulse_is_flat() {
+        let mut re = vec![1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        let mut im = vec![0.0; 8];
+
+        fft(&mut re, &mut im);
+
+        assert!(re.iter().all(|r| (r - 1.0).abs() < 1e-6));
+        assert!(im.iter().all(|i| i.abs() < 1e-6));
+    }
+
+    #[rstest]
+    #[case(8)]
+    #[case(100)]
+    fn test_sine_peaks_in_its_bin(#[case] bin: usize) {
+        let samples: Vec<f32> = (0..1024).map(|i| (2.0 * PI * bin as f32 * i as f32 / 1024.0).sin()).collect();
+
+        let spectrum = spectrum(&samples);
+        let peak = spectrum.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)).unwrap().0;
+
+        assert_eq!(peak, bin);
+    }
+}
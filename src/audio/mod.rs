@@ -0,0 +1,209 @@
+//! Audio input for music-reactive lighting.
+//!
+//! A [`Signal`] hands out windows of mono samples: a decoded [`Wav`] plays back against the
+//! animation clock, so the same file always produces the same frames, while a [`Stream`]
+//! keeps the latest audio from stdin or a capture program such as `pw-record` or `arecord`.
+//! An [`Analyzer`] turns each window into band energies and beats.
+use std::collections::VecDeque;
+use std::io;
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::error;
+
+pub use analysis::{Analyzer, Bands, Features};
+pub use wav::{decode_pcm, SampleFormat, Wav};
+
+mod analysis;
+pub mod fft;
+mod wav;
+
+/// A source of audio to react to.
+pub trait Signal: Send {
+    fn sample_rate(&self) -> u32;
+    /// The `len` samples leading up to `at`, or `None` once the audio has run out.
+    fn window(&mut self, at: Duration, len: usize) -> Option<Vec<f32>>;
+}
+
+impl Signal for Wav {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn window(&mut self, at: Duration, len: usize) -> Option<Vec<f32>> {
+        let end = (at.as_secs_f64() * self.sample_rate as f64) as usize;
+        if end >= self.samples.len() {
+            return None;
+        }
+
+        let start = end.saturating_sub(len);
+        let mut window = vec![0.0; len - (end - start)];
+        window.extend_from_slice(&self.samples[start..end]);
+
+        Some(window)
+    }
+}
+
+/// Live PCM read on a background thread, keeping the most recent second or so.
+#[derive(Debug)]
+pub struct Stream {
+    sample_rate: u32,
+    buffer: Arc<Mutex<VecDeque<f32>>>,
+    ended: Arc<AtomicBool>,
+    child: Option<Child>,
+}
+
+impl Stream {
+    /// Raw interleaved PCM from any reader, e.g. a pipe or a socket.
+    pub fn from_reader(
+        mut reader: impl Read + Send + 'static,
+        format: SampleFormat,
+        channels: u16,
+        sample_rate: u32,
+    ) -> Stream {
+        let buffer = Arc::new(Mutex::new(VecDeque::new()));
+        let ended = Arc::new(AtomicBool::new(false));
+        let keep = sample_rate.max(1) as usize;
+
+        {
+            let (buffer, ended) = (buffer.clone(), ended.clone());
+            let frame = format.bytes() * channels.max(1) as usize;
+            thread::spawn(move || {
+                let mut chunk = vec![0; frame * 512];
+                let mut pending = vec![];
+                loop {
+                    match reader.read(&mut chunk) {
+                        Ok(0) => break,
+                        Ok(n) => {
+                            pending.extend_from_slice(&chunk[..n]);
+                            let whole = pending.len() - pending.len() % frame;
+                            let samples = decode_pcm(&pending[..whole], format, channels);
+                            pending.drain(..whole);
+
+                            let mut buffer = buffer.lock().unwrap();
+                            buffer.extend(samples);
+                            let excess = buffer.len().saturating_sub(keep);
+                            buffer.drain(..excess);
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(e) => {
+                            error!("Audio input failed: {}", e);
+                            break;
+                        }
+                    }
+                }
+                ended.store(true, Ordering::SeqCst);
+            });
+        }
+
+        Stream { sample_rate, buffer, ended, child: None }
+    }
+
+    /// PCM piped into this process, e.g. `arecord -f S16_LE -r 44100 -c 1 -t raw | ...`.
+    pub fn stdin(format: SampleFormat, channels: u16, sample_rate: u32) -> Stream {
+        Stream::from_reader(io::stdin(), format, channels, sample_rate)
+    }
+
+    /// Runs `program`, which must write raw PCM in `format` to its stdout, and reads that.
+    pub fn capture(
+        program: &str,
+        args: &[&str],
+        format: SampleFormat,
+        channels: u16,
+        sample_rate: u32,
+    ) -> io::Result<Stream> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdout = child.stdout.take().ok_or_else(|| io::Error::other("capture has no stdout"))?;
+
+        let mut stream = Stream::from_reader(stdout, format, channels, sample_rate);
+        stream.child = Some(child);
+        Ok(stream)
+    }
+
+    /// The default PipeWire source through `pw-record`.
+    pub fn pipewire(sample_rate: u32) -> io::Result<Stream> {
+        let rate = sample_rate.to_string();
+        Stream::capture(
+            "pw-record",
+            &["--format", "s16", "--rate", &rate, "--channels", "1", "-"],
+            SampleFormat::S16Le,
+            1,
+            sample_rate,
+        )
+    }
+
+    /// The default ALSA capture device through `arecord`.
+    pub fn alsa(sample_rate: u32) -> io::Result<Stream> {
+        let rate = sample_rate.to_string();
+        Stream::capture(
+            "arecord",
+            &["-q", "-f", "S16_LE", "-r", &rate, "-c", "1", "-t", "raw"],
+            SampleFormat::S16Le,
+            1,
+            sample_rate,
+        )
+    }
+}
+
+impl Signal for Stream {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// The latest audio, whatever `at` says; live input can't be sought.
+    fn window(&mut self, _at: Duration, len: usize) -> Option<Vec<f32>> {
+        let buffer = self.buffer.lock().unwrap();
+        if self.ended.load(Ordering::SeqCst) && buffer.is_empty() {
+            return None;
+        }
+
+        let mut window = vec![0.0; len.saturating_sub(buffer.len())];
+        window.extend(buffer.iter().skip(buffer.len().saturating_sub(len)));
+        Some(window)
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        if let Some(child) = self.child.as_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_wav_window_is_padded_at_the_start() {
+        let mut wav = Wav { sample_rate: 10, samples: (0..20).map(|i| i as f32).collect() };
+
+        assert_eq!(wav.window(Duration::from_millis(300), 4), Some(vec![0.0, 0.0, 1.0, 2.0]));
+        assert_eq!(wav.window(Duration::from_secs(1), 2), Some(vec![8.0, 9.0]));
+        assert_eq!(wav.window(Duration::from_secs(2), 2), None);
+    }
+
+    #[rstest]
+    fn test_stream_keeps_latest_samples() {
+        let bytes: Vec<u8> = (0..100i16).flat_map(|i| (i * 100).to_le_bytes()).collect();
+        let mut stream = Stream::from_reader(io::Cursor::new(bytes), SampleFormat::S16Le, 1, 8000);
+
+        while !stream.ended.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let window = stream.window(Duration::ZERO, 2).unwrap();
+        assert_eq!(window, vec![9800.0 / 32768.0, 9900.0 / 32768.0]);
+    }
+}
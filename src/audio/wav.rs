@@ -0,0 +1,170 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail};
+
+/// Decoded audio, mixed down to one channel of samples in `-1.0..=1.0`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wav {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+/// How raw interleaved PCM is laid out, for streams that come without a header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    U8,
+    S16Le,
+    S24Le,
+    S32Le,
+    F32Le,
+}
+
+impl SampleFormat {
+    pub fn bytes(&self) -> usize {
+        match self {
+            SampleFormat::U8 => 1,
+            SampleFormat::S16Le => 2,
+            SampleFormat::S24Le => 3,
+            SampleFormat::S32Le | SampleFormat::F32Le => 4,
+        }
+    }
+
+    fn decode(&self, b: &[u8]) -> f32 {
+        match self {
+            SampleFormat::U8 => (b[0] as f32 - 128.0) / 128.0,
+            SampleFormat::S16Le => i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+            SampleFormat::S24Le => (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.0,
+            SampleFormat::S32Le => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0,
+            SampleFormat::F32Le => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        }
+    }
+}
+
+/// Mixes interleaved frames of `channels` samples down to mono. A trailing partial frame is
+/// ignored.
+pub fn decode_pcm(bytes: &[u8], format: SampleFormat, channels: u16) -> Vec<f32> {
+    let channels = channels.max(1) as usize;
+    let frame = format.bytes() * channels;
+
+    bytes
+        .chunks_exact(frame)
+        .map(|f| f.chunks_exact(format.bytes()).map(|s| format.decode(s)).sum::<f32>() / channels as f32)
+        .collect()
+}
+
+impl Wav {
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Wav> {
+        Wav::parse(&fs::read(path)?)
+    }
+
+    /// Reads a RIFF/WAVE file with integer PCM of 8 to 32 bits or 32-bit float samples.
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<Wav> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            bail!("not a RIFF/WAVE file");
+        }
+
+        let mut format = None;
+        let mut rest = &bytes[12..];
+        while rest.len() >= 8 {
+            let id = &rest[0..4];
+            let size = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+            let body = rest.get(8..8 + size).unwrap_or(&rest[8..]);
+
+            match id {
+                b"fmt " => {
+                    if body.len() < 16 {
+                        bail!("fmt chunk is too short");
+                    }
+                    let tag = u16::from_le_bytes([body[0], body[1]]);
+                    let channels = u16::from_le_bytes([body[2], body[3]]);
+                    let sample_rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+                    let bits = u16::from_le_bytes([body[14], body[15]]);
+                    // 0xfffe is WAVE_FORMAT_EXTENSIBLE, whose subformat we take on trust
+                    let sample_format = match (tag, bits) {
+                        (1 | 0xfffe, 8) => SampleFormat::U8,
+                        (1 | 0xfffe, 16) => SampleFormat::S16Le,
+                        (1 | 0xfffe, 24) => SampleFormat::S24Le,
+                        (1 | 0xfffe, 32) => SampleFormat::S32Le,
+                        (3, 32) => SampleFormat::F32Le,
+                        _ => bail!("unsupported encoding {} with {} bits", tag, bits),
+                    };
+                    format = Some((sample_format, channels, sample_rate));
+                }
+                b"data" => {
+                    let (sample_format, channels, sample_rate) =
+                        format.ok_or_else(|| anyhow!("data chunk before fmt chunk"))?;
+                    return Ok(Wav {
+                        sample_rate,
+                        samples: decode_pcm(body, sample_format, channels),
+                    });
+                }
+                _ => {}
+            }
+
+            // chunks are padded to an even length
+            rest = rest.get(8 + size + size % 2..).unwrap_or(&[]);
+        }
+
+        bail!("no data chunk")
+    }
+
+    /// Writes 16-bit mono PCM, mostly for building test fixtures.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let data: Vec<u8> = self
+            .samples
+            .iter()
+            .flat_map(|s| ((s.clamp(-1.0, 1.0) * 32767.0) as i16).to_le_bytes())
+            .collect();
+
+        let mut bytes = vec![];
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&self.sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(self.sample_rate * 2).to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&data);
+
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_round_trip() {
+        let wav = Wav { sample_rate: 8000, samples: vec![0.0, 0.5, -0.5, 1.0] };
+
+        let back = Wav::parse(&wav.to_bytes()).unwrap();
+
+        assert_eq!(back.sample_rate, 8000);
+        for (a, b) in back.samples.iter().zip(wav.samples.iter()) {
+            assert!((a - b).abs() < 1e-3);
+        }
+    }
+
+    #[rstest]
+    #[case(SampleFormat::U8, vec![255, 255], vec![0.9921875])]
+    #[case(SampleFormat::S16Le, vec![0x00, 0x40, 0x00, 0xc0], vec![0.0])]
+    #[case(SampleFormat::S24Le, vec![0x00, 0x00, 0x40, 0x00, 0x00, 0x40], vec![0.5])]
+    fn test_stereo_mixes_down(#[case] format: SampleFormat, #[case] bytes: Vec<u8>, #[case] expected: Vec<f32>) {
+        assert_eq!(decode_pcm(&bytes, format, 2), expected);
+    }
+
+    #[rstest]
+    #[case(b"RIFX0000WAVE".to_vec(), "not a RIFF/WAVE file")]
+    #[case(b"RIFF0000WAVE".to_vec(), "no data chunk")]
+    fn test_rejects(#[case] bytes: Vec<u8>, #[case] message: &str) {
+        assert_eq!(Wav::parse(&bytes).unwrap_err().to_string(), message);
+    }
+}
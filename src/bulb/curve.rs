@@ -0,0 +1,187 @@
+use serde::{Deserialize, Serialize};
+
+use crate::bulb::SetPilot;
+
+pub(crate) const MIN_DIMMING: u32 = 10;
+pub(crate) const MAX_DIMMING: u32 = 100;
+
+/// Maps a `0.0..=1.0` user level onto the bulb's `10..=100` `dimming`.
+///
+/// The bulbs put out light roughly in proportion to `dimming`, but the eye sees brightness
+/// closer to a cube root of light, so with [`DimmingCurve::Linear`] most of a slider's travel
+/// above the middle changes very little. The other curves hand more of the range to the
+/// dim end.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DimmingCurve {
+    /// `dimming` straight from the level, as the bulb's own app does.
+    #[default]
+    Linear,
+    /// Light output is `level ^ gamma`; `2.2` is a common choice.
+    Gamma(f64),
+    /// The level is CIE L* lightness, converted to relative luminance.
+    CieLightness,
+    /// `dimming` values at evenly spaced levels from `0.0` to `1.0`, interpolated in between,
+    /// for bulbs measured by hand.
+    Table(Vec<u32>),
+}
+
+impl DimmingCurve {
+    /// A reasonable curve for a `getSystemConfig` module name such as `ESP01_SHRGB1C_31`:
+    /// color bulbs get L*, whites a gamma, and anything unknown stays linear.
+    pub fn for_module_name(module_name: &str) -> DimmingCurve {
+        if module_name.contains("RGB") {
+            DimmingCurve::CieLightness
+        } else if module_name.contains("TW") || module_name.contains("DW") {
+            DimmingCurve::Gamma(2.2)
+        } else {
+            DimmingCurve::Linear
+        }
+    }
+
+    pub(crate) fn is_linear(&self) -> bool {
+        *self == DimmingCurve::Linear
+    }
+
+    /// The `dimming` for `level`, or `None` at zero where the bulb should be turned off.
+    pub fn dimming(&self, level: f64) -> Option<u32> {
+        if level.is_nan() || level <= 0.0 {
+            return None;
+        }
+        let level = level.min(1.0);
+
+        let dimming = match self {
+            DimmingCurve::Table(table) if !table.is_empty() => interpolate(table, level),
+            DimmingCurve::Table(_) | DimmingCurve::Linear => from_output(level),
+            DimmingCurve::Gamma(gamma) => from_output(level.powf(*gamma)),
+            DimmingCurve::CieLightness => from_output(lightness_to_luminance(level)),
+        };
+
+        Some(dimming.clamp(MIN_DIMMING, MAX_DIMMING))
+    }
+
+    /// The level that [`DimmingCurve::dimming`] would map onto `dimming`, e.g. to put a
+    /// slider where the bulb currently is.
+    pub fn level(&self, dimming: u32) -> f64 {
+        let dimming = dimming.clamp(MIN_DIMMING, MAX_DIMMING);
+        let output = (dimming - MIN_DIMMING) as f64 / (MAX_DIMMING - MIN_DIMMING) as f64;
+
+        match self {
+            DimmingCurve::Table(table) if !table.is_empty() => invert(table, dimming),
+            DimmingCurve::Table(_) | DimmingCurve::Linear => output,
+            DimmingCurve::Gamma(gamma) => output.powf(1.0 / gamma),
+            DimmingCurve::CieLightness => luminance_to_lightness(output),
+        }
+    }
+}
+
+fn from_output(output: f64) -> u32 {
+    MIN_DIMMING + (output * (MAX_DIMMING - MIN_DIMMING) as f64).round() as u32
+}
+
+fn interpolate(table: &[u32], level: f64) -> u32 {
+    if table.len() == 1 {
+        return table[0];
+    }
+
+    let position = level * (table.len() - 1) as f64;
+    let i = (position.floor() as usize).min(table.len() - 2);
+    let t = position - i as f64;
+
+    (table[i] as f64 + (table[i + 1] as f64 - table[i] as f64) * t).round() as u32
+}
+
+fn invert(table: &[u32], dimming: u32) -> f64 {
+    if table.len() == 1 {
+        return 1.0;
+    }
+
+    let last = (table.len() - 1) as f64;
+    for (i, pair) in table.windows(2).enumerate() {
+        let (from, to) = (pair[0].clamp(MIN_DIMMING, MAX_DIMMING), pair[1].clamp(MIN_DIMMING, MAX_DIMMING));
+        if (from..=to).contains(&dimming) {
+            let t = if to == from { 0.0 } else { (dimming - from) as f64 / (to - from) as f64 };
+            return (i as f64 + t) / last;
+        }
+    }
+
+    if dimming <= table[0] { 0.0 } else { 1.0 }
+}
+
+fn lightness_to_luminance(level: f64) -> f64 {
+    let l = level * 100.0;
+    if l > 8.0 {
+        ((l + 16.0) / 116.0).powi(3)
+    } else {
+        l / 903.3
+    }
+}
+
+fn luminance_to_lightness(y: f64) -> f64 {
+    let l = if y > 0.008856 {
+        116.0 * y.cbrt() - 16.0
+    } else {
+        903.3 * y
+    };
+
+    (l / 100.0).clamp(0.0, 1.0)
+}
+
+impl SetPilot {
+    /// Sets `dimming` for `level` through `curve`, turning the bulb off at zero.
+    pub fn level(&mut self, level: f64, curve: &DimmingCurve) -> &mut Self {
+        match curve.dimming(level) {
+            Some(dimming) => self.state(true).brightness(dimming),
+            None => self.state(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(DimmingCurve::Linear, 0.5, Some(55))]
+    #[case(DimmingCurve::Linear, 1.0, Some(100))]
+    #[case(DimmingCurve::Linear, 0.0, None)]
+    #[case(DimmingCurve::Linear, 1.5, Some(100))]
+    #[case(DimmingCurve::Gamma(2.0), 0.5, Some(33))]
+    #[case(DimmingCurve::CieLightness, 0.5, Some(27))]
+    #[case(DimmingCurve::CieLightness, 1.0, Some(100))]
+    #[case(DimmingCurve::Table(vec![10, 20, 100]), 0.25, Some(15))]
+    #[case(DimmingCurve::Table(vec![10, 20, 100]), 0.75, Some(60))]
+    #[case(DimmingCurve::Table(vec![]), 0.5, Some(55))]
+    fn test_dimming(#[case] curve: DimmingCurve, #[case] level: f64, #[case] expected: Option<u32>) {
+        assert_eq!(curve.dimming(level), expected);
+    }
+
+    #[rstest]
+    #[case(DimmingCurve::Linear)]
+    #[case(DimmingCurve::Gamma(2.2))]
+    #[case(DimmingCurve::CieLightness)]
+    #[case(DimmingCurve::Table(vec![10, 20, 100]))]
+    fn test_level_inverts_dimming(#[case] curve: DimmingCurve) {
+        for level in [0.2, 0.5, 0.8, 1.0] {
+            let back = curve.level(curve.dimming(level).unwrap());
+            // one dimming step is ~1% of output, which is a wider step in level at the dim end
+            assert!((back - level).abs() < 0.05, "{:?} {} -> {}", curve, level, back);
+        }
+    }
+
+    #[rstest]
+    fn test_perceptual_curves_spend_more_range_low() {
+        for curve in [DimmingCurve::Gamma(2.2), DimmingCurve::CieLightness] {
+            assert!(curve.dimming(0.5) < DimmingCurve::Linear.dimming(0.5));
+        }
+    }
+
+    #[rstest]
+    fn test_level_turns_off_at_zero() {
+        let p = SetPilot::default().level(0.0, &DimmingCurve::Linear).to_owned();
+
+        assert_eq!(p.params.state, Some(false));
+        assert_eq!(p.params.dimming, None);
+    }
+}
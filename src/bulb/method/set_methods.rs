@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::color::{Color, Rgbcw};
+
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct SetPilot {
@@ -21,6 +23,14 @@ pub struct SetPilotParams {
     pub g: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub b: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub c: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub w: Option<u32>,
+    #[serde(default, rename = "sceneId", skip_serializing_if = "Option::is_none")]
+    pub scene_id: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<u32>,
 }
 
 impl SetPilot {
@@ -39,11 +49,32 @@ impl SetPilot {
         self
     }
 
-    // todo: update this to use some Color object for more flexibility (maybe implement other color defs + conversions)
-    pub fn color(&mut self, r: u32, g: u32, b: u32) -> &mut Self {
-        self.params.r = Some(r);
-        self.params.g = Some(g);
-        self.params.b = Some(b);
+    pub fn color(&mut self, color: Color) -> &mut Self {
+        self.params.r = Some(color.r as u32);
+        self.params.g = Some(color.g as u32);
+        self.params.b = Some(color.b as u32);
+        self
+    }
+
+    /// One of the bulb's built-in scenes, e.g. `4` for party.
+    pub fn scene(&mut self, scene_id: u32) -> &mut Self {
+        self.params.scene_id = Some(scene_id);
+        self
+    }
+
+    /// How fast a dynamic scene plays, `20..=200`.
+    pub fn speed(&mut self, speed: u32) -> &mut Self {
+        self.params.speed = Some(speed);
+        self
+    }
+
+    /// Sets all five channels at once, e.g. from a [`crate::color::Mixer`].
+    pub fn rgbcw(&mut self, channels: Rgbcw) -> &mut Self {
+        self.params.r = Some(channels.r as u32);
+        self.params.g = Some(channels.g as u32);
+        self.params.b = Some(channels.b as u32);
+        self.params.c = Some(channels.c as u32);
+        self.params.w = Some(channels.w as u32);
         self
     }
 }
@@ -66,6 +97,35 @@ impl Default for SetPilotParams {
             r: None,
             g: None,
             b: None,
+            c: None,
+            w: None,
+            scene_id: None,
+            speed: None,
+        }
+    }
+}
+
+impl SetPilotParams {
+    /// These params cut down to `state` and the named `fields`, as reported by
+    /// [`crate::bulb::response::GetPilotResult::differing_fields`]. Channels the bulb only
+    /// takes together come along with one another: any color channel brings all of them, and
+    /// a scene its speed.
+    pub fn only(&self, fields: &[&str]) -> SetPilotParams {
+        let has = |names: &[&str]| names.iter().any(|n| fields.contains(n));
+        let color = has(&["r", "g", "b", "c", "w"]);
+        let keep = |wanted: bool, value: Option<u32>| value.filter(|_| wanted);
+
+        SetPilotParams {
+            state: self.state,
+            temp: keep(has(&["temp"]), self.temp),
+            dimming: keep(has(&["dimming"]), self.dimming),
+            r: keep(color, self.r),
+            g: keep(color, self.g),
+            b: keep(color, self.b),
+            c: keep(color, self.c),
+            w: keep(color, self.w),
+            scene_id: keep(has(&["sceneId"]), self.scene_id),
+            speed: keep(has(&["sceneId", "speed"]), self.speed),
         }
     }
 }
@@ -83,6 +143,8 @@ mod tests {
     #[case(SetPilotParams {r: Some(0), ..Default::default()}, r#"{"method":"setPilot","params":{"r":0}}"#)]
     #[case(SetPilotParams {g: Some(128), ..Default::default()}, r#"{"method":"setPilot","params":{"g":128}}"#)]
     #[case(SetPilotParams {b: Some(255), ..Default::default()}, r#"{"method":"setPilot","params":{"b":255}}"#)]
+    #[case(SetPilotParams {c: Some(40), w: Some(200), ..Default::default()}, r#"{"method":"setPilot","params":{"c":40,"w":200}}"#)]
+    #[case(SetPilotParams {scene_id: Some(4), speed: Some(150), ..Default::default()}, r#"{"method":"setPilot","params":{"sceneId":4,"speed":150}}"#)]
     fn test_set_pilot_serialization(#[case] params: SetPilotParams, #[case] expected_message: &str) {
         let a = SetPilot {
             method: String::from("setPilot"),
@@ -103,7 +165,7 @@ mod tests {
             .state(true)
             .brightness(90)
             .temperature(4000)
-            .color(255, 255, 255)
+            .color(Color::WHITE)
             .to_owned();
 
         assert_eq!(
@@ -117,8 +179,45 @@ mod tests {
                     r: Some(255),
                     g: Some(255),
                     b: Some(255),
+                    c: None,
+                    w: None,
+                    scene_id: None,
+                    speed: None,
                 }
             }
         )
     }
+
+    #[rstest]
+    #[case(&["dimming"], SetPilotParams { state: Some(true), dimming: Some(50), ..Default::default() })]
+    #[case(&["g"], SetPilotParams { state: Some(true), r: Some(255), g: Some(0), b: Some(0), w: Some(20), ..Default::default() })]
+    #[case(&["sceneId"], SetPilotParams { state: Some(true), scene_id: Some(4), speed: Some(150), ..Default::default() })]
+    #[case(&[], SetPilotParams { state: Some(true), ..Default::default() })]
+    fn test_only(#[case] fields: &[&str], #[case] expected: SetPilotParams) {
+        let full = SetPilotParams {
+            state: Some(true),
+            dimming: Some(50),
+            r: Some(255),
+            g: Some(0),
+            b: Some(0),
+            w: Some(20),
+            scene_id: Some(4),
+            speed: Some(150),
+            ..Default::default()
+        };
+
+        assert_eq!(full.only(fields), expected);
+    }
+
+    #[rstest]
+    fn test_color_from_string() {
+        let a: SetPilot = SetPilot{ ..Default::default() }
+            .color("#ff8800".parse().unwrap())
+            .to_owned();
+
+        assert_eq!(
+            serde_json::to_string(&a).unwrap(),
+            r#"{"method":"setPilot","params":{"r":255,"g":136,"b":0}}"#
+        )
+    }
 }
\ No newline at end of file
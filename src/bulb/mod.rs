@@ -1,7 +1,8 @@
 use std::default::Default;
 use std::io::Error;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
-use std::str::from_utf8;
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use log::{error, info};
 use serde::{Deserialize, Serialize};
@@ -9,13 +10,43 @@ use serde_json;
 
 pub use crate::function::{Off, On};
 use crate::utils::ip_addr_ser;
-use method::*;
+pub use curve::DimmingCurve;
+pub use method::{GetPilot, SetPilot, SetPilotParams};
+pub use snapshot::{BulbSnapshot, Snapshot};
 use response::*;
 use sourced_response::SourcedResponse;
+use transport::{Link, Transport, Udp};
 
+pub mod curve;
 pub(crate) mod method;
+pub mod record;
 pub mod response;
+mod snapshot;
 pub mod sourced_response;
+pub mod transport;
+
+/// How long a command waits on the bulb before a call returns.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum Delivery {
+    /// Wait for the bulb's reply and report its `success` flag.
+    #[default]
+    Acknowledged,
+    /// Send the packet and return straight away without reading anything back, for
+    /// animation frames and other high-rate traffic where a lost packet doesn't matter.
+    FireAndForget,
+    /// Wait for the reply, then read the pilot back and fail unless it matches the request.
+    ReadBack,
+    /// Like `ReadBack`, but resend the command up to `retries` more times while the read-back
+    /// state differs or the bulb doesn't answer, before giving up with the last error; a
+    /// state that never matched fails with [`ErrorResponse::mismatch`] set.
+    Verified { retries: u32 },
+}
+
+impl Delivery {
+    fn is_acknowledged(&self) -> bool {
+        *self == Delivery::Acknowledged
+    }
+}
 
 /// we really need to fix the serialization for the IpAddr
 /// This solution definitely works: https://github.com/surrealdb/surrealdb/issues/3301#issuecomment-1890672975
@@ -30,6 +61,12 @@ pub struct Bulb {
     pub _id: u32,
     pub name: String,
     pub state: bool, // tbd
+    #[serde(default, skip_serializing_if = "Delivery::is_acknowledged")]
+    delivery: Delivery,
+    #[serde(default, skip_serializing_if = "DimmingCurve::is_linear")]
+    dimming_curve: DimmingCurve,
+    #[serde(skip)]
+    transport: Link,
 }
 
 impl Bulb {
@@ -39,9 +76,61 @@ impl Bulb {
             _id: id, // fixme
             name,
             state: false, // fixme
+            delivery: Delivery::default(),
+            dimming_curve: DimmingCurve::default(),
+            transport: Link::default(),
         }
     }
 
+    /// Routes this bulb's traffic through `transport` instead of plain UDP, e.g. a
+    /// [`record::Recorder`] to capture a session or a [`record::Replay`] to play one back.
+    pub fn set_transport(&mut self, transport: Arc<dyn Transport>) -> &mut Self {
+        self.transport = Link::new(transport);
+        self
+    }
+
+    pub(crate) fn transport(&self) -> &dyn Transport {
+        self.transport.get()
+    }
+
+    pub fn delivery(&self) -> Delivery {
+        self.delivery
+    }
+
+    /// Delivery used by `on`/`off`; pass `Delivery::Verified` to have them read the bulb back
+    /// and correct it instead of trusting the acknowledgement.
+    pub fn set_delivery(&mut self, delivery: Delivery) -> &mut Self {
+        self.delivery = delivery;
+        self
+    }
+
+    pub fn dimming_curve(&self) -> &DimmingCurve {
+        &self.dimming_curve
+    }
+
+    /// How [`Bulb::set_level`] maps levels onto this bulb's `dimming`.
+    pub fn set_dimming_curve(&mut self, curve: DimmingCurve) -> &mut Self {
+        self.dimming_curve = curve;
+        self
+    }
+
+    /// Sets a `0.0..=1.0` brightness level through this bulb's dimming curve; zero turns it off.
+    pub fn set_level(&mut self, level: f64) -> Result<bool, ErrorResponse> {
+        let p = SetPilot::default().level(level, &self.dimming_curve).to_owned();
+        self.send_pilot(p, self.delivery)
+    }
+
+    /// The bulb's current brightness as a level on its dimming curve, `0.0` when it's off.
+    pub fn get_level(&self) -> Result<f64, ErrorResponse> {
+        let pilot = self.get_pilot()?.result;
+
+        Ok(match (pilot.state, pilot.dimming) {
+            (true, Some(dimming)) => self.dimming_curve.level(dimming),
+            (true, None) => 1.0,
+            (false, _) => 0.0,
+        })
+    }
+
     pub fn get_state(&self) -> Result<bool, ErrorResponse> {
         Ok(self.get_pilot()?.result.state)
     }
@@ -52,6 +141,25 @@ impl Bulb {
         self.send_message(message.as_bytes()).get_response()
     }
 
+    /// Median time of `samples` `getPilot` round trips; fails only if none came back.
+    pub fn round_trip(&self, samples: u32) -> Result<Duration, ErrorResponse> {
+        let mut times = vec![];
+        let mut last_error = None;
+        for _ in 0..samples.max(1) {
+            let sent = Instant::now();
+            match self.get_pilot() {
+                Ok(_) => times.push(sent.elapsed()),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        times.sort();
+        match times.get(times.len() / 2) {
+            Some(median) => Ok(*median),
+            None => Err(last_error.unwrap_or_default()),
+        }
+    }
+
     pub fn set_pilot(&self, p: SetPilot) -> Result<SetPilotResponse, ErrorResponse> {
         let m = serde_json::to_string(&p).unwrap();
         let message: &[u8] = m.as_bytes();
@@ -59,8 +167,51 @@ impl Bulb {
         self.send_message(message).set_response()
     }
 
+    /// `set_pilot` with a choice of how much confirmation to wait for; see [`Delivery`].
+    pub fn send_pilot(&mut self, p: SetPilot, delivery: Delivery) -> Result<bool, ErrorResponse> {
+        let success = match delivery {
+            Delivery::FireAndForget => {
+                let m = serde_json::to_string(&p).unwrap();
+                self.transport.get().fire(self.ip_address, m.as_bytes()).map_err(|e| {
+                    error!("Error in UDP Communication {}", e);
+                    ErrorResponse::default()
+                })?;
+                true
+            }
+            Delivery::Acknowledged => self.set_pilot(p.clone())?.result.success,
+            Delivery::ReadBack => self.set_pilot_verified(&p, 0)?,
+            Delivery::Verified { retries } => self.set_pilot_verified(&p, retries)?,
+        };
+
+        if let Some(state) = p.params.state {
+            self.state = state;
+        }
+
+        Ok(success)
+    }
+
+    fn set_pilot_verified(&self, p: &SetPilot, retries: u32) -> Result<bool, ErrorResponse> {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let outcome = self
+                .set_pilot(p.clone())
+                .and_then(|r| Ok((r.result.success, self.get_pilot()?.result.differing_fields(&p.params))));
+
+            match outcome {
+                Ok((success, differing)) if differing.is_empty() => return Ok(success),
+                Ok((_, differing)) if attempts > retries => {
+                    return Err(StateMismatch::new(&differing, attempts).into())
+                }
+                Err(e) if attempts > retries => return Err(e),
+                Ok((_, differing)) => info!("{} read back differing {:?}, resending", self.name, differing),
+                Err(e) => info!("{} did not answer ({}), resending", self.name, e),
+            }
+        }
+    }
+
     fn send_message(&self, message: &[u8]) -> Response {
-        match Self::_send_message(self.ip_address, message) {
+        match self._send_message(message) {
             Ok(r) => r,
             Err(e) => {
                 error!("Error in UDP Communication {}", e);
@@ -69,98 +220,137 @@ impl Bulb {
         }
     }
 
-    fn _send_message(ip: IpAddr, message: &[u8]) -> anyhow::Result<Response> {
-        let sock = give_socket()?;
-        let mut buff = [0; 512];
-
-        sock.send_to(message, SocketAddr::new(ip, 38899));
+    fn _send_message(&self, message: &[u8]) -> anyhow::Result<Response> {
+        let datagram = self.transport.get().exchange(self.ip_address, message)?;
 
-        Ok(Self::_poll_response(&sock, &mut buff)?.response)
+        Ok(SourcedResponse::try_from(datagram)?.response)
     }
 
-    fn _poll_response(socket: &UdpSocket, buff: &mut [u8]) -> anyhow::Result<SourcedResponse> {
-        let received = socket.recv_from(buff)?;
+    /// Sends `p` once to every bulb listening on `broadcast` without waiting for replies.
+    ///
+    /// Every bulb on that subnet applies the command, not just the ones you care about, so
+    /// callers are expected to check the members they need with [`Bulb::confirm_pilot`].
+    pub fn broadcast_pilot(broadcast: Ipv4Addr, p: &SetPilot) -> Result<(), ErrorResponse> {
+        Self::broadcast_pilot_via(&Udp, broadcast, p)
+    }
 
-        info!("from {}", received.1);
-        info!(
-            "{}",
-            from_utf8(&buff[..received.0]).unwrap_or("Error retreiving from buffer")
-        );
+    /// [`Bulb::broadcast_pilot`] through `transport`.
+    pub fn broadcast_pilot_via(transport: &dyn Transport, broadcast: Ipv4Addr, p: &SetPilot) -> Result<(), ErrorResponse> {
+        let m = serde_json::to_string(p).unwrap();
 
-        Ok(SourcedResponse {
-            source: match received.1.ip().clone() {
-                IpAddr::V4(ip4) => {
-                    let [a, b, c, d] = ip4.octets();
-                    Ipv4Addr::new(a, b, c, d)
-                }
-                IpAddr::V6(ip6) => {
-                    return Err(anyhow::anyhow!(
-                        "We should never hava an Ipv6 Address: {}",
-                        ip6
-                    ))
-                }
-            },
-            response: serde_json::from_slice(&buff[..received.0])?,
+        transport.fire(IpAddr::V4(broadcast), m.as_bytes()).map_err(|e| {
+            error!("Error in UDP Broadcast {}", e);
+            ErrorResponse::default()
         })
     }
 
-    fn _poll_messages(ip: IpAddr, message: &[u8]) -> anyhow::Result<Vec<SourcedResponse>> {
-        let sock = give_socket()?;
-        let mut buff = [0; 512];
-
-        // TODO: is it possible to yield from a rust function? or do I need to make channels w/
-        // concurrency
-
-        sock.send_to(message, SocketAddr::new(ip, 38899))?;
+    /// Reads the bulb back and unicasts `p` again with `retry` if any requested field didn't
+    /// take.
+    ///
+    /// Returns `true` when the bulb already matched, `false` when it had to be retried.
+    pub fn confirm_pilot(&mut self, p: &SetPilot, retry: Delivery) -> Result<bool, ErrorResponse> {
+        let matched = match self.get_pilot() {
+            Ok(observed) => observed.result.differing_fields(&p.params).is_empty(),
+            Err(e) => {
+                info!("{} did not answer getPilot, retrying directly: {}", self.name, e);
+                false
+            }
+        };
 
-        let mut sourced_responses = vec![];
-        while let Ok(s) = Self::_poll_response(&sock, &mut buff) {
-            sourced_responses.push(s);
+        if !matched {
+            self.send_pilot(p.clone(), retry)?;
+        } else if let Some(state) = p.params.state {
+            self.state = state;
         }
-        Ok(sourced_responses)
+
+        Ok(matched)
     }
 
     pub fn discover() -> Vec<Ipv4Addr> {
+        Self::discover_on(Ipv4Addr::BROADCAST)
+    }
+
+    /// Like [`Bulb::discover`], but on a specific broadcast address such as `192.168.68.255`.
+    pub fn discover_on(broadcast: Ipv4Addr) -> Vec<Ipv4Addr> {
+        Self::survey(broadcast)
+            .iter()
+            .map(|(ip, _)| *ip)
+            .collect()
+    }
+
+    /// Every bulb answering on `broadcast`, with its MAC.
+    pub fn survey(broadcast: Ipv4Addr) -> Vec<(Ipv4Addr, String)> {
+        Self::survey_via(&Udp, broadcast)
+    }
+
+    /// [`Bulb::survey`] through `transport`, e.g. a [`record::Recorder`].
+    pub fn survey_via(transport: &dyn Transport, broadcast: Ipv4Addr) -> Vec<(Ipv4Addr, String)> {
         let message = serde_json::to_string(&GetPilot::default()).unwrap();
-        let ip = IpAddr::V4(Ipv4Addr::BROADCAST);
 
-        Self::_poll_messages(ip, message.as_bytes())
+        transport
+            .poll(IpAddr::V4(broadcast), message.as_bytes())
             .unwrap_or(vec![])
-            .iter()
-            .map(|s| s.source)
+            .into_iter()
+            .filter_map(|d| SourcedResponse::try_from(d).ok())
+            .filter_map(|s| match s.response {
+                Response::GR(g) => Some((s.source, g.result.mac)),
+                _ => None,
+            })
             .collect()
     }
+
+    pub fn ip(&self) -> IpAddr {
+        self.ip_address
+    }
+
+    pub fn mac(&self) -> Result<String, ErrorResponse> {
+        Ok(self.get_pilot()?.result.mac)
+    }
+
+    /// Finds the bulb with `mac` on `broadcast` and points this `Bulb` at it, for when DHCP
+    /// has moved it to a new address.
+    pub fn resolve_mac(&mut self, mac: &str, broadcast: Ipv4Addr) -> Result<IpAddr, ErrorResponse> {
+        match Self::survey_via(self.transport.get(), broadcast).into_iter().find(|(_, m)| m == mac) {
+            Some((ip, _)) => {
+                info!("{} found {} at {}", self.name, mac, ip);
+                self.ip_address = IpAddr::V4(ip);
+                Ok(self.ip_address)
+            }
+            None => {
+                error!("{} could not find {} on {}", self.name, mac, broadcast);
+                Err(ErrorResponse::default())
+            }
+        }
+    }
 }
 
 impl On for Bulb {
     fn on(&mut self) -> Result<bool, ErrorResponse> {
-        let response = self.set_pilot(SetPilot {
+        let success = self.send_pilot(SetPilot {
             method: String::from("setPilot"),
             params: SetPilotParams {
                 state: Some(true),
                 ..Default::default()
             },
-        })?;
-        self.state = true;
+        }, self.delivery)?;
         dbg!(self.clone());
 
-        Ok(response.result.success)
+        Ok(success)
     }
 }
 
 impl Off for Bulb {
     fn off(&mut self) -> Result<bool, ErrorResponse> {
-        let response = self.set_pilot(SetPilot {
+        let success = self.send_pilot(SetPilot {
             method: String::from("setPilot"),
             params: SetPilotParams {
                 state: Some(false),
                 ..Default::default()
             },
-        })?;
-        self.state = false;
+        }, self.delivery)?;
         dbg!(self.clone());
 
-        Ok(response.result.success)
+        Ok(success)
     }
 }
 
@@ -177,9 +367,14 @@ fn give_socket() -> Result<UdpSocket, Error> {
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::sim::{FaultProfile, VirtualBulb};
     use rstest::{fixture, rstest};
-    use std::thread::sleep;
-    use std::time::Duration;
+
+    /// A simulated bulb to talk to; keep it alive for as long as the test uses it.
+    #[fixture]
+    pub fn test_sim() -> VirtualBulb {
+        VirtualBulb::new().unwrap()
+    }
 
     #[fixture]
     pub fn test_bulb(
@@ -190,13 +385,11 @@ pub mod tests {
     }
 
     #[rstest]
-    fn test_get_pilot(test_bulb: Bulb) {
-        let message = test_bulb.get_pilot();
-        println!(
-            "{}",
-            serde_json::to_string_pretty(&message.unwrap()).unwrap()
-        );
-        assert!(true);
+    fn test_get_pilot(test_sim: VirtualBulb) {
+        let message = test_sim.bulb("test_bulb_0", 0).get_pilot().unwrap();
+
+        assert_eq!(message.method, "getPilot");
+        assert_eq!(message.result.mac, test_sim.state().mac);
     }
 
     #[rstest]
@@ -205,13 +398,32 @@ pub mod tests {
         r#"{"Err":{"method":"setPilot","error":{"code":-32600,"message":"Invalid Request"}}}"#
     )]
     #[case(SetPilot { params: SetPilotParams { state: Some(true), ..Default::default()}, ..Default::default()}, r#"{"Ok":{"method":"setPilot","result":{"success":true}}}"#)]
-    fn test_set_pilot(test_bulb: Bulb, #[case] method: SetPilot, #[case] expected_message: &str) {
-        let mymessage = test_bulb.set_pilot(method);
+    fn test_set_pilot(test_sim: VirtualBulb, #[case] method: SetPilot, #[case] expected_message: &str) {
+        let mymessage = test_sim.bulb("test_bulb_0", 0).set_pilot(method);
         assert_eq!(serde_json::to_string(&mymessage).unwrap(), expected_message);
     }
 
     #[rstest]
-    fn test_get_state(test_bulb: Bulb) {
+    #[case(Delivery::FireAndForget)]
+    #[case(Delivery::Acknowledged)]
+    #[case(Delivery::ReadBack)]
+    #[case(Delivery::Verified { retries: 2 })]
+    fn test_send_pilot(test_sim: VirtualBulb, #[case] delivery: Delivery) {
+        let mut test_bulb = test_sim.bulb("test_bulb_0", 0);
+        let p = SetPilot::default().state(true).brightness(50).to_owned();
+
+        assert!(test_bulb.send_pilot(p, delivery).unwrap());
+        assert!(test_bulb.state);
+
+        // the sim answers in order, so this reads back even a fire-and-forget packet
+        let observed = test_bulb.get_pilot().unwrap().result;
+        assert!(observed.state);
+        assert_eq!(observed.dimming, Some(50));
+    }
+
+    #[rstest]
+    fn test_get_state(test_sim: VirtualBulb) {
+        let test_bulb = test_sim.bulb("test_bulb_0", 0);
         let _ = test_bulb.set_pilot(SetPilot {
             method: String::from("setPilot"),
             params: SetPilotParams {
@@ -222,8 +434,6 @@ pub mod tests {
         let mut state = test_bulb.get_state();
         assert_eq!(state.unwrap(), true);
 
-        sleep(Duration::new(2, 0));
-
         let _ = test_bulb.set_pilot(SetPilot {
             method: String::from("setPilot"),
             params: SetPilotParams {
@@ -247,13 +457,46 @@ pub mod tests {
     }
 
     #[rstest]
-    fn test_bulb_on(mut test_bulb: Bulb) {
+    fn test_bulb_on(test_sim: VirtualBulb) {
+        assert!(test_sim.bulb("test_bulb_0", 0).on().unwrap());
+        assert!(test_sim.state().state);
+    }
+
+    #[rstest]
+    fn test_bulb_off(test_sim: VirtualBulb) {
+        test_sim.update(|s| s.state = true);
+
+        assert!(test_sim.bulb("test_bulb_0", 0).off().unwrap());
+        assert!(!test_sim.state().state);
+    }
+
+    #[rstest]
+    fn test_verified_bulb_on(test_sim: VirtualBulb) {
+        // seed 4 loses the first two requests and lets the rest through
+        let lossy = FaultProfile { seed: 4, loss: 0.5, ..Default::default() };
+        let mut test_bulb = test_sim.bulb("test_bulb_0", 0);
+
+        test_sim.set_faults(lossy.clone());
+        test_bulb.set_delivery(Delivery::ReadBack);
+        assert!(test_bulb.on().is_err());
+
+        test_sim.set_faults(lossy);
+        test_bulb.set_delivery(Delivery::Verified { retries: 2 });
         assert!(test_bulb.on().unwrap());
+        assert!(test_sim.state().state);
     }
 
     #[rstest]
-    fn test_bulb_off(mut test_bulb: Bulb) {
-        assert!(test_bulb.off().unwrap());
+    fn test_verified_reports_mismatch(test_sim: VirtualBulb) {
+        let mut test_bulb = test_sim.bulb("test_bulb_0", 0);
+        // the color wins, so the bulb never reports the temperature back
+        let params = SetPilotParams { r: Some(255), g: Some(0), b: Some(0), temp: Some(2700), ..Default::default() };
+        let p = SetPilot { params, ..Default::default() };
+
+        let e = test_bulb.send_pilot(p, Delivery::Verified { retries: 2 }).unwrap_err();
+
+        assert_eq!(e.error.code, ErrorResponse::STATE_MISMATCH);
+        assert_eq!(e.mismatch, Some(StateMismatch::new(&["temp"], 3)));
     }
 
     #[rstest]
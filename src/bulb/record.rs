@@ -0,0 +1,357 @@
+//! Capturing bulb traffic to JSON Lines and feeding it back in.
+//!
+//! Wrap a transport in a [`Recorder`] to log every request and reply a `Bulb` makes, then
+//! give the file to a [`Replay`] to run the same session again without any hardware.
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::bulb::transport::{Datagram, Transport, Udp};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// A request that expected a reply.
+    Request,
+    /// A request sent without waiting for a reply.
+    Fired,
+    /// A request, usually a broadcast, followed by every reply that came back to it.
+    Polled,
+    Response,
+    /// A request whose exchange failed; `error` says how.
+    Failed,
+}
+
+/// One line of a capture. `bytes` is the exact datagram in hex; `text` is the same thing
+/// decoded for people reading the file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    pub timestamp_ms: u128,
+    pub direction: Direction,
+    pub peer: IpAddr,
+    pub text: String,
+    pub bytes: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Record {
+    pub fn new(direction: Direction, peer: IpAddr, bytes: &[u8]) -> Record {
+        Record {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or_default(),
+            direction,
+            peer,
+            text: String::from_utf8_lossy(bytes).into_owned(),
+            bytes: bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+            error: None,
+        }
+    }
+
+    pub fn raw(&self) -> anyhow::Result<Vec<u8>> {
+        (0..self.bytes.len())
+            .step_by(2)
+            .map(|i| {
+                let pair = self.bytes.get(i..i + 2).ok_or_else(|| anyhow::anyhow!("odd hex length"))?;
+                Ok(u8::from_str_radix(pair, 16)?)
+            })
+            .collect()
+    }
+}
+
+/// Passes traffic through to another transport and appends every datagram to a file.
+#[derive(Debug)]
+pub struct Recorder {
+    inner: Arc<dyn Transport>,
+    out: Mutex<File>,
+}
+
+impl Recorder {
+    pub fn new(inner: Arc<dyn Transport>, path: impl AsRef<Path>) -> std::io::Result<Recorder> {
+        Ok(Recorder {
+            inner,
+            out: Mutex::new(File::options().create(true).append(true).open(path)?),
+        })
+    }
+
+    /// Records plain UDP traffic.
+    pub fn udp(path: impl AsRef<Path>) -> std::io::Result<Recorder> {
+        Recorder::new(Arc::new(Udp), path)
+    }
+
+    fn write(&self, record: &Record) {
+        let line = serde_json::to_string(record).unwrap();
+        let mut out = self.out.lock().unwrap();
+
+        if let Err(e) = writeln!(out, "{}", line) {
+            error!("Failed to record traffic: {}", e);
+        }
+    }
+}
+
+impl Transport for Recorder {
+    fn exchange(&self, target: IpAddr, message: &[u8]) -> anyhow::Result<Datagram> {
+        self.write(&Record::new(Direction::Request, target, message));
+
+        let result = self.inner.exchange(target, message);
+        match &result {
+            Ok(d) => self.write(&Record::new(Direction::Response, IpAddr::V4(d.source), &d.bytes)),
+            Err(e) => {
+                let mut failed = Record::new(Direction::Failed, target, &[]);
+                failed.error = Some(e.to_string());
+                self.write(&failed);
+            }
+        }
+
+        result
+    }
+
+    fn fire(&self, target: IpAddr, message: &[u8]) -> anyhow::Result<()> {
+        self.write(&Record::new(Direction::Fired, target, message));
+
+        self.inner.fire(target, message)
+    }
+
+    fn poll(&self, target: IpAddr, message: &[u8]) -> anyhow::Result<Vec<Datagram>> {
+        self.write(&Record::new(Direction::Polled, target, message));
+
+        let result = self.inner.poll(target, message);
+        match &result {
+            Ok(replies) => {
+                for d in replies {
+                    self.write(&Record::new(Direction::Response, IpAddr::V4(d.source), &d.bytes));
+                }
+            }
+            Err(e) => {
+                let mut failed = Record::new(Direction::Failed, target, &[]);
+                failed.error = Some(e.to_string());
+                self.write(&failed);
+            }
+        }
+
+        result
+    }
+}
+
+/// Answers requests from a capture, in order. A request that doesn't match the next one in
+/// the capture is an error, so a reproduction can't silently drift from the original session.
+#[derive(Debug)]
+pub struct Replay {
+    records: Mutex<VecDeque<Record>>,
+}
+
+impl Replay {
+    pub fn new(records: Vec<Record>) -> Replay {
+        Replay {
+            records: Mutex::new(records.into()),
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Replay> {
+        let mut records = vec![];
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                records.push(serde_json::from_str(&line)?);
+            }
+        }
+
+        Ok(Replay::new(records))
+    }
+
+    /// Records not yet consumed.
+    pub fn remaining(&self) -> usize {
+        self.records.lock().unwrap().len()
+    }
+
+    fn expect(&self, direction: Direction, message: &[u8]) -> anyhow::Result<()> {
+        let next = self.records.lock().unwrap().pop_front();
+        match next {
+            Some(r) if r.direction == direction && r.raw()? == message => Ok(()),
+            Some(r) => Err(anyhow::anyhow!(
+                "replay diverged: expected {:?} {} but got {}",
+                r.direction,
+                r.text,
+                String::from_utf8_lossy(message),
+            )),
+            None => Err(anyhow::anyhow!("replay exhausted")),
+        }
+    }
+}
+
+impl Transport for Replay {
+    fn exchange(&self, _target: IpAddr, message: &[u8]) -> anyhow::Result<Datagram> {
+        self.expect(Direction::Request, message)?;
+
+        let next = self.records.lock().unwrap().pop_front();
+        match next {
+            Some(r) if r.direction == Direction::Response => datagram(&r),
+            Some(r) if r.direction == Direction::Failed => Err(anyhow::anyhow!(
+                "{}",
+                r.error.unwrap_or_else(|| "recorded failure".to_string())
+            )),
+            Some(r) => Err(anyhow::anyhow!("replay diverged: expected a reply but found {:?}", r.direction)),
+            None => Err(anyhow::anyhow!("replay exhausted")),
+        }
+    }
+
+    fn fire(&self, _target: IpAddr, message: &[u8]) -> anyhow::Result<()> {
+        self.expect(Direction::Fired, message)
+    }
+
+    fn poll(&self, _target: IpAddr, message: &[u8]) -> anyhow::Result<Vec<Datagram>> {
+        self.expect(Direction::Polled, message)?;
+
+        let mut records = self.records.lock().unwrap();
+        if let Some(r) = records.front().filter(|r| r.direction == Direction::Failed) {
+            let e = anyhow::anyhow!("{}", r.error.clone().unwrap_or_else(|| "recorded failure".to_string()));
+            records.pop_front();
+            return Err(e);
+        }
+
+        let mut replies = vec![];
+        while let Some(r) = records.front().filter(|r| r.direction == Direction::Response) {
+            replies.push(datagram(r)?);
+            records.pop_front();
+        }
+        Ok(replies)
+    }
+}
+
+fn datagram(r: &Record) -> anyhow::Result<Datagram> {
+    Ok(Datagram {
+        source: match r.peer {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+        },
+        bytes: r.raw()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::bulb::{Bulb, Delivery, SetPilot};
+    use crate::function::On;
+    use crate::registry::{GraphStore, Group};
+    use crate::sim::{FaultProfile, VirtualBulb, VirtualNetwork};
+    use rstest::rstest;
+    use surrealdb::sql::Id;
+
+    fn capture_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("wiz-{}-{}.jsonl", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[rstest]
+    fn test_record_then_replay() {
+        let path = capture_path("record_then_replay");
+        let p = SetPilot::default().brightness(40).to_owned();
+
+        let recorded = {
+            let sim = VirtualBulb::new().unwrap();
+            let mut b = sim.bulb("sim", 0);
+            b.set_transport(Arc::new(Recorder::udp(&path).unwrap()));
+
+            b.on().unwrap();
+            b.send_pilot(p.clone(), Delivery::FireAndForget).unwrap();
+            serde_json::to_string(&b.get_pilot().unwrap()).unwrap()
+        };
+
+        let replay = Arc::new(Replay::from_file(&path).unwrap());
+        assert_eq!(replay.remaining(), 5);
+
+        let mut b = Bulb::new(IpAddr::V4(Ipv4Addr::LOCALHOST), "replayed".to_string(), 0);
+        b.set_transport(replay.clone());
+        assert!(b.on().unwrap());
+        b.send_pilot(p, Delivery::FireAndForget).unwrap();
+        assert_eq!(serde_json::to_string(&b.get_pilot().unwrap()).unwrap(), recorded);
+        assert_eq!(replay.remaining(), 0);
+    }
+
+    #[rstest]
+    fn test_record_then_replay_broadcast_and_discovery() {
+        let path = capture_path("broadcast_discovery");
+        let group = |ips: &[IpAddr]| {
+            let members = ips
+                .iter()
+                .enumerate()
+                .map(|(i, ip)| Box::new(Bulb::new(*ip, "member".to_string(), i as u32)) as Box<dyn GraphStore>)
+                .collect();
+            Group::new(Id::from(1), "subnet".to_string(), members)
+        };
+
+        let (ips, broadcast, found) = {
+            let net = VirtualNetwork::new(2).unwrap();
+            let ips: Vec<IpAddr> = net.bulbs().iter().map(|b| IpAddr::V4(b.ip())).collect();
+            let recorder = Arc::new(Recorder::udp(&path).unwrap());
+            let mut g = group(&ips);
+            g.set_broadcast(Some(net.broadcast())).set_transport(recorder.clone());
+
+            assert!(g.on().unwrap());
+            let found = Bulb::survey_via(recorder.as_ref(), net.broadcast());
+            assert_eq!(found.len(), 2);
+            (ips, net.broadcast(), found)
+        };
+
+        let replay = Arc::new(Replay::from_file(&path).unwrap());
+        let mut g = group(&ips);
+        g.set_broadcast(Some(broadcast)).set_transport(replay.clone());
+
+        assert!(g.on().unwrap());
+        assert_eq!(Bulb::survey_via(replay.as_ref(), broadcast), found);
+        assert_eq!(replay.remaining(), 0);
+    }
+
+    #[rstest]
+    fn test_replay_reproduces_failures() {
+        let path = capture_path("replay_failures");
+        {
+            let sim = VirtualBulb::new().unwrap();
+            sim.set_faults(FaultProfile { loss: 1.0, ..Default::default() });
+            let mut b = sim.bulb("sim", 0);
+            b.set_transport(Arc::new(Recorder::udp(&path).unwrap()));
+
+            assert!(b.get_pilot().is_err());
+        }
+
+        let mut b = Bulb::new(IpAddr::V4(Ipv4Addr::LOCALHOST), "replayed".to_string(), 0);
+        b.set_transport(Arc::new(Replay::from_file(&path).unwrap()));
+
+        assert!(b.get_pilot().is_err());
+    }
+
+    #[rstest]
+    fn test_replay_diverges() {
+        let replay = Replay::new(vec![Record::new(
+            Direction::Request,
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            br#"{"method":"getPilot","params":{}}"#,
+        )]);
+
+        let e = replay
+            .exchange(IpAddr::V4(Ipv4Addr::LOCALHOST), br#"{"method":"setPilot","params":{}}"#)
+            .unwrap_err();
+
+        assert!(e.to_string().starts_with("replay diverged"));
+    }
+
+    #[rstest]
+    fn test_record_hex_round_trip() {
+        let r = Record::new(Direction::Response, IpAddr::V4(Ipv4Addr::LOCALHOST), b"{\"a\":1}\x00\xff");
+
+        assert_eq!(r.raw().unwrap(), b"{\"a\":1}\x00\xff");
+    }
+}
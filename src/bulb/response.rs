@@ -1,9 +1,13 @@
+use std::error::Error;
 use std::fmt;
 use std::fmt::Formatter;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::bulb::method::SetPilotParams;
+use crate::color::Color;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
 pub struct GetPilotResult {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub dimming: Option<u32>,
@@ -17,6 +21,97 @@ pub struct GetPilotResult {
     pub g: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub b: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub c: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub w: Option<u32>,
+    #[serde(default, rename = "sceneId", skip_serializing_if = "Option::is_none")]
+    pub scene_id: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<u32>,
+}
+
+impl GetPilotResult {
+    /// The RGB the bulb reports, if it's in color mode.
+    pub fn color(&self) -> Option<Color> {
+        match (self.r, self.g, self.b) {
+            (Some(r), Some(g), Some(b)) => Some(Color::rgb(
+                r.min(255) as u8,
+                g.min(255) as u8,
+                b.min(255) as u8,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Names of the fields requested in `params` that this observed state doesn't match.
+    pub fn differing_fields(&self, params: &SetPilotParams) -> Vec<&'static str> {
+        let mut fields = vec![];
+
+        if params.state.is_some_and(|s| s != self.state) {
+            fields.push("state");
+        }
+        if params.dimming.is_some() && params.dimming != self.dimming {
+            fields.push("dimming");
+        }
+        if params.temp.is_some() && params.temp != self.temp {
+            fields.push("temp");
+        }
+        if params.r.is_some() && params.r != self.r {
+            fields.push("r");
+        }
+        if params.g.is_some() && params.g != self.g {
+            fields.push("g");
+        }
+        if params.b.is_some() && params.b != self.b {
+            fields.push("b");
+        }
+        if params.c.is_some() && params.c != self.c {
+            fields.push("c");
+        }
+        if params.w.is_some() && params.w != self.w {
+            fields.push("w");
+        }
+        if params.scene_id.is_some() && params.scene_id != self.scene_id {
+            fields.push("sceneId");
+        }
+        if params.speed.is_some() && params.speed != self.speed {
+            fields.push("speed");
+        }
+
+        fields
+    }
+
+    /// Updates this state to what a bulb in it would report after being sent `params`: any
+    /// command without `state` switches it on, and a temp, color or scene replaces the others.
+    pub fn apply(&mut self, params: &SetPilotParams) {
+        self.state = params.state.unwrap_or(true);
+        self.dimming = params.dimming.or(self.dimming);
+
+        if params.temp.is_some() {
+            (self.r, self.g, self.b, self.c, self.w) = (None, None, None, None, None);
+            (self.scene_id, self.speed) = (None, None);
+            self.temp = params.temp;
+        }
+        if params.r.is_some() || params.g.is_some() || params.b.is_some() {
+            self.r = Some(params.r.or(self.r).unwrap_or(0));
+            self.g = Some(params.g.or(self.g).unwrap_or(0));
+            self.b = Some(params.b.or(self.b).unwrap_or(0));
+            (self.temp, self.scene_id, self.speed) = (None, None, None);
+        }
+        if params.c.is_some() || params.w.is_some() {
+            self.c = params.c.or(self.c);
+            self.w = params.w.or(self.w);
+            (self.temp, self.scene_id, self.speed) = (None, None, None);
+        }
+        if params.scene_id.is_some() {
+            (self.temp, self.r, self.g, self.b, self.c, self.w) = (None, None, None, None, None, None);
+            self.scene_id = params.scene_id;
+            self.speed = params.speed.or(Some(100));
+        } else if params.speed.is_some() {
+            self.speed = params.speed;
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -52,6 +147,9 @@ pub struct SetPilotResponse {
 pub struct ErrorResponse {
     pub method: String,
     pub error: ErrorResult,
+    /// What didn't take, when this is a [`ErrorResponse::STATE_MISMATCH`].
+    #[serde(skip)]
+    pub mismatch: Option<StateMismatch>,
 }
 
 impl Default for ErrorResponse {
@@ -62,6 +160,54 @@ impl Default for ErrorResponse {
                 code: 69,
                 message: "unknown error detected".to_owned(),
             },
+            mismatch: None,
+        }
+    }
+}
+
+impl ErrorResponse {
+    /// Code used when a bulb acknowledged a command but reads back a different state.
+    pub const STATE_MISMATCH: i32 = 70;
+}
+
+/// A verified write that still read back differently after every attempt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateMismatch {
+    pub fields: Vec<String>,
+    pub attempts: u32,
+}
+
+impl StateMismatch {
+    pub fn new(fields: &[&str], attempts: u32) -> StateMismatch {
+        StateMismatch {
+            fields: fields.iter().map(|f| f.to_string()).collect(),
+            attempts,
+        }
+    }
+}
+
+impl fmt::Display for StateMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "state mismatch after {} attempts in {}",
+            self.attempts,
+            self.fields.join(", "),
+        )
+    }
+}
+
+impl Error for StateMismatch {}
+
+impl From<StateMismatch> for ErrorResponse {
+    fn from(value: StateMismatch) -> Self {
+        ErrorResponse {
+            method: "setPilot".to_owned(),
+            error: ErrorResult {
+                code: ErrorResponse::STATE_MISMATCH,
+                message: value.to_string(),
+            },
+            mismatch: Some(value),
         }
     }
 }
@@ -103,3 +249,51 @@ impl Response {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(SetPilotParams { state: Some(true), dimming: Some(80), ..Default::default() }, vec![])]
+    #[case(SetPilotParams { state: Some(false), ..Default::default() }, vec!["state"])]
+    #[case(SetPilotParams { temp: Some(4000), dimming: Some(50), ..Default::default() }, vec!["dimming", "temp"])]
+    #[case(SetPilotParams { r: Some(255), g: Some(0), b: Some(0), ..Default::default() }, vec!["r", "g", "b"])]
+    fn test_differing_fields(#[case] params: SetPilotParams, #[case] expected: Vec<&str>) {
+        let observed = GetPilotResult {
+            dimming: Some(80),
+            mac: "a8bb50000000".to_string(),
+            temp: Some(2700),
+            state: true,
+            ..Default::default()
+        };
+
+        assert_eq!(observed.differing_fields(&params), expected);
+    }
+
+    #[rstest]
+    fn test_apply_switches_mode() {
+        let mut state = GetPilotResult { dimming: Some(80), temp: Some(2700), ..Default::default() };
+
+        state.apply(&SetPilotParams { r: Some(255), g: Some(0), b: Some(0), ..Default::default() });
+        assert!(state.state);
+        assert_eq!((state.temp, state.color(), state.dimming), (None, Some(Color::rgb(255, 0, 0)), Some(80)));
+
+        state.apply(&SetPilotParams { scene_id: Some(4), ..Default::default() });
+        assert_eq!((state.color(), state.scene_id, state.speed), (None, Some(4), Some(100)));
+
+        state.apply(&SetPilotParams { state: Some(false), ..Default::default() });
+        assert!(!state.state);
+        assert_eq!(state.scene_id, Some(4));
+    }
+
+    #[rstest]
+    fn test_state_mismatch_into_error_response() {
+        let e: ErrorResponse = StateMismatch::new(&["dimming", "temp"], 3).into();
+
+        assert_eq!(e.error.code, ErrorResponse::STATE_MISMATCH);
+        assert_eq!(e.mismatch, Some(StateMismatch::new(&["dimming", "temp"], 3)));
+        assert_eq!(e.to_string(), "method: setPilot - code: 70 - message: state mismatch after 3 attempts in dimming, temp");
+    }
+}
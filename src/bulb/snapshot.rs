@@ -0,0 +1,202 @@
+use std::net::IpAddr;
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::bulb::response::{ErrorResponse, GetPilotResult};
+use crate::bulb::{Bulb, SetPilot, SetPilotParams};
+use crate::utils::ip_addr_ser;
+
+/// The pilot state of some bulbs at one moment, to put them back with [`Bulb::restore`] or
+/// [`crate::registry::Group::restore`] after a flash or an effect.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Snapshot {
+    pub bulbs: Vec<BulbSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BulbSnapshot {
+    #[serde(with = "ip_addr_ser")]
+    pub ip: IpAddr,
+    pub name: String,
+    pub pilot: GetPilotResult,
+}
+
+impl Snapshot {
+    /// What the bulb with `mac` was showing, if it was captured, wherever it has moved to
+    /// since. An entry captured without a MAC is matched on `ip` instead.
+    pub fn pilot(&self, mac: &str, ip: IpAddr) -> Option<&GetPilotResult> {
+        self.bulbs
+            .iter()
+            .find(|b| match b.pilot.mac.as_str() {
+                "" => b.ip == ip,
+                captured => captured == mac,
+            })
+            .map(|b| &b.pilot)
+    }
+
+    /// Reads back each of `bulbs`. One that doesn't answer is logged and left out; this only
+    /// fails if none of them answer.
+    pub(crate) fn capture<'a>(bulbs: impl IntoIterator<Item = &'a Bulb>) -> Result<Snapshot, ErrorResponse> {
+        let mut snapshot = Snapshot::default();
+        let mut last_error = None;
+        for b in bulbs {
+            match b.capture() {
+                Ok(captured) => snapshot.bulbs.push(captured),
+                Err(e) => {
+                    error!("{} left out of the snapshot: {}", b.name, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match (snapshot.bulbs.is_empty(), last_error) {
+            (true, Some(e)) => Err(e),
+            _ => Ok(snapshot),
+        }
+    }
+}
+
+impl GetPilotResult {
+    /// The setPilot that brings a bulb back to this state: the scene and its speed, or the
+    /// color and white channels, or the temperature, with the dimming and on/off state. A bulb
+    /// that was off gets its look too, so it comes back that way when it's switched on.
+    pub fn restoring_params(&self) -> SetPilotParams {
+        let mut p = SetPilotParams {
+            state: Some(self.state),
+            dimming: self.dimming,
+            ..Default::default()
+        };
+
+        match self.scene_id.filter(|s| *s != 0) {
+            Some(scene_id) => {
+                p.scene_id = Some(scene_id);
+                p.speed = self.speed;
+            }
+            None if self.color().is_some() || self.c.is_some() || self.w.is_some() => {
+                (p.r, p.g, p.b) = (self.r, self.g, self.b);
+                (p.c, p.w) = (self.c, self.w);
+            }
+            None => p.temp = self.temp,
+        }
+
+        p
+    }
+}
+
+impl Bulb {
+    /// Reads back everything the bulb is showing.
+    pub fn snapshot(&self) -> Result<Snapshot, ErrorResponse> {
+        Ok(Snapshot {
+            bulbs: vec![self.capture()?],
+        })
+    }
+
+    pub(crate) fn capture(&self) -> Result<BulbSnapshot, ErrorResponse> {
+        Ok(BulbSnapshot {
+            ip: self.ip_address,
+            name: self.name.clone(),
+            pilot: self.get_pilot()?.result,
+        })
+    }
+
+    /// Puts the bulb back the way `snapshot` saw it, in one setPilot sent with the bulb's
+    /// delivery. The bulb is looked up by its MAC, so one that has moved address since is
+    /// still found. Returns `false` without sending anything if it isn't in the snapshot.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<bool, ErrorResponse> {
+        let mac = self.mac()?;
+        match snapshot.pilot(&mac, self.ip_address) {
+            Some(pilot) => self.restore_pilot(pilot),
+            None => {
+                info!("{} is not in the snapshot, leaving it be", self.name);
+                Ok(false)
+            }
+        }
+    }
+
+    pub(crate) fn restore_pilot(&mut self, pilot: &GetPilotResult) -> Result<bool, ErrorResponse> {
+        let params = pilot.restoring_params();
+        self.send_pilot(SetPilot { params, ..Default::default() }, self.delivery)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::bulb::{Off, On};
+    use crate::color::Color;
+    use crate::sim::VirtualBulb;
+
+    use super::*;
+
+    #[rstest]
+    #[case(SetPilotParams { state: Some(true), dimming: Some(40), temp: Some(3500), ..Default::default() })]
+    #[case(SetPilotParams { state: Some(true), dimming: Some(70), r: Some(10), g: Some(200), b: Some(30), c: Some(5), w: Some(60), ..Default::default() })]
+    #[case(SetPilotParams { state: Some(true), dimming: Some(55), scene_id: Some(4), speed: Some(150), ..Default::default() })]
+    fn test_restore_round_trip(#[case] look: SetPilotParams) {
+        let sim = VirtualBulb::new().unwrap();
+        let mut b = sim.bulb("foo", 1);
+        b.send_pilot(SetPilot { params: look, ..Default::default() }, Default::default()).unwrap();
+        let before = sim.state();
+
+        let snapshot = b.snapshot().unwrap();
+        b.send_pilot(SetPilot::default().state(true).color(Color::WHITE).to_owned(), Default::default())
+            .unwrap();
+        assert!(b.restore(&snapshot).unwrap());
+
+        assert_eq!(sim.state(), before);
+    }
+
+    #[rstest]
+    fn test_off_bulb_stays_off_with_its_look() {
+        let sim = VirtualBulb::new().unwrap();
+        let mut b = sim.bulb("foo", 1);
+        b.send_pilot(SetPilot::default().state(true).color(Color::rgb(255, 0, 0)).to_owned(), Default::default())
+            .unwrap();
+        b.off().unwrap();
+        let before = sim.state();
+
+        let snapshot = b.snapshot().unwrap();
+        b.on().unwrap();
+        b.send_pilot(SetPilot::default().temperature(6000).to_owned(), Default::default()).unwrap();
+        b.restore(&snapshot).unwrap();
+
+        assert_eq!(sim.state(), before);
+    }
+
+    #[rstest]
+    fn test_restore_follows_mac_to_new_address() {
+        let mut sim = VirtualBulb::new().unwrap();
+        let mut b = sim.bulb("foo", 1);
+        b.send_pilot(SetPilot::default().state(true).temperature(3000).to_owned(), Default::default()).unwrap();
+        let before = sim.state();
+        let snapshot = b.snapshot().unwrap();
+
+        sim.come_back(true).unwrap();
+        let mut moved = sim.bulb("foo", 1);
+        moved.send_pilot(SetPilot::default().color(Color::rgb(0, 0, 255)).to_owned(), Default::default()).unwrap();
+
+        assert!(moved.restore(&snapshot).unwrap());
+        assert_eq!(sim.state(), before);
+    }
+
+    #[rstest]
+    fn test_pilot_falls_back_to_ip_without_mac() {
+        let ip: IpAddr = "192.168.1.20".parse().unwrap();
+        let snapshot = Snapshot {
+            bulbs: vec![BulbSnapshot { ip, name: "foo".to_string(), pilot: GetPilotResult::default() }],
+        };
+
+        assert!(snapshot.pilot("a8bb50000000", ip).is_some());
+        assert!(snapshot.pilot("a8bb50000000", "192.168.1.21".parse().unwrap()).is_none());
+    }
+
+    #[rstest]
+    fn test_restore_skips_unknown_bulb() {
+        let sim = VirtualBulb::new().unwrap();
+        let mut b = sim.bulb("foo", 1);
+
+        assert!(!b.restore(&Snapshot::default()).unwrap());
+    }
+}
@@ -1,7 +1,19 @@
 use crate::bulb::response::Response;
+use crate::bulb::transport::Datagram;
 use std::net::Ipv4Addr;
 
 pub struct SourcedResponse {
     pub source: Ipv4Addr,
     pub response: Response,
 }
+
+impl TryFrom<Datagram> for SourcedResponse {
+    type Error = serde_json::Error;
+
+    fn try_from(value: Datagram) -> Result<Self, Self::Error> {
+        Ok(SourcedResponse {
+            source: value.source,
+            response: serde_json::from_slice(&value.bytes)?,
+        })
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::str::from_utf8;
+use std::sync::Arc;
+
+use log::info;
+
+use crate::bulb::give_socket;
+
+/// A raw reply and the address it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Datagram {
+    pub source: Ipv4Addr,
+    pub bytes: Vec<u8>,
+}
+
+/// How a `Bulb` gets its messages to the bulb and back. Implementations deal in raw bytes so
+/// wrappers can see exactly what went over the wire.
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Sends `message` to the bulb at `target` and waits for one reply.
+    fn exchange(&self, target: IpAddr, message: &[u8]) -> anyhow::Result<Datagram>;
+    /// Sends `message` to `target` without waiting for a reply.
+    fn fire(&self, target: IpAddr, message: &[u8]) -> anyhow::Result<()>;
+    /// Sends `message` to `target`, usually a broadcast address, and collects every reply
+    /// that arrives until the read times out.
+    fn poll(&self, target: IpAddr, message: &[u8]) -> anyhow::Result<Vec<Datagram>>;
+}
+
+/// Plain UDP on the bulbs' port; what every `Bulb` uses unless told otherwise.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Udp;
+
+impl Transport for Udp {
+    fn exchange(&self, target: IpAddr, message: &[u8]) -> anyhow::Result<Datagram> {
+        let sock = give_socket()?;
+        let mut buff = [0; 512];
+
+        sock.send_to(message, SocketAddr::new(target, 38899))?;
+
+        recv_datagram(&sock, &mut buff)
+    }
+
+    fn fire(&self, target: IpAddr, message: &[u8]) -> anyhow::Result<()> {
+        let sock = give_socket()?;
+
+        sock.send_to(message, SocketAddr::new(target, 38899))?;
+
+        Ok(())
+    }
+
+    fn poll(&self, target: IpAddr, message: &[u8]) -> anyhow::Result<Vec<Datagram>> {
+        let sock = give_socket()?;
+        let mut buff = [0; 512];
+
+        sock.send_to(message, SocketAddr::new(target, 38899))?;
+
+        let mut datagrams = vec![];
+        while let Ok(d) = recv_datagram(&sock, &mut buff) {
+            datagrams.push(d);
+        }
+        Ok(datagrams)
+    }
+}
+
+pub(crate) fn recv_datagram(socket: &UdpSocket, buff: &mut [u8]) -> anyhow::Result<Datagram> {
+    let received = socket.recv_from(buff)?;
+
+    info!("from {}", received.1);
+    info!(
+        "{}",
+        from_utf8(&buff[..received.0]).unwrap_or("Error retreiving from buffer")
+    );
+
+    Ok(Datagram {
+        source: match received.1.ip() {
+            IpAddr::V4(ip4) => ip4,
+            IpAddr::V6(ip6) => {
+                return Err(anyhow::anyhow!(
+                    "We should never hava an Ipv6 Address: {}",
+                    ip6
+                ))
+            }
+        },
+        bytes: buff[..received.0].to_vec(),
+    })
+}
+
+/// The transport a `Bulb` was given, falling back to [`Udp`]. It isn't part of the bulb's
+/// identity, so it's never serialized and never affects equality.
+#[derive(Clone, Default)]
+pub(crate) struct Link(Option<Arc<dyn Transport>>);
+
+impl Link {
+    pub fn new(transport: Arc<dyn Transport>) -> Link {
+        Link(Some(transport))
+    }
+
+    pub fn get(&self) -> &dyn Transport {
+        match &self.0 {
+            Some(t) => t.as_ref(),
+            None => &Udp,
+        }
+    }
+}
+
+impl fmt::Debug for Link {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.get())
+    }
+}
+
+impl PartialEq for Link {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,341 @@
+//! Lighting that follows the sun.
+//!
+//! A [`Circadian`] controller works out where the sun is for a [`Location`] from the clock
+//! alone, no network services involved, and steers each enrolled [`Group`] between its own
+//! [`Limits`]: warm and dim while the sun is down, cool and bright when it's high. A group
+//! someone has changed by hand is left alone for a while before the controller takes it back.
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::Id;
+
+use crate::bulb::response::ErrorResponse;
+use crate::bulb::{Delivery, DimmingCurve, SetPilot, SetPilotParams};
+use crate::color::{MAX_KELVIN, MIN_KELVIN};
+use crate::effect::Clock;
+use crate::registry::Group;
+pub use solar::Location;
+
+mod solar;
+
+/// Sun elevation, in degrees, from which the light starts to follow it: civil twilight.
+const DAWN_ELEVATION: f64 = -6.0;
+/// Sun elevation, in degrees, at and above which the light stays at its coolest and brightest.
+const FULL_ELEVATION: f64 = 45.0;
+
+/// The span a group is steered across.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct Limits {
+    pub min_kelvin: u32,
+    pub max_kelvin: u32,
+    /// Brightness levels in `0.0..=1.0`, mapped through each bulb's dimming curve.
+    pub min_level: f64,
+    pub max_level: f64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            min_kelvin: 2200,
+            max_kelvin: 5500,
+            min_level: 0.3,
+            max_level: 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Member {
+    group: Group,
+    limits: Limits,
+    paused_until: Option<SystemTime>,
+    /// What each bulb is known to have taken, in [`Group::bulbs`] order: `None` where the last
+    /// send failed or went out fire-and-forget, so there's nothing to check it against.
+    sent: Vec<Option<SetPilotParams>>,
+}
+
+#[derive(Debug)]
+pub struct Circadian {
+    location: Location,
+    members: Vec<Member>,
+    override_hold: Duration,
+}
+
+impl Circadian {
+    pub fn new(location: Location) -> Circadian {
+        Circadian {
+            location,
+            members: vec![],
+            override_hold: Duration::from_secs(60 * 60),
+        }
+    }
+
+    /// How long a group changed by hand is left alone.
+    pub fn set_override_hold(&mut self, hold: Duration) -> &mut Self {
+        self.override_hold = hold;
+        self
+    }
+
+    pub fn enroll(&mut self, group: Group, limits: Limits) -> &mut Self {
+        self.members.push(Member { group, limits, paused_until: None, sent: vec![] });
+        self
+    }
+
+    pub fn groups(&self) -> Vec<&Group> {
+        self.members.iter().map(|m| &m.group).collect()
+    }
+
+    fn member(&mut self, id: &Id) -> Option<&mut Member> {
+        self.members.iter_mut().find(|m| m.group._id == *id)
+    }
+
+    /// Stops steering the group with `id` until `until`. Returns `false` if it isn't enrolled.
+    pub fn pause(&mut self, id: &Id, until: SystemTime) -> bool {
+        self.member(id).map(|m| m.paused_until = Some(until)).is_some()
+    }
+
+    pub fn resume(&mut self, id: &Id) -> bool {
+        self.member(id).map(|m| m.paused_until = None).is_some()
+    }
+
+    pub fn is_paused(&self, id: &Id, at: SystemTime) -> bool {
+        self.members
+            .iter()
+            .any(|m| m.group._id == *id && m.paused_until.is_some_and(|until| at < until))
+    }
+
+    /// How far along the day is at `at`, from `0.0` while the sun is down to `1.0` when it's
+    /// high, following the sun's elevation.
+    pub fn daylight(&self, at: SystemTime) -> f64 {
+        let elevation = self.location.solar_elevation(at);
+        let t = ((elevation - DAWN_ELEVATION) / (FULL_ELEVATION - DAWN_ELEVATION)).clamp(0.0, 1.0);
+
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    /// The white a bulb with `curve` should show at `at` within `limits`.
+    pub fn target(&self, limits: &Limits, at: SystemTime, curve: &DimmingCurve) -> SetPilotParams {
+        let t = self.daylight(at);
+        let kelvin = limits.min_kelvin as f64 + (limits.max_kelvin as f64 - limits.min_kelvin as f64) * t;
+        let level = limits.min_level + (limits.max_level - limits.min_level) * t;
+
+        SetPilot::default()
+            .level(level, curve)
+            .temperature((kelvin.round() as u32).clamp(MIN_KELVIN, MAX_KELVIN))
+            .to_owned()
+            .params
+    }
+
+    /// Brings every enrolled group up to date for `at`.
+    ///
+    /// Before sending, each group is read back; if a bulb no longer shows what it last took,
+    /// someone has taken over and the group is paused for the override hold. Bulbs whose last
+    /// send wasn't acknowledged aren't checked, since a lost packet looks the same. Paused
+    /// groups are skipped and left out of the results.
+    pub fn step(&mut self, at: SystemTime) -> Vec<(Id, Result<bool, ErrorResponse>)> {
+        let mut results = vec![];
+        for i in 0..self.members.len() {
+            let target: Vec<_> = self.members[i]
+                .group
+                .bulbs()
+                .iter()
+                .map(|b| self.target(&self.members[i].limits, at, b.dimming_curve()))
+                .collect();
+            let hold = self.override_hold;
+            let m = &mut self.members[i];
+
+            match m.paused_until {
+                Some(until) if at < until => continue,
+                Some(_) => {
+                    m.paused_until = None;
+                    m.sent.clear();
+                }
+                None => {}
+            }
+
+            if m.overridden() {
+                info!("{} was changed by hand, pausing it", m.group._id);
+                m.paused_until = Some(at + hold);
+                m.sent.clear();
+                continue;
+            }
+
+            results.push((m.group._id.clone(), m.apply(target)));
+        }
+
+        results
+    }
+
+    /// Calls [`Circadian::step`] every `every` until the task is dropped or aborted. Wall time
+    /// is taken to move with `clock`, so a virtual clock plays through a simulated day.
+    pub async fn run(mut self, clock: Arc<dyn Clock>, every: Duration) {
+        let epoch = SystemTime::now() - clock.now();
+        loop {
+            let at = epoch + clock.now();
+            self = tokio::task::spawn_blocking(move || {
+                self.step(at);
+                self
+            })
+            .await
+            .unwrap();
+
+            clock.sleep_until(clock.now() + every).await;
+        }
+    }
+}
+
+impl Member {
+    fn overridden(&self) -> bool {
+        self.group.bulbs().iter().zip(self.sent.iter()).any(|(b, sent)| match (sent, b.get_pilot()) {
+            (Some(sent), Ok(observed)) => !observed.result.differing_fields(sent).is_empty(),
+            _ => false,
+        })
+    }
+
+    /// Sends each bulb its target, carrying on past members that fail; the last error is
+    /// returned once all have been tried.
+    fn apply(&mut self, target: Vec<SetPilotParams>) -> Result<bool, ErrorResponse> {
+        let mut result = Ok(true);
+        self.sent.clear();
+        for (b, p) in self.group.bulbs_mut().into_iter().zip(target) {
+            let delivery = b.delivery();
+            match b.send_pilot(SetPilot { params: p.clone(), ..Default::default() }, delivery) {
+                Ok(success) => {
+                    let taken = success && delivery != Delivery::FireAndForget;
+                    self.sent.push(taken.then_some(p));
+                    result = result.map(|s| s && success);
+                }
+                Err(e) => {
+                    error!("{} did not take its circadian target: {}", b.name, e);
+                    self.sent.push(None);
+                    result = Err(e);
+                }
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use rstest::rstest;
+
+    use crate::sim::{FaultProfile, VirtualBulb};
+
+    use super::*;
+
+    const GREENWICH: Location = Location::new(51.48, 0.0);
+    // 2024-06-21
+    const NOON: u64 = 1718971200;
+    const MIDNIGHT: u64 = 1718928000;
+
+    fn utc(unix: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(unix)
+    }
+
+    #[rstest]
+    fn test_target_follows_limits() {
+        let c = Circadian::new(GREENWICH);
+        let limits = Limits { min_kelvin: 2700, max_kelvin: 5000, min_level: 0.2, max_level: 0.9 };
+
+        let night = c.target(&limits, utc(MIDNIGHT), &DimmingCurve::Linear);
+        assert_eq!((night.temp, night.dimming), (Some(2700), Some(28)));
+
+        let noon = c.target(&limits, utc(NOON), &DimmingCurve::Linear);
+        assert_eq!((noon.temp, noon.dimming), (Some(5000), Some(91)));
+    }
+
+    #[rstest]
+    fn test_daylight_rises_through_the_morning() {
+        let c = Circadian::new(GREENWICH);
+        let hours: Vec<f64> = (0..=12).map(|h| c.daylight(utc(MIDNIGHT + h * 3600))).collect();
+
+        assert!(hours.windows(2).all(|w| w[0] <= w[1]), "{:?}", hours);
+        assert!(hours[6] > 0.0 && hours[6] < 1.0);
+    }
+
+    #[rstest]
+    fn test_step_applies_to_enrolled_groups() {
+        let sims = VirtualBulb::many(2).unwrap();
+        let mut c = Circadian::new(GREENWICH);
+        c.enroll(VirtualBulb::group(&sims, Id::from(40), "living"), Limits::default());
+
+        let results = c.step(utc(NOON));
+
+        assert_eq!(results.len(), 1);
+        assert!(results[0].1.as_ref().unwrap());
+        assert!(sims.iter().all(|s| s.state().temp == Some(5500) && s.state().dimming == 100));
+    }
+
+    #[rstest]
+    fn test_manual_change_pauses_group() {
+        let sims = VirtualBulb::many(2).unwrap();
+        let mut c = Circadian::new(GREENWICH);
+        c.enroll(VirtualBulb::group(&sims, Id::from(40), "living"), Limits::default()).set_override_hold(Duration::from_secs(600));
+
+        c.step(utc(NOON));
+        sims[1].update(|s| s.dimming = 40);
+
+        assert!(c.step(utc(NOON + 60)).is_empty());
+        assert!(c.is_paused(&Id::from(40), utc(NOON + 60)));
+        assert_eq!(sims[1].state().dimming, 40);
+
+        assert_eq!(c.step(utc(NOON + 900)).len(), 1);
+        assert_eq!(sims[1].state().dimming, 100);
+    }
+
+    #[rstest]
+    fn test_failed_send_does_not_pause_group() {
+        let sims = VirtualBulb::many(2).unwrap();
+        let mut c = Circadian::new(GREENWICH);
+        c.enroll(VirtualBulb::group(&sims, Id::from(40), "living"), Limits::default());
+
+        c.step(utc(MIDNIGHT));
+        sims[1].set_faults(FaultProfile { loss: 1.0, ..Default::default() });
+        assert!(c.step(utc(NOON)).iter().all(|(_, r)| r.is_err()));
+        assert_eq!(sims[0].state().dimming, 100);
+        sims[1].set_faults(FaultProfile::default());
+
+        assert_eq!(c.step(utc(NOON + 60)).len(), 1);
+        assert!(!c.is_paused(&Id::from(40), utc(NOON + 60)));
+        assert_eq!(sims[1].state().dimming, 100);
+    }
+
+    #[rstest]
+    fn test_lost_frame_does_not_pause_group() {
+        let sims = VirtualBulb::many(2).unwrap();
+        let mut g = VirtualBulb::group(&sims, Id::from(40), "living");
+        for b in g.bulbs_mut() {
+            b.set_delivery(Delivery::FireAndForget);
+        }
+        let mut c = Circadian::new(GREENWICH);
+        c.enroll(g, Limits::default());
+
+        c.step(utc(MIDNIGHT));
+        sims[1].set_faults(FaultProfile { loss: 1.0, ..Default::default() });
+        c.step(utc(NOON));
+        sims[1].set_faults(FaultProfile::default());
+
+        assert_eq!(c.step(utc(NOON + 60)).len(), 1);
+        assert!(!c.is_paused(&Id::from(40), utc(NOON + 60)));
+    }
+
+    #[rstest]
+    fn test_explicit_pause() {
+        let sims = VirtualBulb::many(1).unwrap();
+        let mut c = Circadian::new(GREENWICH);
+        c.enroll(VirtualBulb::group(&sims, Id::from(40), "living"), Limits::default());
+
+        assert!(c.pause(&Id::from(40), utc(NOON + 60)));
+        assert!(!c.pause(&Id::from(41), utc(NOON + 60)));
+        assert!(c.step(utc(NOON)).is_empty());
+
+        assert!(c.resume(&Id::from(40)));
+        assert_eq!(c.step(utc(NOON)).len(), 1);
+    }
+}
@@ -0,0 +1,89 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// A place on Earth in degrees, north and east positive.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Location {
+    pub const fn new(latitude: f64, longitude: f64) -> Location {
+        Location { latitude, longitude }
+    }
+
+    /// The sun's height above the horizon at `at`, in degrees, negative at night.
+    ///
+    /// This is NOAA's solar position algorithm without the refraction correction, good to well
+    /// under a degree for any date this code will see.
+    pub fn solar_elevation(&self, at: SystemTime) -> f64 {
+        let seconds = match at.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs_f64(),
+            Err(e) => -e.duration().as_secs_f64(),
+        };
+        let jd = seconds / 86400.0 + 2440587.5;
+        let jc = (jd - 2451545.0) / 36525.0;
+
+        let mean_long = (280.46646 + jc * (36000.76983 + jc * 0.0003032)).rem_euclid(360.0);
+        let mean_anom = 357.52911 + jc * (35999.05029 - 0.0001537 * jc);
+        let eccentricity = 0.016708634 - jc * (0.000042037 + 0.0000001267 * jc);
+        let m = mean_anom.to_radians();
+        let center = m.sin() * (1.914602 - jc * (0.004817 + 0.000014 * jc))
+            + (2.0 * m).sin() * (0.019993 - 0.000101 * jc)
+            + (3.0 * m).sin() * 0.000289;
+
+        let omega = (125.04 - 1934.136 * jc).to_radians();
+        let apparent_long = mean_long + center - 0.00569 - 0.00478 * omega.sin();
+        let mean_obliquity = 23.0 + (26.0 + (21.448 - jc * (46.815 + jc * (0.00059 - jc * 0.001813))) / 60.0) / 60.0;
+        let obliquity = (mean_obliquity + 0.00256 * omega.cos()).to_radians();
+        let declination = (obliquity.sin() * apparent_long.to_radians().sin()).asin();
+
+        let y = (obliquity / 2.0).tan().powi(2);
+        let l = mean_long.to_radians();
+        let equation_of_time = 4.0
+            * (y * (2.0 * l).sin() - 2.0 * eccentricity * m.sin()
+                + 4.0 * eccentricity * y * m.sin() * (2.0 * l).cos()
+                - 0.5 * y * y * (4.0 * l).sin()
+                - 1.25 * eccentricity * eccentricity * (2.0 * m).sin())
+            .to_degrees();
+
+        let minutes = seconds.rem_euclid(86400.0) / 60.0;
+        let solar_time = (minutes + equation_of_time + 4.0 * self.longitude).rem_euclid(1440.0);
+        let hour_angle = (solar_time / 4.0 - 180.0).to_radians();
+
+        let latitude = self.latitude.to_radians();
+        let cos_zenith = latitude.sin() * declination.sin()
+            + latitude.cos() * declination.cos() * hour_angle.cos();
+
+        90.0 - cos_zenith.clamp(-1.0, 1.0).acos().to_degrees()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use rstest::rstest;
+
+    fn utc(unix: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(unix)
+    }
+
+    #[rstest]
+    // Greenwich, 2024-06-21 12:00 UTC: close to the 62° summer maximum
+    #[case(Location::new(51.48, 0.0), 1718971200, 62.0)]
+    // Greenwich, 2024-06-21 00:00 UTC: well below the horizon
+    #[case(Location::new(51.48, 0.0), 1718928000, -15.0)]
+    // on the equator at the March 2024 equinox, around noon: nearly overhead
+    #[case(Location::new(0.0, 0.0), 1710936000, 88.0)]
+    // Sydney, 2024-06-21 02:00 UTC (noon local, midwinter)
+    #[case(Location::new(-33.87, 151.21), 1718935200, 32.5)]
+    fn test_solar_elevation(#[case] location: Location, #[case] unix: u64, #[case] expected: f64) {
+        let elevation = location.solar_elevation(utc(unix));
+
+        assert!((elevation - expected).abs() < 1.5, "{} vs {}", elevation, expected);
+    }
+}
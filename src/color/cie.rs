@@ -0,0 +1,260 @@
+//! CIE 1931 xy and mireds, as Home Assistant and Hue-style integrations speak them.
+use serde::{Deserialize, Serialize};
+
+use crate::bulb::response::GetPilotResult;
+use crate::bulb::SetPilotParams;
+use crate::color::{Color, PilotState, MAX_KELVIN, MIN_KELVIN};
+
+/// A chromaticity in the CIE 1931 xy plane.
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct Xy {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Xy {
+    pub const fn new(x: f64, y: f64) -> Xy {
+        Xy { x, y }
+    }
+}
+
+/// The triangle of chromaticities a set of RGB LEDs can reach.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Gamut {
+    pub red: Xy,
+    pub green: Xy,
+    pub blue: Xy,
+}
+
+/// The WiZ RGB LEDs, approximated by the common "gamut C" used for RGB bulbs. WiZ doesn't
+/// publish its primaries, so treat this as close rather than exact.
+pub const WIZ_GAMUT: Gamut = Gamut {
+    red: Xy::new(0.6915, 0.3083),
+    green: Xy::new(0.17, 0.7),
+    blue: Xy::new(0.1532, 0.0475),
+};
+
+impl Gamut {
+    pub fn contains(&self, p: Xy) -> bool {
+        let side = |a: Xy, b: Xy| (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x);
+        let d1 = side(self.red, self.green);
+        let d2 = side(self.green, self.blue);
+        let d3 = side(self.blue, self.red);
+
+        !((d1 < 0.0 || d2 < 0.0 || d3 < 0.0) && (d1 > 0.0 || d2 > 0.0 || d3 > 0.0))
+    }
+
+    /// `p` if it's reachable, otherwise the nearest point on the edge of the gamut.
+    pub fn clamp(&self, p: Xy) -> Xy {
+        if self.contains(p) {
+            return p;
+        }
+
+        [
+            closest_on_segment(self.red, self.green, p),
+            closest_on_segment(self.green, self.blue, p),
+            closest_on_segment(self.blue, self.red, p),
+        ]
+        .into_iter()
+        .min_by(|a, b| distance(*a, p).total_cmp(&distance(*b, p)))
+        .unwrap()
+    }
+}
+
+fn distance(a: Xy, b: Xy) -> f64 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+}
+
+fn closest_on_segment(a: Xy, b: Xy, p: Xy) -> Xy {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let t = (((p.x - a.x) * dx + (p.y - a.y) * dy) / (dx * dx + dy * dy)).clamp(0.0, 1.0);
+
+    Xy::new(a.x + t * dx, a.y + t * dy)
+}
+
+pub fn mireds_to_kelvin(mireds: u32) -> u32 {
+    1_000_000 / mireds.max(1)
+}
+
+pub fn kelvin_to_mireds(kelvin: u32) -> u32 {
+    1_000_000 / kelvin.max(1)
+}
+
+/// A `0..=255` brightness as `dimming`; `None` for 0, which means off.
+pub fn brightness_to_dimming(brightness: u8) -> Option<u32> {
+    match brightness {
+        0 => None,
+        b => Some(10 + ((b as u32 - 1) * 90 + 127) / 254),
+    }
+}
+
+/// `dimming` as a `1..=255` brightness.
+pub fn dimming_to_brightness(dimming: u32) -> u8 {
+    (1 + (dimming.clamp(10, 100) - 10) * 254 / 90) as u8
+}
+
+impl Color {
+    /// Chromaticity and relative luminance `Y` of this sRGB color (D65 white).
+    pub fn to_xy(&self) -> (Xy, f64) {
+        let (r, g, b) = self.to_linear();
+        let x = 0.4124 * r + 0.3576 * g + 0.1805 * b;
+        let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let z = 0.0193 * r + 0.1192 * g + 0.9505 * b;
+        let sum = x + y + z;
+
+        if sum == 0.0 {
+            // black has no chromaticity; report the white point
+            return (Xy::new(0.3127, 0.3290), 0.0);
+        }
+        (Xy::new(x / sum, y / sum), y)
+    }
+
+    /// The brightest sRGB color with chromaticity `xy`, so the bulb's `dimming` can carry the
+    /// brightness separately. Colors outside sRGB are clipped.
+    pub fn from_xy(xy: Xy) -> Color {
+        let xy = Xy::new(xy.x, xy.y.max(f64::EPSILON));
+        let (x, y, z) = (xy.x / xy.y, 1.0, (1.0 - xy.x - xy.y) / xy.y);
+
+        let r = 3.2406 * x - 1.5372 * y - 0.4986 * z;
+        let g = -0.9689 * x + 1.8758 * y + 0.0415 * z;
+        let b = 0.0557 * x - 0.2040 * y + 1.0570 * z;
+        let (r, g, b) = (r.max(0.0), g.max(0.0), b.max(0.0));
+        let max = r.max(g).max(b).max(f64::EPSILON);
+
+        Color::from_linear(r / max, g / max, b / max)
+    }
+}
+
+impl SetPilotParams {
+    /// A color command from xy and a `0..=255` brightness, clamped to [`WIZ_GAMUT`].
+    pub fn from_xy(xy: Xy, brightness: u8) -> SetPilotParams {
+        let color = Color::from_xy(WIZ_GAMUT.clamp(xy));
+
+        SetPilotParams {
+            r: Some(color.r as u32),
+            g: Some(color.g as u32),
+            b: Some(color.b as u32),
+            ..SetPilotParams::from_brightness(brightness)
+        }
+    }
+
+    /// A white command from mireds and a `0..=255` brightness, clamped to what WiZ accepts.
+    pub fn from_mireds(mireds: u32, brightness: u8) -> SetPilotParams {
+        SetPilotParams {
+            temp: Some(mireds_to_kelvin(mireds).clamp(MIN_KELVIN, MAX_KELVIN)),
+            ..SetPilotParams::from_brightness(brightness)
+        }
+    }
+
+    fn from_brightness(brightness: u8) -> SetPilotParams {
+        match brightness_to_dimming(brightness) {
+            Some(dimming) => SetPilotParams {
+                state: Some(true),
+                dimming: Some(dimming),
+                ..Default::default()
+            },
+            None => SetPilotParams {
+                state: Some(false),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl GetPilotResult {
+    /// Chromaticity of whatever the bulb shows, whether it's in color, white or scene mode.
+    pub fn xy(&self) -> Xy {
+        let full = GetPilotResult {
+            state: true,
+            dimming: None,
+            mac: String::new(),
+            ..*self
+        };
+        full.display_color().to_xy().0
+    }
+
+    /// Color temperature in mireds, if the bulb is in white mode.
+    pub fn mireds(&self) -> Option<u32> {
+        self.temp.map(kelvin_to_mireds)
+    }
+
+    /// `dimming` as a `0..=255` brightness, 0 when off.
+    pub fn brightness(&self) -> u8 {
+        match self.state {
+            true => dimming_to_brightness(self.dimming.unwrap_or(100)),
+            false => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn near(a: Xy, b: Xy) -> bool {
+        distance(a, b) < 0.005
+    }
+
+    #[rstest]
+    #[case(Color::rgb(255, 0, 0), Xy::new(0.64, 0.33))]
+    #[case(Color::rgb(0, 255, 0), Xy::new(0.30, 0.60))]
+    #[case(Color::WHITE, Xy::new(0.3127, 0.3290))]
+    fn test_to_xy(#[case] color: Color, #[case] expected: Xy) {
+        assert!(near(color.to_xy().0, expected), "{:?}", color.to_xy());
+    }
+
+    #[rstest]
+    #[case(Color::rgb(255, 136, 0))]
+    #[case(Color::rgb(30, 60, 255))]
+    #[case(Color::WHITE)]
+    fn test_xy_round_trip(#[case] color: Color) {
+        assert_eq!(Color::from_xy(color.to_xy().0), color);
+    }
+
+    #[rstest]
+    fn test_gamut_clamp() {
+        let outside = Xy::new(0.0, 0.9);
+        let clamped = WIZ_GAMUT.clamp(outside);
+
+        assert!(!WIZ_GAMUT.contains(outside));
+        assert!(WIZ_GAMUT.contains(Xy::new(clamped.x + 0.001, clamped.y - 0.001)));
+        assert_eq!(WIZ_GAMUT.clamp(Xy::new(0.3127, 0.3290)), Xy::new(0.3127, 0.3290));
+    }
+
+    #[rstest]
+    #[case(0, None)]
+    #[case(1, Some(10))]
+    #[case(128, Some(55))]
+    #[case(255, Some(100))]
+    fn test_brightness_to_dimming(#[case] brightness: u8, #[case] expected: Option<u32>) {
+        assert_eq!(brightness_to_dimming(brightness), expected);
+    }
+
+    #[rstest]
+    #[case(153, Some(6500))]
+    #[case(370, Some(2702))]
+    #[case(500, Some(2200))]
+    fn test_from_mireds(#[case] mireds: u32, #[case] temp: Option<u32>) {
+        let p = SetPilotParams::from_mireds(mireds, 255);
+
+        assert_eq!(p.temp, temp);
+        assert_eq!(p.dimming, Some(100));
+    }
+
+    #[rstest]
+    fn test_from_xy_off() {
+        let p = SetPilotParams::from_xy(Xy::new(0.5, 0.4), 0);
+
+        assert_eq!(p.state, Some(false));
+    }
+
+    #[rstest]
+    fn test_get_pilot_result_xy() {
+        let p = GetPilotResult { state: true, r: Some(255), g: Some(0), b: Some(0), dimming: Some(10), ..Default::default() };
+
+        assert!(near(p.xy(), Xy::new(0.64, 0.33)));
+        assert_eq!(p.brightness(), 1);
+        assert_eq!(p.mireds(), None);
+    }
+}
@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+
+use crate::color::Color;
+
+/// A color in Oklab, where equal steps look like equal changes, so blends between two
+/// colors don't pass through muddy or overly dark midpoints the way RGB blends do.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Oklab {
+    pub l: f64,
+    pub a: f64,
+    pub b: f64,
+}
+
+impl Color {
+    pub fn to_oklab(&self) -> Oklab {
+        let (r, g, b) = self.to_linear();
+        let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+        let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+        let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+        Oklab {
+            l: 0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+            a: 1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+            b: 0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+        }
+    }
+
+    pub fn from_oklab(lab: Oklab) -> Color {
+        let l = (lab.l + 0.3963377774 * lab.a + 0.2158037573 * lab.b).powi(3);
+        let m = (lab.l - 0.1055613458 * lab.a - 0.0638541728 * lab.b).powi(3);
+        let s = (lab.l - 0.0894841775 * lab.a - 1.2914855480 * lab.b).powi(3);
+
+        Color::from_linear(
+            4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+            -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+            -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+        )
+    }
+
+    /// The color `t` of the way from `self` to `other`, blended in Oklab.
+    pub fn mix(&self, other: Color, t: f64) -> Color {
+        let (a, b) = (self.to_oklab(), other.to_oklab());
+        let t = t.clamp(0.0, 1.0);
+
+        Color::from_oklab(Oklab {
+            l: a.l + (b.l - a.l) * t,
+            a: a.a + (b.a - a.a) * t,
+            b: a.b + (b.b - a.b) * t,
+        })
+    }
+}
+
+/// Colors at positions along `0.0..=1.0`, blended in Oklab in between.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Gradient {
+    stops: Vec<(f64, Color)>,
+}
+
+impl Gradient {
+    /// `colors` spaced evenly from one end to the other.
+    pub fn new(colors: &[Color]) -> Gradient {
+        let last = colors.len().saturating_sub(1).max(1) as f64;
+
+        Gradient {
+            stops: colors.iter().enumerate().map(|(i, c)| (i as f64 / last, *c)).collect(),
+        }
+    }
+
+    /// Stops at explicit positions, which are clamped to `0.0..=1.0` and sorted.
+    pub fn with_stops(mut stops: Vec<(f64, Color)>) -> Gradient {
+        for s in stops.iter_mut() {
+            s.0 = s.0.clamp(0.0, 1.0);
+        }
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        Gradient { stops }
+    }
+
+    pub fn stops(&self) -> &[(f64, Color)] {
+        &self.stops
+    }
+
+    /// The color at `t`; before the first stop or after the last it holds that stop's color,
+    /// and a `t` that isn't a number gets the first.
+    pub fn at(&self, t: f64) -> Color {
+        let (first, last) = match (self.stops.first(), self.stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Color::BLACK,
+        };
+        if self.stops.len() == 1 || !t.is_finite() {
+            return first.1;
+        }
+        if t <= first.0 {
+            return first.1;
+        }
+        if t >= last.0 {
+            return last.1;
+        }
+
+        let i = self.stops.iter().position(|s| s.0 > t).unwrap_or(self.stops.len() - 1);
+        let (from, to) = (self.stops[i - 1], self.stops[i]);
+        let span = to.0 - from.0;
+        if span <= 0.0 {
+            return to.1;
+        }
+
+        from.1.mix(to.1, (t - from.0) / span)
+    }
+
+    /// `n` evenly spaced colors from end to end; a single sample takes the middle.
+    pub fn sample(&self, n: usize) -> Vec<Color> {
+        match n {
+            0 => vec![],
+            1 => vec![self.at(0.5)],
+            n => (0..n).map(|i| self.at(i as f64 / (n - 1) as f64)).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(Color::rgb(255, 136, 0))]
+    #[case(Color::rgb(10, 20, 250))]
+    #[case(Color::WHITE)]
+    #[case(Color::BLACK)]
+    fn test_oklab_round_trip(#[case] color: Color) {
+        assert_eq!(Color::from_oklab(color.to_oklab()), color);
+    }
+
+    #[rstest]
+    fn test_ends_and_midpoint() {
+        let g = Gradient::new(&[Color::rgb(255, 0, 0), Color::rgb(0, 0, 255)]);
+
+        assert_eq!(g.at(0.0), Color::rgb(255, 0, 0));
+        assert_eq!(g.at(1.0), Color::rgb(0, 0, 255));
+        // an RGB blend would give a dark (128, 0, 128); Oklab keeps it brighter
+        let mid = g.at(0.5);
+        assert!(mid.r > 128 && mid.b > 128, "{:?}", mid);
+    }
+
+    #[rstest]
+    fn test_sample_hits_every_stop() {
+        let colors = [Color::rgb(255, 0, 0), Color::rgb(0, 255, 0), Color::rgb(0, 0, 255)];
+        let g = Gradient::new(&colors);
+
+        assert_eq!(g.sample(3), colors.to_vec());
+        assert_eq!(g.sample(5).len(), 5);
+    }
+
+    #[rstest]
+    fn test_with_stops_sorts_and_holds_ends() {
+        let g = Gradient::with_stops(vec![(0.8, Color::WHITE), (0.2, Color::BLACK)]);
+
+        assert_eq!(g.at(0.0), Color::BLACK);
+        assert_eq!(g.at(1.0), Color::WHITE);
+    }
+
+    #[rstest]
+    #[case(f64::NAN)]
+    #[case(f64::INFINITY)]
+    #[case(0.5)]
+    fn test_single_stop_and_odd_positions(#[case] t: f64) {
+        let one = Gradient::new(&[Color::WHITE]);
+        let two = Gradient::new(&[Color::rgb(255, 0, 0), Color::rgb(0, 0, 255)]);
+
+        assert_eq!(one.at(t), Color::WHITE);
+        if !t.is_finite() {
+            assert_eq!(two.at(t), Color::rgb(255, 0, 0));
+        }
+    }
+}
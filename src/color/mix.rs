@@ -0,0 +1,250 @@
+use crate::color::render::{COOL_KELVIN, WARM_KELVIN};
+use crate::color::{kelvin_to_rgb, Color};
+
+/// Which LED channels a bulb has.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Channels {
+    pub rgb: bool,
+    pub cool: bool,
+    pub warm: bool,
+}
+
+impl Channels {
+    pub const RGBCW: Channels = Channels { rgb: true, cool: true, warm: true };
+    pub const RGBW: Channels = Channels { rgb: true, cool: false, warm: true };
+    pub const RGB: Channels = Channels { rgb: true, cool: false, warm: false };
+    pub const TUNABLE_WHITE: Channels = Channels { rgb: false, cool: true, warm: true };
+    pub const DIMMABLE_WHITE: Channels = Channels { rgb: false, cool: false, warm: true };
+
+    /// Guesses the channels from a `getSystemConfig` module name such as `ESP01_SHRGB1C_31`.
+    pub fn from_module_name(module_name: &str) -> Channels {
+        if module_name.contains("RGB") {
+            Channels::RGBCW
+        } else if module_name.contains("TW") {
+            Channels::TUNABLE_WHITE
+        } else if module_name.contains("DW") {
+            Channels::DIMMABLE_WHITE
+        } else {
+            Channels::RGBCW
+        }
+    }
+}
+
+impl Default for Channels {
+    fn default() -> Self {
+        Channels::RGBCW
+    }
+}
+
+/// Drive levels for every channel, as `setPilot` takes them.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Rgbcw {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub c: u8,
+    pub w: u8,
+}
+
+/// Splits target colors into RGB plus cool/warm white so whites come from the white LEDs.
+///
+/// The largest blend of the two whites that fits under the target (in linear light) goes to
+/// `c`/`w`, and only what's left over is made with the RGB LEDs. Without RGB the closest
+/// white blend is used on its own.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Mixer {
+    pub channels: Channels,
+    pub warm_kelvin: u32,
+    pub cool_kelvin: u32,
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Mixer {
+            channels: Channels::default(),
+            warm_kelvin: WARM_KELVIN,
+            cool_kelvin: COOL_KELVIN,
+        }
+    }
+}
+
+/// Steps tried between pure warm and pure cool when blending the whites.
+const BLEND_STEPS: u32 = 100;
+
+impl Mixer {
+    pub fn new(channels: Channels) -> Mixer {
+        Mixer {
+            channels,
+            ..Default::default()
+        }
+    }
+
+    pub fn mix(&self, target: Color) -> Rgbcw {
+        let t = target.to_linear();
+        let warm = kelvin_to_rgb(self.warm_kelvin).to_linear();
+        let cool = kelvin_to_rgb(self.cool_kelvin).to_linear();
+
+        let blends: Vec<f64> = match (self.channels.cool, self.channels.warm) {
+            (true, true) => (0..=BLEND_STEPS).map(|i| i as f64 / BLEND_STEPS as f64).collect(),
+            (true, false) => vec![1.0],
+            (false, true) => vec![0.0],
+            (false, false) => vec![],
+        };
+        let white = |k: f64| {
+            (
+                k * cool.0 + (1.0 - k) * warm.0,
+                k * cool.1 + (1.0 - k) * warm.1,
+                k * cool.2 + (1.0 - k) * warm.2,
+            )
+        };
+
+        // (share of cool, amount of white), keeping whichever blend takes the most light off
+        // the RGB LEDs
+        let best = if self.channels.rgb {
+            blends
+                .into_iter()
+                .map(|k| {
+                    let w = white(k);
+                    let amount = (t.0 / w.0).min(t.1 / w.1).min(t.2 / w.2).min(1.0);
+                    (k, amount, amount * (w.0 + w.1 + w.2))
+                })
+                .fold(None, |best: Option<(f64, f64, f64)>, candidate| match best {
+                    Some((_, _, light)) if light >= candidate.2 => best,
+                    _ => Some(candidate),
+                })
+                .map(|(k, amount, _)| (k, amount))
+        } else {
+            // nothing to make up the difference with, so match hue and brightness instead
+            let norm = |v: (f64, f64, f64)| {
+                let m = v.0.max(v.1).max(v.2).max(f64::EPSILON);
+                (v.0 / m, v.1 / m, v.2 / m)
+            };
+            let target = norm(t);
+            let error = |k: f64| {
+                let w = norm(white(k));
+                (w.0 - target.0).powi(2) + (w.1 - target.1).powi(2) + (w.2 - target.2).powi(2)
+            };
+            blends
+                .into_iter()
+                .min_by(|a, b| error(*a).total_cmp(&error(*b)))
+                .map(|k| {
+                    let w = white(k);
+                    (k, t.0.max(t.1).max(t.2) / w.0.max(w.1).max(w.2))
+                })
+        };
+
+        let (k, amount) = best.unwrap_or((0.0, 0.0));
+        let amount = amount.clamp(0.0, 1.0);
+        let w = white(k);
+        let residual = if self.channels.rgb {
+            Color::from_linear(t.0 - amount * w.0, t.1 - amount * w.1, t.2 - amount * w.2)
+        } else {
+            Color::BLACK
+        };
+        let level = |v: f64| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+        Rgbcw {
+            r: residual.r,
+            g: residual.g,
+            b: residual.b,
+            c: level(amount * k),
+            w: level(amount * (1.0 - k)),
+        }
+    }
+
+    /// A white at `kelvin`, nudged toward green (`tint > 0`) or magenta (`tint < 0`) by up to
+    /// about 10% at `tint = ±1`.
+    pub fn mix_kelvin(&self, kelvin: u32, tint: f64) -> Rgbcw {
+        let (r, g, b) = kelvin_to_rgb(kelvin).to_linear();
+        let tint = tint.clamp(-1.0, 1.0) * 0.1;
+        let (r, g, b) = if tint >= 0.0 {
+            (r * (1.0 - tint), g, b * (1.0 - tint))
+        } else {
+            (r, g * (1.0 + tint), b)
+        };
+
+        self.mix(Color::from_linear(r, g, b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bulb::SetPilotParams;
+    use crate::color::render;
+    use rstest::rstest;
+
+    fn close(a: Color, b: Color) -> bool {
+        let d = |x: u8, y: u8| (x as i32 - y as i32).abs();
+        d(a.r, b.r) <= 3 && d(a.g, b.g) <= 3 && d(a.b, b.b) <= 3
+    }
+
+    fn rendered(m: Rgbcw) -> Color {
+        render(&SetPilotParams {
+            r: Some(m.r as u32),
+            g: Some(m.g as u32),
+            b: Some(m.b as u32),
+            c: Some(m.c as u32),
+            w: Some(m.w as u32),
+            ..Default::default()
+        })
+    }
+
+    #[rstest]
+    fn test_warm_white_uses_only_warm_leds() {
+        let m = Mixer::default().mix(kelvin_to_rgb(WARM_KELVIN));
+
+        assert_eq!(m, Rgbcw { w: 255, ..Default::default() });
+    }
+
+    #[rstest]
+    fn test_in_between_white_blends_both() {
+        let m = Mixer::default().mix_kelvin(4000, 0.0);
+
+        // the black-body curve bows away from the line between the two whites, so a little
+        // green is left for the RGB LEDs
+        assert!(m.c > 0 && m.w > 0, "{:?}", m);
+        assert!(m.r < 64 && m.g < 64 && m.b < 64, "{:?}", m);
+    }
+
+    #[rstest]
+    fn test_saturated_color_has_no_white() {
+        let m = Mixer::default().mix(Color::rgb(255, 0, 0));
+
+        assert_eq!(m, Rgbcw { r: 255, ..Default::default() });
+    }
+
+    #[rstest]
+    #[case(Color::rgb(255, 200, 150))]
+    #[case(Color::rgb(120, 180, 255))]
+    #[case(Color::rgb(255, 136, 0))]
+    #[case(Color::rgb(200, 200, 200))]
+    fn test_mix_renders_back_to_target(#[case] target: Color) {
+        let m = Mixer::default().mix(target);
+
+        assert!(close(rendered(m), target), "{:?} -> {:?} -> {:?}", target, m, rendered(m));
+    }
+
+    #[rstest]
+    fn test_rgb_only_bulb_keeps_rgb() {
+        let m = Mixer::new(Channels::RGB).mix(Color::WHITE);
+
+        assert_eq!(m, Rgbcw { r: 255, g: 255, b: 255, ..Default::default() });
+    }
+
+    #[rstest]
+    fn test_tunable_white_picks_closest_white() {
+        let m = Mixer::new(Channels::TUNABLE_WHITE).mix(kelvin_to_rgb(6500));
+
+        assert_eq!((m.r, m.g, m.b), (0, 0, 0));
+        assert!(m.c > 200 && m.w < 30, "{:?}", m);
+    }
+
+    #[rstest]
+    #[case("ESP01_SHRGB1C_31", Channels::RGBCW)]
+    #[case("ESP15_SHTW1_01I", Channels::TUNABLE_WHITE)]
+    #[case("ESP06_SHDW9_01", Channels::DIMMABLE_WHITE)]
+    fn test_channels_from_module_name(#[case] module_name: &str, #[case] expected: Channels) {
+        assert_eq!(Channels::from_module_name(module_name), expected);
+    }
+}
@@ -0,0 +1,383 @@
+//! Colors for the bulbs, and the conversions between the ways people write them.
+//!
+//! A [`Color`] is plain 8-bit sRGB, which is what `setPilot` takes. It parses from hex
+//! (`#ff8800`, `#f80`), `rgb(255, 136, 0)`, `hsv(30, 100, 100)`, `hsl(30, 100%, 50%)`, CSS/X11
+//! names and a few WiZ-style whites like `"warm white"` or `"daylight"`.
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+pub use cie::{Gamut, Xy, WIZ_GAMUT};
+pub use gradient::{Gradient, Oklab};
+pub use mix::{Channels, Mixer, Rgbcw};
+pub use palette::{extract_palette, image_palette, image_palette_from_bytes};
+pub use render::{kelvin_to_rgb, render, PilotState};
+
+pub mod cie;
+mod gradient;
+mod mix;
+mod names;
+mod palette;
+mod render;
+
+/// Coolest and warmest `temp` the bulbs accept.
+pub const MIN_KELVIN: u32 = 2200;
+pub const MAX_KELVIN: u32 = 6500;
+
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// Hue in degrees, saturation and value in `0.0..=1.0`.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Hsv {
+    pub h: f64,
+    pub s: f64,
+    pub v: f64,
+}
+
+/// Hue in degrees, saturation and lightness in `0.0..=1.0`.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Hsl {
+    pub h: f64,
+    pub s: f64,
+    pub l: f64,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+    pub const WHITE: Color = Color::rgb(255, 255, 255);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b }
+    }
+
+    /// A color from `0xrrggbb`.
+    pub const fn from_u32(rgb: u32) -> Color {
+        Color::rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Color, ParseColorError> {
+        let digits = hex.trim().trim_start_matches('#');
+        let err = || ParseColorError::new(hex);
+
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(err());
+        }
+        match digits.len() {
+            3 => {
+                let v = u32::from_str_radix(digits, 16).map_err(|_| err())?;
+                let (r, g, b) = ((v >> 8) & 0xf, (v >> 4) & 0xf, v & 0xf);
+                Ok(Color::rgb((r * 17) as u8, (g * 17) as u8, (b * 17) as u8))
+            }
+            6 => Ok(Color::from_u32(u32::from_str_radix(digits, 16).map_err(|_| err())?)),
+            _ => Err(err()),
+        }
+    }
+
+    /// Looks up a CSS/X11 name or WiZ-style preset, ignoring case, spaces, `-` and `_`.
+    pub fn named(name: &str) -> Option<Color> {
+        let key: String = name
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '_'))
+            .flat_map(char::to_lowercase)
+            .collect();
+
+        names::NAMED
+            .iter()
+            .chain(names::PRESETS.iter())
+            .find(|(n, _)| *n == key)
+            .map(|(_, v)| Color::from_u32(*v))
+    }
+
+    pub fn to_hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+
+    pub fn to_hsv(&self) -> Hsv {
+        let (r, g, b) = self.unit();
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+
+        Hsv {
+            h: hue(r, g, b, max, delta),
+            s: if max == 0.0 { 0.0 } else { delta / max },
+            v: max,
+        }
+    }
+
+    pub fn from_hsv(hsv: Hsv) -> Color {
+        let c = hsv.v * hsv.s;
+        Color::from_chroma(hsv.h, c, hsv.v - c)
+    }
+
+    pub fn to_hsl(&self) -> Hsl {
+        let (r, g, b) = self.unit();
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+        let l = (max + min) / 2.0;
+
+        Hsl {
+            h: hue(r, g, b, max, delta),
+            s: if delta == 0.0 { 0.0 } else { delta / (1.0 - (2.0 * l - 1.0).abs()) },
+            l,
+        }
+    }
+
+    pub fn from_hsl(hsl: Hsl) -> Color {
+        let c = (1.0 - (2.0 * hsl.l - 1.0).abs()) * hsl.s;
+        Color::from_chroma(hsl.h, c, hsl.l - c / 2.0)
+    }
+
+    /// Channels as `0.0..=1.0`.
+    pub fn unit(&self) -> (f64, f64, f64) {
+        (self.r as f64 / 255.0, self.g as f64 / 255.0, self.b as f64 / 255.0)
+    }
+
+    /// A color from `0.0..=1.0` channels, clamping anything outside.
+    pub fn from_unit(r: f64, g: f64, b: f64) -> Color {
+        let c = |v: f64| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Color::rgb(c(r), c(g), c(b))
+    }
+
+    /// Channels in linear light, undoing the sRGB transfer curve.
+    pub fn to_linear(&self) -> (f64, f64, f64) {
+        let (r, g, b) = self.unit();
+        (srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b))
+    }
+
+    /// A color from linear-light channels, clamping anything outside `0.0..=1.0`.
+    pub fn from_linear(r: f64, g: f64, b: f64) -> Color {
+        Color::from_unit(linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b))
+    }
+
+    fn from_chroma(h: f64, c: f64, m: f64) -> Color {
+        let h = h.rem_euclid(360.0) / 60.0;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+
+        Color::from_unit(r + m, g + m, b + m)
+    }
+}
+
+fn srgb_to_linear(v: f64) -> f64 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(v: f64) -> f64 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn hue(r: f64, g: f64, b: f64, max: f64, delta: f64) -> f64 {
+    if delta == 0.0 {
+        0.0
+    } else if max == r {
+        (60.0 * ((g - b) / delta)).rem_euclid(360.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    }
+}
+
+impl From<(u8, u8, u8)> for Color {
+    fn from((r, g, b): (u8, u8, u8)) -> Self {
+        Color::rgb(r, g, b)
+    }
+}
+
+impl From<Hsv> for Color {
+    fn from(value: Hsv) -> Self {
+        Color::from_hsv(value)
+    }
+}
+
+impl From<Hsl> for Color {
+    fn from(value: Hsl) -> Self {
+        Color::from_hsl(value)
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseColorError {
+    input: String,
+}
+
+impl ParseColorError {
+    fn new(input: &str) -> ParseColorError {
+        ParseColorError {
+            input: input.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Not a color: {:?}", self.input)
+    }
+}
+
+impl Error for ParseColorError {}
+
+/// Splits `name(a, b, c)` into its three arguments.
+fn function_args<'a>(s: &'a str, name: &str) -> Option<[&'a str; 3]> {
+    let inner = s.strip_prefix(name)?.trim().strip_prefix('(')?.strip_suffix(')')?;
+    let args: Vec<&str> = inner.split(',').map(str::trim).collect();
+
+    args.try_into().ok()
+}
+
+/// A number, or a percentage divided by 100.
+fn number(arg: &str) -> Option<f64> {
+    match arg.strip_suffix('%') {
+        Some(pct) => pct.trim().parse::<f64>().ok().map(|v| v / 100.0),
+        None => arg.parse().ok(),
+    }
+}
+
+/// A saturation/value/lightness in percent, `0..=100` with or without the `%`, as CSS has it.
+fn fraction(arg: &str) -> Option<f64> {
+    arg.strip_suffix('%').unwrap_or(arg).trim().parse::<f64>().ok().map(|v| v / 100.0)
+}
+
+impl FromStr for Color {
+    type Err = ParseColorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.trim().to_lowercase();
+        let err = || ParseColorError::new(s);
+
+        if lower.starts_with('#') {
+            return Color::from_hex(&lower);
+        }
+        if let Some([r, g, b]) = function_args(&lower, "rgb") {
+            let channel = |a: &str| -> Option<u8> {
+                match a.strip_suffix('%') {
+                    Some(_) => number(a).map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8),
+                    None => a.parse().ok(),
+                }
+            };
+            return match (channel(r), channel(g), channel(b)) {
+                (Some(r), Some(g), Some(b)) => Ok(Color::rgb(r, g, b)),
+                _ => Err(err()),
+            };
+        }
+        if let Some([h, a, b]) = function_args(&lower, "hsv") {
+            return match (number(h), fraction(a), fraction(b)) {
+                (Some(h), Some(s), Some(v)) => Ok(Color::from_hsv(Hsv { h, s, v })),
+                _ => Err(err()),
+            };
+        }
+        if let Some([h, a, b]) = function_args(&lower, "hsl") {
+            return match (number(h), fraction(a), fraction(b)) {
+                (Some(h), Some(s), Some(l)) => Ok(Color::from_hsl(Hsl { h, s, l })),
+                _ => Err(err()),
+            };
+        }
+
+        Color::named(&lower).ok_or_else(err)
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+    {
+        serializer.serialize_str(self.to_hex().as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("#ff8800", Color::rgb(255, 136, 0))]
+    #[case("#F80", Color::rgb(255, 136, 0))]
+    #[case("rgb(255, 136, 0)", Color::rgb(255, 136, 0))]
+    #[case("rgb(100%, 0%, 50%)", Color::rgb(255, 0, 128))]
+    #[case("hsv(32, 100, 100)", Color::rgb(255, 136, 0))]
+    #[case("hsv(120, 100%, 50%)", Color::rgb(0, 128, 0))]
+    #[case("hsv(30, 100, 1)", Color::from_hsv(Hsv { h: 30.0, s: 1.0, v: 0.01 }))]
+    #[case("hsv(30, 1, 100)", Color::from_hsv(Hsv { h: 30.0, s: 0.01, v: 1.0 }))]
+    #[case("hsl(30, 100, 1)", Color::from_hsl(Hsl { h: 30.0, s: 1.0, l: 0.01 }))]
+    #[case("hsl(32, 100%, 50%)", Color::rgb(255, 136, 0))]
+    #[case("rebeccapurple", Color::rgb(102, 51, 153))]
+    #[case("Dark Orange", Color::rgb(255, 140, 0))]
+    #[case("warm white", Color::rgb(255, 167, 87))]
+    #[case("daylight", Color::rgb(255, 249, 251))]
+    fn test_parse(#[case] input: &str, #[case] expected: Color) {
+        assert_eq!(input.parse::<Color>().unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("#ff88")]
+    #[case("rgb(256, 0, 0)")]
+    #[case("hsv(30, 100)")]
+    #[case("not a color")]
+    #[case("ff8800")]
+    #[case("bad")]
+    fn test_parse_errors(#[case] input: &str) {
+        assert!(input.parse::<Color>().is_err());
+    }
+
+    #[rstest]
+    #[case(Color::rgb(255, 136, 0))]
+    #[case(Color::rgb(12, 200, 99))]
+    #[case(Color::rgb(128, 128, 128))]
+    #[case(Color::BLACK)]
+    #[case(Color::WHITE)]
+    fn test_hsv_hsl_round_trip(#[case] color: Color) {
+        assert_eq!(Color::from_hsv(color.to_hsv()), color);
+        assert_eq!(Color::from_hsl(color.to_hsl()), color);
+    }
+
+    #[rstest]
+    fn test_serde_as_hex() {
+        let c = Color::rgb(255, 136, 0);
+
+        assert_eq!(serde_json::to_string(&c).unwrap(), r##""#ff8800""##);
+        assert_eq!(serde_json::from_str::<Color>(r#""hsv(32,100,100)""#).unwrap(), c);
+    }
+}
@@ -0,0 +1,156 @@
+use std::path::Path;
+
+use image::imageops::FilterType;
+
+use crate::color::{Color, Oklab};
+
+/// Largest side images are shrunk to before their pixels are clustered.
+const SAMPLE_SIZE: u32 = 128;
+const KMEANS_ROUNDS: usize = 8;
+
+fn distance(a: &Oklab, b: &Oklab) -> f64 {
+    (a.l - b.l).powi(2) + (a.a - b.a).powi(2) + (a.b - b.b).powi(2)
+}
+
+fn mean(points: &[Oklab]) -> Oklab {
+    let n = points.len().max(1) as f64;
+    let sum = points.iter().fold(Oklab::default(), |s, p| Oklab { l: s.l + p.l, a: s.a + p.a, b: s.b + p.b });
+
+    Oklab { l: sum.l / n, a: sum.a / n, b: sum.b / n }
+}
+
+fn channel(p: &Oklab, c: usize) -> f64 {
+    match c {
+        0 => p.l,
+        1 => p.a,
+        _ => p.b,
+    }
+}
+
+/// Splits `points` into up to `n` boxes, each time cutting the box with the widest spread at
+/// the median of that channel.
+fn median_cut(points: Vec<Oklab>, n: usize) -> Vec<Vec<Oklab>> {
+    let mut boxes = vec![points];
+    while boxes.len() < n {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| {
+                let spread = |c: usize| {
+                    let values = b.iter().map(|p| channel(p, c));
+                    values.clone().fold(f64::MIN, f64::max) - values.fold(f64::MAX, f64::min)
+                };
+                let c = (0..3).max_by(|x, y| spread(*x).total_cmp(&spread(*y))).unwrap();
+                (i, c, spread(c))
+            })
+            .max_by(|x, y| x.2.total_cmp(&y.2));
+
+        let (i, c) = match widest {
+            Some((i, c, spread)) if spread > 0.0 => (i, c),
+            _ => break,
+        };
+        let mut b = boxes.swap_remove(i);
+        b.sort_by(|x, y| channel(x, c).total_cmp(&channel(y, c)));
+        let upper = b.split_off(b.len() / 2);
+        boxes.push(b);
+        boxes.push(upper);
+    }
+
+    boxes
+}
+
+/// Up to `n` colors that stand for `pixels`, most common first.
+///
+/// The pixels are clustered in Oklab so the split follows what the eye sees: median cut for a
+/// starting point, then a few rounds of k-means. Fewer colors come back if the pixels don't
+/// have `n` distinct ones.
+pub fn extract_palette(pixels: &[Color], n: usize) -> Vec<Color> {
+    if pixels.is_empty() || n == 0 {
+        return vec![];
+    }
+
+    let points: Vec<Oklab> = pixels.iter().map(|c| c.to_oklab()).collect();
+    let mut centres: Vec<Oklab> = median_cut(points.clone(), n).iter().map(|b| mean(b)).collect();
+
+    let mut clusters: Vec<Vec<Oklab>> = vec![];
+    for _ in 0..KMEANS_ROUNDS {
+        clusters = vec![vec![]; centres.len()];
+        for p in points.iter() {
+            let nearest = (0..centres.len())
+                .min_by(|x, y| distance(p, &centres[*x]).total_cmp(&distance(p, &centres[*y])))
+                .unwrap();
+            clusters[nearest].push(*p);
+        }
+        clusters.retain(|c| !c.is_empty());
+        centres = clusters.iter().map(|c| mean(c)).collect();
+    }
+
+    let mut ranked: Vec<(usize, Oklab)> = clusters.iter().map(|c| c.len()).zip(centres).collect();
+    ranked.sort_by_key(|r| std::cmp::Reverse(r.0));
+
+    ranked.into_iter().map(|(_, c)| Color::from_oklab(c)).collect()
+}
+
+fn image_pixels(image: image::DynamicImage) -> Vec<Color> {
+    let image = if image.width() > SAMPLE_SIZE || image.height() > SAMPLE_SIZE {
+        image.resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+    } else {
+        image
+    };
+
+    image.to_rgb8().pixels().map(|p| Color::rgb(p[0], p[1], p[2])).collect()
+}
+
+/// [`extract_palette`] of a PNG or JPEG file.
+pub fn image_palette(path: impl AsRef<Path>, n: usize) -> anyhow::Result<Vec<Color>> {
+    Ok(extract_palette(&image_pixels(image::open(path)?), n))
+}
+
+/// [`extract_palette`] of an encoded PNG or JPEG.
+pub fn image_palette_from_bytes(bytes: &[u8], n: usize) -> anyhow::Result<Vec<Color>> {
+    Ok(extract_palette(&image_pixels(image::load_from_memory(bytes)?), n))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageOutputFormat, Rgb, RgbImage};
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    fn test_picks_dominant_colors_in_order() {
+        let mut pixels = vec![Color::rgb(200, 30, 30); 60];
+        pixels.extend(vec![Color::rgb(20, 40, 220); 30]);
+        pixels.extend(vec![Color::rgb(250, 250, 240); 10]);
+
+        assert_eq!(
+            extract_palette(&pixels, 3),
+            vec![Color::rgb(200, 30, 30), Color::rgb(20, 40, 220), Color::rgb(250, 250, 240)]
+        );
+    }
+
+    #[rstest]
+    fn test_fewer_colors_than_asked() {
+        assert_eq!(extract_palette(&[Color::WHITE; 5], 4), vec![Color::WHITE]);
+        assert!(extract_palette(&[], 4).is_empty());
+    }
+
+    #[rstest]
+    fn test_png() {
+        let image = RgbImage::from_fn(300, 200, |x, _| if x < 200 { Rgb([255, 140, 0]) } else { Rgb([0, 90, 40]) });
+        let mut png = vec![];
+        image.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png).unwrap();
+
+        let palette = image_palette_from_bytes(&png, 2).unwrap();
+
+        assert_eq!(palette.len(), 2);
+        // resampling blends a few pixels along the edge, so allow a little slack
+        let close = |a: Color, b: Color| (a.r as i32 - b.r as i32).abs() + (a.g as i32 - b.g as i32).abs() + (a.b as i32 - b.b as i32).abs() < 12;
+        assert!(close(palette[0], Color::rgb(255, 140, 0)), "{:?}", palette);
+        assert!(close(palette[1], Color::rgb(0, 90, 40)), "{:?}", palette);
+    }
+}
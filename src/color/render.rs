@@ -0,0 +1,243 @@
+use crate::bulb::response::GetPilotResult;
+use crate::bulb::SetPilotParams;
+use crate::color::Color;
+
+/// Color temperature of the warm white LEDs and of the scene fallback.
+pub(crate) const WARM_KELVIN: u32 = 2700;
+/// Color temperature of the cool white LEDs.
+pub(crate) const COOL_KELVIN: u32 = 6500;
+
+/// What a bulb is (or will be) showing, whichever way it was described.
+pub trait PilotState {
+    fn is_on(&self) -> bool;
+    fn dimming(&self) -> Option<u32>;
+    fn temp(&self) -> Option<u32>;
+    fn rgb(&self) -> Option<Color>;
+    /// Cool and warm white channels.
+    fn cw(&self) -> (Option<u32>, Option<u32>);
+    fn scene_id(&self) -> Option<u32>;
+
+    /// Approximate sRGB for drawing this state on a screen; see [`render`].
+    fn display_color(&self) -> Color
+    where
+        Self: Sized,
+    {
+        render(self)
+    }
+}
+
+impl PilotState for GetPilotResult {
+    fn is_on(&self) -> bool {
+        self.state
+    }
+
+    fn dimming(&self) -> Option<u32> {
+        self.dimming
+    }
+
+    fn temp(&self) -> Option<u32> {
+        self.temp
+    }
+
+    fn rgb(&self) -> Option<Color> {
+        self.color()
+    }
+
+    fn cw(&self) -> (Option<u32>, Option<u32>) {
+        (self.c, self.w)
+    }
+
+    fn scene_id(&self) -> Option<u32> {
+        self.scene_id.filter(|s| *s != 0)
+    }
+}
+
+/// A command rendered as the state it asks for; a missing `state` counts as on, since any
+/// other setPilot switches the bulb on.
+impl PilotState for SetPilotParams {
+    fn is_on(&self) -> bool {
+        self.state.unwrap_or(true)
+    }
+
+    fn dimming(&self) -> Option<u32> {
+        self.dimming
+    }
+
+    fn temp(&self) -> Option<u32> {
+        self.temp
+    }
+
+    fn rgb(&self) -> Option<Color> {
+        match (self.r, self.g, self.b) {
+            (None, None, None) => None,
+            (r, g, b) => Some(Color::rgb(
+                r.unwrap_or(0).min(255) as u8,
+                g.unwrap_or(0).min(255) as u8,
+                b.unwrap_or(0).min(255) as u8,
+            )),
+        }
+    }
+
+    fn cw(&self) -> (Option<u32>, Option<u32>) {
+        (self.c, self.w)
+    }
+
+    fn scene_id(&self) -> Option<u32> {
+        self.scene_id
+    }
+}
+
+/// Approximate sRGB of a black body at `kelvin`, after Tanner Helland's fit to the CIE data.
+/// Good to a few percent between 1000K and 40000K, which covers every bulb.
+pub fn kelvin_to_rgb(kelvin: u32) -> Color {
+    let t = kelvin.clamp(1000, 40000) as f64 / 100.0;
+
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.698727446 * (t - 60.0).powf(-0.1332047592)
+    };
+    let g = if t <= 66.0 {
+        99.4708025861 * t.ln() - 161.1195681661
+    } else {
+        288.1221695283 * (t - 60.0).powf(-0.0755148492)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.5177312231 * (t - 10.0).ln() - 305.0447927307
+    };
+
+    Color::from_unit(r / 255.0, g / 255.0, b / 255.0)
+}
+
+/// Rough look of the built-in scenes, for swatches only; dynamic scenes get their dominant hue.
+fn scene_color(scene_id: u32) -> Color {
+    match scene_id {
+        1 => Color::rgb(0, 90, 200),      // Ocean
+        2 => Color::rgb(200, 40, 90),     // Romance
+        3 => Color::rgb(255, 100, 30),    // Sunset
+        4 => Color::rgb(200, 0, 200),     // Party
+        5 => Color::rgb(255, 90, 10),     // Fireplace
+        6 => Color::rgb(255, 140, 60),    // Cozy
+        7 => Color::rgb(40, 160, 60),     // Forest
+        8 => Color::rgb(200, 170, 220),   // Pastel colors
+        9 => kelvin_to_rgb(3200),         // Wake up
+        10 => kelvin_to_rgb(2200),        // Bedtime
+        11 => kelvin_to_rgb(2700),        // Warm white
+        12 => kelvin_to_rgb(5000),        // Daylight
+        13 => kelvin_to_rgb(6500),        // Cool white
+        14 => Color::rgb(255, 120, 40),   // Night light
+        15 => kelvin_to_rgb(5500),        // Focus
+        16 => kelvin_to_rgb(2900),        // Relax
+        17 => kelvin_to_rgb(4000),        // True colors
+        18 => Color::rgb(60, 80, 200),    // TV time
+        19 => Color::rgb(200, 60, 220),   // Plantgrowth
+        20 => Color::rgb(120, 220, 120),  // Spring
+        21 => Color::rgb(255, 200, 60),   // Summer
+        22 => Color::rgb(230, 110, 30),   // Fall
+        23 => Color::rgb(0, 60, 160),     // Deep dive
+        24 => Color::rgb(30, 170, 60),    // Jungle
+        25 => Color::rgb(120, 230, 90),   // Mojito
+        26 => Color::rgb(230, 0, 160),    // Club
+        27 => Color::rgb(220, 30, 30),    // Christmas
+        28 => Color::rgb(255, 100, 0),    // Halloween
+        29 => kelvin_to_rgb(2000),        // Candlelight
+        30 => kelvin_to_rgb(3000),        // Golden white
+        31 => Color::rgb(180, 120, 255),  // Pulse
+        32 => Color::rgb(200, 120, 40),   // Steampunk
+        1000 => Color::rgb(160, 80, 255), // Rhythm
+        _ => kelvin_to_rgb(WARM_KELVIN),
+    }
+}
+
+/// Approximate sRGB for whatever `pilot` describes: a scene, RGB plus any white channels, a
+/// color temperature, or warm white when nothing else is known; then dimmed in linear light
+/// by `dimming`, and black when off.
+pub fn render(pilot: &impl PilotState) -> Color {
+    if !pilot.is_on() {
+        return Color::BLACK;
+    }
+
+    let base = if let Some(scene_id) = pilot.scene_id() {
+        scene_color(scene_id)
+    } else {
+        let (c, w) = pilot.cw();
+        match (pilot.rgb(), c, w, pilot.temp()) {
+            (None, None, None, Some(temp)) => kelvin_to_rgb(temp),
+            (None, None, None, None) => kelvin_to_rgb(WARM_KELVIN),
+            (rgb, c, w, _) => {
+                let (mut r, mut g, mut b) = rgb.unwrap_or(Color::BLACK).to_linear();
+                for (level, kelvin) in [(c, COOL_KELVIN), (w, WARM_KELVIN)] {
+                    let share = level.unwrap_or(0).min(255) as f64 / 255.0;
+                    let (wr, wg, wb) = kelvin_to_rgb(kelvin).to_linear();
+                    r += wr * share;
+                    g += wg * share;
+                    b += wb * share;
+                }
+                Color::from_linear(r, g, b)
+            }
+        }
+    };
+
+    let level = pilot.dimming().unwrap_or(100).min(100) as f64 / 100.0;
+    let (r, g, b) = base.to_linear();
+    Color::from_linear(r * level, g * level, b * level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(1900, Color::rgb(255, 132, 0))]
+    #[case(2700, Color::rgb(255, 167, 87))]
+    #[case(6600, Color::rgb(255, 255, 255))]
+    #[case(10000, Color::rgb(202, 218, 255))]
+    fn test_kelvin_to_rgb(#[case] kelvin: u32, #[case] expected: Color) {
+        assert_eq!(kelvin_to_rgb(kelvin), expected);
+    }
+
+    #[rstest]
+    fn test_render_off_is_black() {
+        let p = GetPilotResult { state: false, temp: Some(4000), ..Default::default() };
+
+        assert_eq!(render(&p), Color::BLACK);
+    }
+
+    #[rstest]
+    fn test_render_temp_and_dimming() {
+        let full = GetPilotResult { state: true, temp: Some(2700), dimming: Some(100), ..Default::default() };
+        let half = GetPilotResult { state: true, temp: Some(2700), dimming: Some(50), ..Default::default() };
+
+        assert_eq!(full.display_color(), kelvin_to_rgb(2700));
+        let dimmed = half.display_color();
+        assert!(dimmed.r < 255 && dimmed.r > 128, "{:?}", dimmed);
+    }
+
+    #[rstest]
+    fn test_render_rgb_with_warm_white() {
+        let p = GetPilotResult { state: true, r: Some(0), g: Some(0), b: Some(255), w: Some(255), ..Default::default() };
+
+        let c = p.display_color();
+        assert_eq!(c.r, 255);
+        assert_eq!(c.b, 255);
+    }
+
+    #[rstest]
+    fn test_render_set_pilot_params() {
+        let p = SetPilotParams { r: Some(255), g: Some(136), b: Some(0), ..Default::default() };
+
+        assert_eq!(p.display_color(), Color::rgb(255, 136, 0));
+    }
+
+    #[rstest]
+    fn test_render_scene() {
+        let p = GetPilotResult { state: true, scene_id: Some(27), dimming: Some(100), ..Default::default() };
+
+        assert_eq!(p.display_color(), Color::rgb(220, 30, 30));
+    }
+}
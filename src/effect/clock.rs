@@ -0,0 +1,96 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::time::Instant;
+
+/// Where animations get their time from. Times are offsets from the clock's own epoch.
+#[async_trait]
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> Duration;
+    /// Waits until [`Clock::now`] reaches `deadline`; returns straight away if it already has.
+    async fn sleep_until(&self, deadline: Duration);
+}
+
+/// Wall-clock time through tokio's timer.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    epoch: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock { epoch: Instant::now() }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new()
+    }
+}
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.epoch.elapsed()
+    }
+
+    async fn sleep_until(&self, deadline: Duration) {
+        tokio::time::sleep_until(self.epoch + deadline).await
+    }
+}
+
+/// A clock that only moves when something sleeps on it or calls [`VirtualClock::advance`],
+/// so an animation runs as fast as it can and produces the same frames every time.
+#[derive(Debug, Default)]
+pub struct VirtualClock {
+    now: Mutex<Duration>,
+}
+
+impl VirtualClock {
+    pub fn new() -> VirtualClock {
+        VirtualClock::default()
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+#[async_trait]
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    async fn sleep_until(&self, deadline: Duration) {
+        {
+            let mut now = self.now.lock().unwrap();
+            if *now < deadline {
+                *now = deadline;
+            }
+        }
+        tokio::task::yield_now().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_virtual_clock_jumps_to_deadline() {
+        let clock = VirtualClock::new();
+        clock.advance(Duration::from_millis(30));
+
+        clock.sleep_until(Duration::from_millis(100)).await;
+        assert_eq!(clock.now(), Duration::from_millis(100));
+
+        clock.sleep_until(Duration::from_millis(50)).await;
+        assert_eq!(clock.now(), Duration::from_millis(100));
+    }
+}
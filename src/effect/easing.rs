@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+
+/// How progress through a transition is paced.
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    /// Starts slow and speeds up.
+    EaseIn,
+    /// Starts fast and settles gently.
+    EaseOut,
+    /// Slow at both ends.
+    EaseInOut,
+}
+
+impl Easing {
+    /// Eased progress for `t` in `0.0..=1.0`; values outside are clamped.
+    pub fn apply(&self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(Easing::Linear)]
+    #[case(Easing::EaseIn)]
+    #[case(Easing::EaseOut)]
+    #[case(Easing::EaseInOut)]
+    fn test_ends_are_fixed(#[case] easing: Easing) {
+        assert_eq!(easing.apply(0.0), 0.0);
+        assert_eq!(easing.apply(1.0), 1.0);
+        assert_eq!(easing.apply(2.0), 1.0);
+    }
+
+    #[rstest]
+    #[case(Easing::Linear, 0.5)]
+    #[case(Easing::EaseIn, 0.125)]
+    #[case(Easing::EaseOut, 0.875)]
+    #[case(Easing::EaseInOut, 0.5)]
+    fn test_midpoint(#[case] easing: Easing, #[case] expected: f64) {
+        assert!((easing.apply(0.5) - expected).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,406 @@
+use std::f64::consts::TAU;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::bulb::{Bulb, Delivery, DimmingCurve, SetPilot};
+use crate::color::{Color, Hsv};
+use crate::effect::{tick, Clock, Outcome, Status};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EffectKind {
+    /// Flickers unevenly between the first two colors.
+    Candle,
+    /// Slow swells in brightness, moving on to the next color each breath.
+    Breathe,
+    /// Hard flashes, cycling through the colors.
+    Strobe,
+    /// Runs through the hues, spread across the members so a group shows the whole wheel.
+    Rainbow,
+    /// Double flashes alternating between the first two colors, odd members out of phase.
+    Police,
+    /// A dim sky in the second color with occasional strikes in the first, on every member at
+    /// once.
+    Lightning,
+}
+
+impl EffectKind {
+    fn default_colors(&self) -> Vec<Color> {
+        match self {
+            EffectKind::Candle => vec![Color::from_u32(0xff8b27), Color::from_u32(0xe25822)],
+            EffectKind::Breathe | EffectKind::Strobe => vec![Color::WHITE],
+            EffectKind::Rainbow => vec![],
+            EffectKind::Police => vec![Color::rgb(255, 0, 0), Color::rgb(0, 0, 255)],
+            EffectKind::Lightning => vec![Color::rgb(200, 210, 255), Color::rgb(10, 10, 60)],
+        }
+    }
+}
+
+/// What one member should show at one moment; a `level` of zero means off.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Look {
+    pub color: Color,
+    pub level: f64,
+}
+
+/// A software effect and its parameters.
+///
+/// `speed` scales how fast it runs, with `1.0` the natural pace, and `intensity` in
+/// `0.0..=1.0` is how strong it is: flicker depth for a candle, how far a breath dims,
+/// brightness of flashes, saturation of the rainbow and how often lightning strikes. Anything
+/// random comes from `seed`, so a given effect plays out the same way every time.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Effect {
+    pub kind: EffectKind,
+    pub colors: Vec<Color>,
+    pub speed: f64,
+    pub intensity: f64,
+    #[serde(default)]
+    pub seed: u64,
+    /// Stop after this long; `None` runs until cancelled or superseded.
+    #[serde(default)]
+    pub duration: Option<Duration>,
+}
+
+impl Effect {
+    pub fn new(kind: EffectKind) -> Effect {
+        Effect {
+            kind,
+            colors: kind.default_colors(),
+            speed: 1.0,
+            intensity: 1.0,
+            seed: 0,
+            duration: None,
+        }
+    }
+
+    pub fn candle() -> Effect {
+        Effect::new(EffectKind::Candle)
+    }
+
+    pub fn breathe() -> Effect {
+        Effect::new(EffectKind::Breathe)
+    }
+
+    pub fn strobe() -> Effect {
+        Effect::new(EffectKind::Strobe)
+    }
+
+    pub fn rainbow() -> Effect {
+        Effect::new(EffectKind::Rainbow)
+    }
+
+    pub fn police() -> Effect {
+        Effect::new(EffectKind::Police)
+    }
+
+    pub fn lightning() -> Effect {
+        Effect::new(EffectKind::Lightning)
+    }
+
+    pub fn colors(&mut self, colors: Vec<Color>) -> &mut Self {
+        self.colors = colors;
+        self
+    }
+
+    pub fn speed(&mut self, speed: f64) -> &mut Self {
+        self.speed = speed.max(0.0);
+        self
+    }
+
+    pub fn intensity(&mut self, intensity: f64) -> &mut Self {
+        self.intensity = intensity.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn duration(&mut self, duration: Option<Duration>) -> &mut Self {
+        self.duration = duration;
+        self
+    }
+
+    /// The frame member `member` is sent at `at`, with brightness along `curve`.
+    pub(crate) fn pilot(&self, at: Duration, member: usize, members: usize, curve: &DimmingCurve) -> SetPilot {
+        let look = self.look(at, member, members);
+        let mut p = SetPilot::default().level(look.level, curve).to_owned();
+        if look.level > 0.0 {
+            p.color(look.color);
+        }
+
+        p
+    }
+
+    fn color(&self, i: usize) -> Color {
+        match self.colors.len() {
+            0 => Color::WHITE,
+            n => self.colors[i % n],
+        }
+    }
+
+    /// What `member` of `members` shows `at` into the effect.
+    pub fn look(&self, at: Duration, member: usize, members: usize) -> Look {
+        let t = at.as_secs_f64() * self.speed;
+        let on = self.intensity.max(0.01);
+
+        match self.kind {
+            EffectKind::Candle => {
+                let n = smooth_noise(self.seed, member as u64, t * 6.0);
+                Look {
+                    color: self.color(0).mix(self.color(1), n),
+                    level: 1.0 - self.intensity * 0.7 * n,
+                }
+            }
+            EffectKind::Breathe => {
+                let phase = t / 4.0;
+                let low = (1.0 - self.intensity).max(0.01);
+                Look {
+                    color: self.color(phase.floor() as usize),
+                    level: low + (1.0 - low) * (0.5 - 0.5 * (TAU * phase).cos()),
+                }
+            }
+            EffectKind::Strobe => {
+                let flashes = t * 5.0;
+                Look {
+                    color: self.color(flashes.floor() as usize),
+                    level: if flashes.fract() < 0.5 { on } else { 0.0 },
+                }
+            }
+            EffectKind::Rainbow => {
+                let offset = member as f64 / members.max(1) as f64;
+                let h = (t * 0.1 + offset).fract() * 360.0;
+                Look {
+                    color: Color::from_hsv(Hsv { h, s: self.intensity, v: 1.0 }),
+                    level: 1.0,
+                }
+            }
+            EffectKind::Police => {
+                let half = t * 2.0;
+                let side = (half.floor() as usize + member) % 2;
+                let p = half.fract();
+                let lit = p < 0.2 || (0.25..0.45).contains(&p);
+                Look {
+                    color: self.color(side),
+                    level: if lit { on } else { 0.0 },
+                }
+            }
+            EffectKind::Lightning => {
+                let sky = Look { color: self.color(1), level: 0.05 };
+                let slot = t.floor() as u64;
+                if noise(self.seed, 0, slot) >= self.intensity * 0.5 {
+                    return sky;
+                }
+
+                let since = t.fract() - noise(self.seed, 1, slot) * 0.5;
+                let flash = Look { color: self.color(0), level: 1.0 };
+                match since {
+                    s if (0.0..0.08).contains(&s) || (0.15..0.2).contains(&s) => flash,
+                    s if (0.2..0.4).contains(&s) => Look {
+                        color: self.color(0).mix(sky.color, (s - 0.2) / 0.2),
+                        level: 1.0 - (s - 0.2) / 0.2 * 0.95,
+                    },
+                    _ => sky,
+                }
+            }
+        }
+    }
+}
+
+/// A repeatable pseudo-random number in `0.0..1.0` for the given inputs.
+fn noise(seed: u64, a: u64, b: u64) -> f64 {
+    let mut z = seed ^ a.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ b.wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// [`noise`] eased between whole steps of `t`, so it wanders instead of jumping every frame.
+fn smooth_noise(seed: u64, a: u64, t: f64) -> f64 {
+    let step = t.floor();
+    let f = t - step;
+    let (from, to) = (noise(seed, a, step as u64), noise(seed, a, step as u64 + 1));
+
+    from + (to - from) * f * f * (3.0 - 2.0 * f)
+}
+
+/// Streams `effect` to every bulb until it's stopped or its duration is up.
+///
+/// Each bulb is sent the moment of the effect it will be showing when the packet lands: the
+/// time since the start, plus the time spent sending to the bulbs before it, plus its `lead`.
+pub(crate) async fn run(
+    bulbs: Vec<Bulb>,
+    leads: Vec<Duration>,
+    effect: Effect,
+    clock: Arc<dyn Clock>,
+    interval: Duration,
+    status: Arc<Status>,
+) -> Outcome {
+    let members = bulbs.len();
+    let frame_clock = clock.clone();
+
+    let (_, stopped) = tick(clock.as_ref(), interval, &status, bulbs, move |bulbs, elapsed| {
+        if effect.duration.is_some_and(|d| elapsed >= d) {
+            return false;
+        }
+
+        let frame_start = frame_clock.now();
+        for (i, b) in bulbs.iter_mut().enumerate() {
+            let at = elapsed + frame_clock.now().saturating_sub(frame_start) + leads[i];
+            let p = effect.pilot(at, i, members, b.dimming_curve());
+            if let Err(e) = b.send_pilot(p, Delivery::FireAndForget) {
+                error!("{} dropped a frame: {}", b.name, e);
+            }
+        }
+        true
+    })
+    .await;
+
+    stopped.unwrap_or(Outcome::Completed)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::effect::tests::Tap;
+    use crate::effect::{Engine, VirtualClock};
+    use crate::registry::{GraphStore, Group};
+    use crate::sim::VirtualBulb;
+    use surrealdb::sql::Id;
+
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[rstest]
+    fn test_candle_is_repeatable_and_bounded() {
+        let candle = Effect::candle().intensity(0.5).seed(7).to_owned();
+
+        for at in (0..100).map(|i| ms(i * 37)) {
+            let look = candle.look(at, 0, 1);
+            assert_eq!(look, candle.look(at, 0, 1));
+            assert!((0.65..=1.0).contains(&look.level), "{:?}", look);
+        }
+        assert_ne!(candle.look(ms(500), 0, 2), candle.look(ms(500), 1, 2));
+    }
+
+    #[rstest]
+    fn test_breathe_swells_and_changes_color() {
+        let breathe = Effect::breathe().colors(vec![Color::rgb(255, 0, 0), Color::rgb(0, 0, 255)]).to_owned();
+
+        assert!(breathe.look(ms(0), 0, 1).level < 0.02);
+        assert_eq!(breathe.look(ms(2000), 0, 1).level, 1.0);
+        assert_eq!(breathe.look(ms(2000), 0, 1).color, Color::rgb(255, 0, 0));
+        assert_eq!(breathe.look(ms(6000), 0, 1).color, Color::rgb(0, 0, 255));
+    }
+
+    #[rstest]
+    #[case(ms(0), 1.0)]
+    #[case(ms(150), 0.0)]
+    #[case(ms(250), 1.0)]
+    fn test_strobe(#[case] at: Duration, #[case] level: f64) {
+        assert_eq!(Effect::strobe().look(at, 0, 1).level, level);
+    }
+
+    #[rstest]
+    fn test_speed_scales_time() {
+        let fast = Effect::strobe().speed(2.0).to_owned();
+
+        assert_eq!(fast.look(ms(75), 0, 1), Effect::strobe().look(ms(150), 0, 1));
+    }
+
+    #[rstest]
+    fn test_rainbow_spreads_hues_across_members() {
+        let rainbow = Effect::rainbow();
+
+        assert_eq!(rainbow.look(ms(0), 0, 3).color, Color::rgb(255, 0, 0));
+        assert_eq!(rainbow.look(ms(0), 1, 3).color, Color::rgb(0, 255, 0));
+        assert_eq!(rainbow.look(ms(0), 2, 3).color, Color::rgb(0, 0, 255));
+    }
+
+    #[rstest]
+    fn test_police_members_alternate() {
+        let police = Effect::police();
+
+        assert_eq!(police.look(ms(50), 0, 2).color, Color::rgb(255, 0, 0));
+        assert_eq!(police.look(ms(50), 1, 2).color, Color::rgb(0, 0, 255));
+        assert_eq!(police.look(ms(110), 0, 2).level, 0.0);
+    }
+
+    #[rstest]
+    fn test_lightning_needs_intensity() {
+        let strikes = |intensity: f64| {
+            let lightning = Effect::lightning().intensity(intensity).seed(3).to_owned();
+            (0..600).filter(|i| lightning.look(ms(i * 50), 0, 1).level == 1.0).count()
+        };
+
+        assert_eq!(strikes(0.0), 0);
+        assert!(strikes(1.0) > 0);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_effect_runs_for_its_duration_on_a_group() {
+        let sims = VirtualBulb::many(3).unwrap();
+        let g = VirtualBulb::group(&sims, Id::from(30), "bar");
+        let engine = Engine::new(Arc::new(VirtualClock::new()));
+
+        let rainbow = Effect::rainbow().duration(Some(Duration::from_secs(3))).to_owned();
+        let outcome = engine.play(&g, &rainbow).join().await;
+
+        assert!(matches!(outcome, Outcome::Completed), "{:?}", outcome);
+        // frames are fire-and-forget, so give the sims a moment to take in the last ones
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(sims.iter().all(|s| s.state().state));
+        assert_ne!(sims[0].state().rgb, sims[1].state().rgb);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_endless_effect_until_cancelled() {
+        let sim = VirtualBulb::new().unwrap();
+        let engine = Engine::new(Arc::new(VirtualClock::new()));
+
+        let handle = engine.play(&sim.bulb("foo", 1), &Effect::candle());
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert!(!handle.is_finished());
+        engine.stop(&sim.bulb("foo", 1));
+
+        assert!(matches!(handle.join().await, Outcome::Cancelled));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_frames_lead_by_latency() {
+        let sims = VirtualBulb::many(2).unwrap();
+        let taps = [Arc::new(Tap::default()), Arc::new(Tap::default())];
+        let mut bulbs = vec![sims[0].bulb("bar", 0), sims[1].bulb("bar", 1)];
+        for (b, tap) in bulbs.iter_mut().zip(taps.iter()) {
+            b.set_transport(tap.clone());
+        }
+        let g = Group::new(Id::from(32), "bar".to_string(), bulbs.into_iter().map(|b| Box::new(b) as Box<dyn GraphStore>).collect());
+        let engine = Engine::new(Arc::new(VirtualClock::new()));
+        engine.set_latency(sims[1].ip().into(), ms(40));
+
+        let strobe = Effect::strobe().duration(Some(Duration::from_secs(1))).to_owned();
+        engine.play(&g, &strobe).join().await;
+
+        for (tap, lead) in taps.iter().zip([0, 40]) {
+            let lit: Vec<_> = tap.fired.lock().unwrap().iter().map(|p| p.params.state == Some(true)).collect();
+            let expected: Vec<_> = (0..20).map(|k| strobe.look(ms(k * 50 + lead), 0, 1).level > 0.0).collect();
+            assert_eq!(lit, expected);
+        }
+    }
+}
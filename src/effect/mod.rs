@@ -0,0 +1,517 @@
+//! Animation driven from the host: timed transitions between states, software effects, lights
+//! that react to music, slow routines such as a wake-up sunrise and light shows written as
+//! keyframe [`Timeline`]s. A [`Preview`] draws any of the effects or timelines without
+//! sending anything, as an image or in the terminal.
+//!
+//! An [`Engine`] runs each animation as a tokio task that streams fire-and-forget `setPilot`
+//! frames at its frame rate and finishes with one acknowledged frame carrying the exact target.
+//! Starting a new animation on a bulb supersedes whatever the engine was already running on
+//! it, and the returned [`Handle`] can cancel it early. Time comes from a [`Clock`], so with a
+//! [`VirtualClock`] the frames are reproducible and a test doesn't have to wait them out.
+//!
+//! Effects on several bulbs are kept in phase by sending each bulb its frame early by that
+//! bulb's one-way latency, as measured by [`Engine::calibrate`], so every bulb shows the same
+//! moment of the effect at the same time.
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+
+use crate::audio::Signal;
+use crate::bulb::response::ErrorResponse;
+use crate::bulb::Bulb;
+use crate::registry::Group;
+pub use clock::{Clock, SystemClock, VirtualClock};
+pub use easing::Easing;
+pub use library::{Effect, EffectKind, Look};
+pub use player::Player;
+pub use preview::Preview;
+pub use reactive::Reactive;
+pub use routine::Routine;
+pub use timeline::{Keyframe, Timeline, TimelineError, Track, TrackTarget};
+pub use transition::Transition;
+
+mod clock;
+mod easing;
+mod library;
+mod player;
+mod preview;
+mod reactive;
+mod routine;
+mod timeline;
+mod transition;
+
+const DEFAULT_FRAME_RATE: f64 = 20.0;
+/// The most commands a second a bulb is sent by anything that runs as fast as its input,
+/// beyond which the bulbs start dropping them.
+pub const MAX_COMMAND_RATE: f64 = 20.0;
+
+/// Anything an animation can run on.
+pub trait Target {
+    /// The bulbs to drive. They're copies, so the animation owns them while it runs.
+    fn bulbs(&self) -> Vec<Bulb>;
+}
+
+impl Target for Bulb {
+    fn bulbs(&self) -> Vec<Bulb> {
+        vec![self.clone()]
+    }
+}
+
+impl Target for Group {
+    fn bulbs(&self) -> Vec<Bulb> {
+        Group::bulbs(self).into_iter().cloned().collect()
+    }
+}
+
+/// How an animation ended.
+#[derive(Debug)]
+pub enum Outcome {
+    Completed,
+    /// Stopped through its [`Handle`] or [`Engine::stop`].
+    Cancelled,
+    /// Another animation was started on one of its bulbs.
+    Superseded,
+    /// One of its bulbs was switched off by something other than the engine.
+    Interrupted,
+    /// The final frame was refused or never answered.
+    Failed(ErrorResponse),
+}
+
+const RUNNING: u8 = 0;
+const CANCELLED: u8 = 1;
+const SUPERSEDED: u8 = 2;
+
+/// Shared between a running animation and everything that may stop it.
+#[derive(Debug, Default)]
+pub(crate) struct Status(AtomicU8);
+
+impl Status {
+    fn stop(&self, reason: u8) {
+        let _ = self.0.compare_exchange(RUNNING, reason, Ordering::SeqCst, Ordering::SeqCst);
+    }
+
+    /// Why the animation should stop, if it should.
+    pub(crate) fn stopped(&self) -> Option<Outcome> {
+        match self.0.load(Ordering::SeqCst) {
+            CANCELLED => Some(Outcome::Cancelled),
+            SUPERSEDED => Some(Outcome::Superseded),
+            _ => None,
+        }
+    }
+}
+
+/// Calls `frame` with `state` and the time since the start once per `interval` until it
+/// returns `false` or the animation is stopped, then hands `state` back with why it was
+/// stopped, if it was. Frames run on the blocking pool, since sending one means blocking
+/// socket calls. A frame that runs late skips ahead rather than replaying the ticks it missed.
+pub(crate) async fn tick<S, F>(
+    clock: &dyn Clock,
+    interval: Duration,
+    status: &Status,
+    mut state: S,
+    mut frame: F,
+) -> (S, Option<Outcome>)
+where
+    S: Send + 'static,
+    F: FnMut(&mut S, Duration) -> bool + Send + 'static,
+{
+    let start = clock.now();
+    loop {
+        if let Some(outcome) = status.stopped() {
+            return (state, Some(outcome));
+        }
+        let elapsed = clock.now().saturating_sub(start);
+        let more;
+        (state, frame, more) = tokio::task::spawn_blocking(move || {
+            let more = frame(&mut state, elapsed);
+            (state, frame, more)
+        })
+        .await
+        .unwrap();
+        if !more {
+            return (state, status.stopped());
+        }
+
+        let ticks = (elapsed.as_nanos() / interval.as_nanos()) as u32 + 1;
+        clock.sleep_until(start + interval * ticks).await;
+    }
+}
+
+type Active = Arc<Mutex<HashMap<IpAddr, Arc<Status>>>>;
+
+/// Lets go of an animation's bulbs when dropped, except those a newer animation has claimed.
+struct Release {
+    active: Active,
+    ips: Vec<IpAddr>,
+    status: Arc<Status>,
+}
+
+impl Drop for Release {
+    fn drop(&mut self) {
+        let mut active = self.active.lock().unwrap();
+        for ip in self.ips.iter() {
+            if active.get(ip).is_some_and(|s| Arc::ptr_eq(s, &self.status)) {
+                active.remove(ip);
+            }
+        }
+    }
+}
+
+/// A running animation.
+#[derive(Debug)]
+pub struct Handle {
+    status: Arc<Status>,
+    task: JoinHandle<Outcome>,
+}
+
+impl Handle {
+    /// Stops the animation after the frame it's on; the bulbs stay where they got to.
+    pub fn cancel(&self) {
+        self.status.stop(CANCELLED);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Waits for the animation to end.
+    pub async fn join(self) -> Outcome {
+        match self.task.await {
+            Ok(outcome) => outcome,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(_) => Outcome::Cancelled,
+        }
+    }
+}
+
+/// Runs animations and keeps track of which one owns each bulb.
+#[derive(Debug)]
+pub struct Engine {
+    clock: Arc<dyn Clock>,
+    frame_rate: f64,
+    active: Active,
+    latencies: Mutex<HashMap<IpAddr, Duration>>,
+}
+
+impl Engine {
+    pub fn new(clock: Arc<dyn Clock>) -> Engine {
+        Engine {
+            clock,
+            frame_rate: DEFAULT_FRAME_RATE,
+            active: Arc::new(Mutex::new(HashMap::new())),
+            latencies: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Frames per second sent to each bulb. The bulbs cope with about 20; much more than that
+    /// mostly gets dropped on a busy network.
+    pub fn set_frame_rate(&mut self, frame_rate: f64) -> &mut Self {
+        self.frame_rate = frame_rate.max(0.1);
+        self
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// The one-way delay effect frames to `ip` are sent ahead by; zero until measured or set.
+    pub fn latency(&self, ip: IpAddr) -> Duration {
+        self.latencies.lock().unwrap().get(&ip).copied().unwrap_or_default()
+    }
+
+    pub fn set_latency(&self, ip: IpAddr, latency: Duration) {
+        self.latencies.lock().unwrap().insert(ip, latency);
+    }
+
+    /// Times `samples` round trips to each of `target`'s bulbs and takes half the median as
+    /// its latency from now on. Bulbs that never answer keep what they had.
+    pub async fn calibrate(&self, target: &impl Target, samples: u32) -> Vec<(IpAddr, Result<Duration, ErrorResponse>)> {
+        let mut results = vec![];
+        for b in target.bulbs() {
+            let ip = b.ip();
+            let latency = tokio::task::spawn_blocking(move || b.round_trip(samples))
+                .await
+                .unwrap()
+                .map(|rtt| rtt / 2);
+
+            if let Ok(latency) = latency {
+                self.set_latency(ip, latency);
+            }
+            results.push((ip, latency));
+        }
+
+        results
+    }
+
+    pub(crate) fn frame_interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.frame_rate).max(Duration::from_millis(1))
+    }
+
+    /// Fades `target` from wherever it is now to `transition.to`. Must be called from within
+    /// a tokio runtime.
+    pub fn transition(&self, target: &impl Target, transition: &Transition) -> Handle {
+        let bulbs = target.bulbs();
+        let status = self.claim(&bulbs);
+
+        let task = self.spawn(&bulbs, &status, transition::run(
+            bulbs.clone(),
+            transition.clone(),
+            self.clock(),
+            self.frame_interval(),
+            status.clone(),
+        ));
+
+        Handle { status, task }
+    }
+
+    /// Plays `effect` on `target` until it's cancelled, superseded or its duration runs out.
+    /// Must be called from within a tokio runtime.
+    pub fn play(&self, target: &impl Target, effect: &Effect) -> Handle {
+        let bulbs = target.bulbs();
+        let status = self.claim(&bulbs);
+        let leads = bulbs.iter().map(|b| self.latency(b.ip())).collect();
+
+        let task = self.spawn(&bulbs, &status, library::run(
+            bulbs.clone(),
+            leads,
+            effect.clone(),
+            self.clock(),
+            self.frame_interval(),
+            status.clone(),
+        ));
+
+        Handle { status, task }
+    }
+
+    /// Runs `routine` on `target` at its own pace, after its delay. Must be called from within
+    /// a tokio runtime.
+    pub fn run_routine(&self, target: &impl Target, routine: &Routine) -> Handle {
+        let bulbs = target.bulbs();
+        let status = self.claim(&bulbs);
+
+        let task = self.spawn(
+            &bulbs,
+            &status,
+            routine::run(bulbs.clone(), routine.clone(), self.clock(), status.clone()),
+        );
+
+        Handle { status, task }
+    }
+
+    /// Drives `target` from `signal` until the audio runs out or the animation is stopped.
+    /// Frames go out no faster than [`MAX_COMMAND_RATE`], and not at all while a bulb's look
+    /// stays the same. Must be called from within a tokio runtime.
+    pub fn react(&self, target: &impl Target, signal: impl Signal + 'static, reactive: &Reactive) -> Handle {
+        let bulbs = target.bulbs();
+        let status = self.claim(&bulbs);
+        let leads = bulbs.iter().map(|b| self.latency(b.ip())).collect();
+        let interval = self.frame_interval().max(Duration::from_secs_f64(1.0 / MAX_COMMAND_RATE));
+
+        let task = self.spawn(&bulbs, &status, reactive::run(
+            bulbs.clone(),
+            leads,
+            Box::new(signal),
+            reactive.clone(),
+            self.clock(),
+            interval,
+            status.clone(),
+        ));
+
+        Handle { status, task }
+    }
+
+    /// Starts playing `timeline`, with `resolve` looking up the bulbs each track names. Fails
+    /// without touching any bulb if the timeline is invalid or a target can't be found. Must
+    /// be called from within a tokio runtime.
+    pub fn play_timeline(
+        &self,
+        timeline: &Timeline,
+        resolve: impl Fn(&TrackTarget) -> Option<Vec<Bulb>>,
+    ) -> Result<Player, TimelineError> {
+        let bulbs = timeline.members(resolve)?;
+        let status = self.claim(&bulbs.iter().map(|(b, _)| b.clone()).collect::<Vec<_>>());
+
+        Ok(player::start(self, bulbs, timeline.clone(), status))
+    }
+
+    /// Cancels whatever this engine is running on any of `target`'s bulbs.
+    pub fn stop(&self, target: &impl Target) {
+        let active = self.active.lock().unwrap();
+        for b in target.bulbs() {
+            if let Some(status) = active.get(&b.ip()) {
+                status.stop(CANCELLED);
+            }
+        }
+    }
+
+    /// Registers a new animation on `bulbs`, superseding the ones already running on them.
+    pub(crate) fn claim(&self, bulbs: &[Bulb]) -> Arc<Status> {
+        let status = Arc::new(Status::default());
+
+        let mut active = self.active.lock().unwrap();
+        for b in bulbs {
+            if let Some(previous) = active.insert(b.ip(), status.clone()) {
+                previous.stop(SUPERSEDED);
+            }
+        }
+
+        status
+    }
+
+    /// Runs `animation` as a task that gives up its claim on `bulbs` when it ends, however it
+    /// ends.
+    pub(crate) fn spawn(
+        &self,
+        bulbs: &[Bulb],
+        status: &Arc<Status>,
+        animation: impl Future<Output = Outcome> + Send + 'static,
+    ) -> JoinHandle<Outcome> {
+        let release = Release {
+            active: self.active.clone(),
+            ips: bulbs.iter().map(|b| b.ip()).collect(),
+            status: status.clone(),
+        };
+
+        tokio::spawn(async move {
+            let _release = release;
+            animation.await
+        })
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new(Arc::new(SystemClock::new()))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use rstest::rstest;
+
+    use crate::bulb::transport::{Datagram, Transport, Udp};
+    use crate::bulb::{SetPilot, SetPilotParams};
+    use crate::sim::{FaultProfile, Latency, VirtualBulb};
+
+    use super::*;
+
+    /// Forwards to UDP and remembers every frame fired.
+    #[derive(Debug, Default)]
+    pub(crate) struct Tap {
+        pub fired: Mutex<Vec<SetPilot>>,
+    }
+
+    impl Transport for Tap {
+        fn exchange(&self, target: IpAddr, message: &[u8]) -> anyhow::Result<Datagram> {
+            Udp.exchange(target, message)
+        }
+
+        fn fire(&self, target: IpAddr, message: &[u8]) -> anyhow::Result<()> {
+            self.fired.lock().unwrap().push(serde_json::from_slice(message)?);
+            Udp.fire(target, message)
+        }
+
+        fn poll(&self, target: IpAddr, message: &[u8]) -> anyhow::Result<Vec<Datagram>> {
+            Udp.poll(target, message)
+        }
+    }
+
+    fn engine() -> Engine {
+        Engine::new(Arc::new(VirtualClock::new()))
+    }
+
+    fn to_warm_half() -> Transition {
+        Transition::new(
+            SetPilotParams { state: Some(true), dimming: Some(50), temp: Some(2700), ..Default::default() },
+            Duration::from_secs(2),
+        )
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_transition_reaches_target() {
+        let sim = VirtualBulb::new().unwrap();
+        let b = sim.bulb("foo", 1);
+
+        let outcome = engine().transition(&b, &to_warm_half()).join().await;
+
+        assert!(matches!(outcome, Outcome::Completed), "{:?}", outcome);
+        let state = sim.state();
+        assert_eq!((state.state, state.dimming, state.temp), (true, 50, Some(2700)));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_new_transition_supersedes_running_one() {
+        let sim = VirtualBulb::new().unwrap();
+        let b = sim.bulb("foo", 1);
+        let engine = engine();
+
+        let first = engine.transition(&b, &to_warm_half());
+        let second = engine.transition(
+            &b,
+            &Transition::new(SetPilotParams { dimming: Some(80), ..Default::default() }, Duration::from_secs(1)),
+        );
+
+        assert!(matches!(first.join().await, Outcome::Superseded));
+        assert!(matches!(second.join().await, Outcome::Completed));
+        assert_eq!(sim.state().dimming, 80);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_finished_animations_let_go_of_their_bulbs() {
+        let sim = VirtualBulb::new().unwrap();
+        let b = sim.bulb("foo", 1);
+        let engine = engine();
+
+        let first = engine.transition(&b, &to_warm_half());
+        let second = engine.transition(&b, &to_warm_half());
+
+        // the superseded one leaves the newer claim alone
+        first.join().await;
+        assert_eq!(engine.active.lock().unwrap().len(), 1);
+        second.join().await;
+        assert!(engine.active.lock().unwrap().is_empty());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_cancel_leaves_bulb_short_of_target() {
+        let sim = VirtualBulb::new().unwrap();
+        let b = sim.bulb("foo", 1);
+        let engine = engine();
+
+        let handle = engine.transition(&b, &to_warm_half());
+        handle.cancel();
+
+        assert!(matches!(handle.join().await, Outcome::Cancelled));
+        assert_eq!(sim.state().dimming, 100);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_calibrate_halves_round_trip() {
+        let near = VirtualBulb::new().unwrap();
+        let far = VirtualBulb::new().unwrap();
+        far.set_faults(FaultProfile { latency: Latency::Fixed(Duration::from_millis(80)), ..Default::default() });
+        let engine = engine();
+
+        let g = Group::new(
+            surrealdb::sql::Id::from(31),
+            "bar".to_string(),
+            vec![Box::new(near.bulb("bar", 0)), Box::new(far.bulb("bar", 1))],
+        );
+        let results = engine.calibrate(&g, 3).await;
+
+        assert!(results.iter().all(|(_, r)| r.is_ok()));
+        assert!(engine.latency(near.bulb("bar", 0).ip()) < Duration::from_millis(20));
+        let far_latency = engine.latency(far.bulb("bar", 1).ip());
+        assert!(far_latency >= Duration::from_millis(40) && far_latency < Duration::from_millis(60), "{:?}", far_latency);
+    }
+}
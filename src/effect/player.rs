@@ -0,0 +1,247 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::error;
+
+use crate::bulb::{Bulb, Delivery, SetPilotParams};
+use crate::effect::timeline::Timeline;
+use crate::effect::transition::{finish, pilot};
+use crate::effect::{tick, Clock, Engine, Handle, Outcome, Status};
+
+#[derive(Debug)]
+struct Position {
+    /// Clock time the timeline's start lines up with while playing.
+    origin: Duration,
+    paused: Option<Duration>,
+    /// Moved while paused and not yet shown.
+    moved: bool,
+}
+
+/// Where a timeline is, shared between its [`Player`] and the task playing it.
+#[derive(Debug)]
+struct Playhead {
+    clock: Arc<dyn Clock>,
+    position: Mutex<Position>,
+}
+
+impl Playhead {
+    fn new(clock: Arc<dyn Clock>) -> Playhead {
+        let origin = clock.now();
+        Playhead {
+            clock,
+            position: Mutex::new(Position { origin, paused: None, moved: false }),
+        }
+    }
+
+    fn at(&self, p: &Position) -> Duration {
+        p.paused.unwrap_or_else(|| self.clock.now().saturating_sub(p.origin))
+    }
+
+    /// The position to show next, or `None` while paused with nothing new to show.
+    fn frame(&self) -> Option<Duration> {
+        let mut p = self.position.lock().unwrap();
+        if p.paused.is_some() && !std::mem::take(&mut p.moved) {
+            return None;
+        }
+
+        Some(self.at(&p))
+    }
+}
+
+/// A [`Timeline`] playing on its bulbs, started with [`super::Engine::play_timeline`].
+#[derive(Debug)]
+pub struct Player {
+    handle: Handle,
+    playhead: Arc<Playhead>,
+}
+
+impl Player {
+    /// Carries on from where it was paused.
+    pub fn play(&self) {
+        let mut p = self.playhead.position.lock().unwrap();
+        if let Some(at) = p.paused.take() {
+            p.origin = self.playhead.clock.now().saturating_sub(at);
+        }
+    }
+
+    /// Holds the bulbs on the current frame.
+    pub fn pause(&self) {
+        let mut p = self.playhead.position.lock().unwrap();
+        if p.paused.is_none() {
+            p.paused = Some(self.playhead.at(&p));
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.playhead.position.lock().unwrap().paused.is_some()
+    }
+
+    /// Jumps to `to` from the start of the first pass. While paused the bulbs are sent that
+    /// one frame.
+    pub fn seek(&self, to: Duration) {
+        let mut p = self.playhead.position.lock().unwrap();
+        match p.paused {
+            Some(_) => {
+                p.paused = Some(to);
+                p.moved = true;
+            }
+            None => p.origin = self.playhead.clock.now().saturating_sub(to),
+        }
+    }
+
+    /// Time from the start of the first pass.
+    pub fn position(&self) -> Duration {
+        let p = self.playhead.position.lock().unwrap();
+        self.playhead.at(&p)
+    }
+
+    /// Ends playback after the frame it's on; the bulbs stay where they got to.
+    pub fn stop(&self) {
+        self.handle.cancel()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Waits for the last pass to end, or for the player to be stopped.
+    pub async fn join(self) -> Outcome {
+        self.handle.join().await
+    }
+}
+
+/// Sends each bulb its track's look whenever it changes until every pass has played, then
+/// the end of the timeline once more with each bulb's own delivery.
+async fn run(
+    bulbs: Vec<(Bulb, usize)>,
+    timeline: Timeline,
+    playhead: Arc<Playhead>,
+    clock: Arc<dyn Clock>,
+    interval: Duration,
+    status: Arc<Status>,
+) -> Outcome {
+    let mut sent: Vec<Option<SetPilotParams>> = vec![None; bulbs.len()];
+    let tracks = timeline.clone();
+
+    let (bulbs, stopped) = tick(clock.as_ref(), interval, &status, bulbs, move |bulbs, _| {
+        let at = match playhead.frame() {
+            Some(at) => at,
+            None => return true,
+        };
+        let t = match tracks.pass_position(at.as_secs_f64()) {
+            Some(t) => t,
+            None => return false,
+        };
+
+        for ((b, track), sent) in bulbs.iter_mut().zip(sent.iter_mut()) {
+            let p = tracks.tracks[*track].sample(t, b.dimming_curve());
+            if sent.as_ref() == Some(&p) {
+                continue;
+            }
+            if let Err(e) = b.send_pilot(pilot(p.clone()), Delivery::FireAndForget) {
+                error!("{} dropped a frame: {}", b.name, e);
+            }
+            *sent = Some(p);
+        }
+        true
+    })
+    .await;
+
+    match stopped {
+        Some(outcome) => outcome,
+        None => {
+            let end = timeline.length();
+            let targets = bulbs
+                .into_iter()
+                .map(|(b, track)| {
+                    let last = timeline.tracks[track].sample(end, b.dimming_curve());
+                    (b, last)
+                })
+                .collect();
+            finish(targets).await
+        }
+    }
+}
+
+/// Starts `timeline` on `bulbs`, each paired with the index of its track.
+pub(crate) fn start(engine: &Engine, bulbs: Vec<(Bulb, usize)>, timeline: Timeline, status: Arc<Status>) -> Player {
+    let clock = engine.clock();
+    let playhead = Arc::new(Playhead::new(clock.clone()));
+    let claimed: Vec<_> = bulbs.iter().map(|(b, _)| b.clone()).collect();
+    let task = engine.spawn(
+        &claimed,
+        &status,
+        run(bulbs, timeline, playhead.clone(), clock, engine.frame_interval(), status.clone()),
+    );
+
+    Player {
+        handle: Handle { status, task },
+        playhead,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::effect::timeline::Timeline;
+    use crate::effect::{Engine, VirtualClock};
+    use crate::sim::VirtualBulb;
+
+    use super::*;
+
+    const FADE: &str = r#"
+tracks:
+  - target: { bulb: desk }
+    keyframes:
+      - { at: 0, dimming: 100, temp: 2700 }
+      - { at: 2, dimming: 10 }
+"#;
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_plays_to_the_end() {
+        let sim = VirtualBulb::new().unwrap();
+        let b = sim.bulb("desk", 1);
+        let engine = Engine::new(Arc::new(VirtualClock::new()));
+
+        let player = engine
+            .play_timeline(&Timeline::from_yaml(FADE).unwrap(), |_| Some(vec![b.clone()]))
+            .unwrap();
+
+        assert!(matches!(player.join().await, Outcome::Completed));
+        let state = sim.state();
+        assert_eq!((state.dimming, state.temp), (10, Some(2700)));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_seek_while_paused_shows_one_frame() {
+        let sim = VirtualBulb::new().unwrap();
+        let b = sim.bulb("desk", 1);
+        let engine = Engine::new(Arc::new(VirtualClock::new()));
+
+        let player = engine
+            .play_timeline(&Timeline::from_yaml(FADE).unwrap(), |_| Some(vec![b.clone()]))
+            .unwrap();
+        player.pause();
+        player.seek(Duration::from_secs(1));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(player.position(), Duration::from_secs(1));
+        assert_eq!(sim.state().dimming, 55);
+
+        player.stop();
+        assert!(matches!(player.join().await, Outcome::Cancelled));
+    }
+
+    #[rstest]
+    fn test_unknown_target() {
+        let engine = Engine::new(Arc::new(VirtualClock::new()));
+
+        let error = engine
+            .play_timeline(&Timeline::from_yaml(FADE).unwrap(), |_| None)
+            .unwrap_err();
+        assert_eq!(error.to_string(), r#"tracks[0].target: no bulb "desk" to play on"#);
+    }
+}
@@ -0,0 +1,231 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, Rgb, RgbImage, Rgba, RgbaImage};
+
+use crate::bulb::response::GetPilotResult;
+use crate::bulb::{Bulb, SetPilotParams};
+use crate::color::{render, Color};
+use crate::effect::{Effect, Target, Timeline, TimelineError, TrackTarget};
+
+/// What each member of a target shows over time, worked out without touching the network.
+///
+/// Every frame an animation would send is applied to a model of the bulb and drawn with
+/// [`render`], the same way a `getPilot` reply is, so a preview looks like the bulbs would.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preview {
+    /// Time between frames.
+    pub step: Duration,
+    /// One name per member.
+    pub members: Vec<String>,
+    /// `frames[i][m]` is member `m` at `step * i`.
+    pub frames: Vec<Vec<Color>>,
+}
+
+impl Preview {
+    /// `length` of `effect` on `target`, or less if the effect ends sooner.
+    pub fn effect(effect: &Effect, target: &impl Target, length: Duration, step: Duration) -> Preview {
+        let length = effect.duration.map_or(length, |d| d.min(length));
+        let bulbs = target.bulbs();
+        let members = bulbs.len();
+
+        Preview::record(&bulbs, length, step, |at, i, b| {
+            effect.pilot(at, i, members, b.dimming_curve()).params
+        })
+    }
+
+    /// Every pass of `timeline`, or one if it loops forever, with `resolve` finding the bulbs
+    /// each track names as [`super::Engine::play_timeline`] does.
+    pub fn timeline(
+        timeline: &Timeline,
+        resolve: impl Fn(&TrackTarget) -> Option<Vec<Bulb>>,
+        step: Duration,
+    ) -> Result<Preview, TimelineError> {
+        let members = timeline.members(resolve)?;
+        let bulbs: Vec<Bulb> = members.iter().map(|(b, _)| b.clone()).collect();
+        let length = Duration::from_secs_f64(timeline.length() * timeline.repeat.max(1) as f64);
+
+        Ok(Preview::record(&bulbs, length, step, |at, i, b| {
+            let t = timeline.pass_position(at.as_secs_f64()).unwrap_or(timeline.length());
+            timeline.tracks[members[i].1].sample(t, b.dimming_curve())
+        }))
+    }
+
+    /// Samples `frame` every `step` up to and including `length`, starting from bulbs that
+    /// are off.
+    fn record(
+        bulbs: &[Bulb],
+        length: Duration,
+        step: Duration,
+        mut frame: impl FnMut(Duration, usize, &Bulb) -> SetPilotParams,
+    ) -> Preview {
+        let step = step.max(Duration::from_millis(1));
+        let mut states = vec![GetPilotResult::default(); bulbs.len()];
+        let mut frames = vec![];
+
+        let mut at = Duration::ZERO;
+        while at <= length {
+            let row = bulbs
+                .iter()
+                .zip(states.iter_mut())
+                .enumerate()
+                .map(|(i, (b, state))| {
+                    state.apply(&frame(at, i, b));
+                    render(state)
+                })
+                .collect();
+            frames.push(row);
+            at += step;
+        }
+
+        Preview {
+            step,
+            members: bulbs.iter().map(|b| b.name.clone()).collect(),
+            frames,
+        }
+    }
+
+    /// A strip with time running left to right and a row per member, each frame `cell`
+    /// pixels square.
+    pub fn strip(&self, cell: u32) -> RgbImage {
+        let cell = cell.max(1);
+        let (width, height) = (self.frames.len() as u32 * cell, self.members.len() as u32 * cell);
+
+        RgbImage::from_fn(width, height, |x, y| {
+            let c = self.frames[(x / cell) as usize][(y / cell) as usize];
+            Rgb([c.r, c.g, c.b])
+        })
+    }
+
+    /// Saves [`Preview::strip`]; the format follows the extension, e.g. `.png`.
+    pub fn save_strip(&self, path: impl AsRef<Path>, cell: u32) -> anyhow::Result<()> {
+        self.strip(cell).save(path)?;
+        Ok(())
+    }
+
+    /// An animated GIF, looping forever, with the members side by side as `cell`-pixel
+    /// squares. GIF delays are in hundredths of a second, so short steps are rounded.
+    pub fn save_gif(&self, path: impl AsRef<Path>, cell: u32) -> anyhow::Result<()> {
+        let cell = cell.max(1);
+        let delay = Delay::from_saturating_duration(self.step);
+        let frames = self.frames.iter().map(|row| {
+            let image = RgbaImage::from_fn(row.len() as u32 * cell, cell, |x, _| {
+                let c = row[(x / cell) as usize];
+                Rgba([c.r, c.g, c.b, 255])
+            });
+            Frame::from_parts(image, 0, 0, delay)
+        });
+
+        let mut encoder = GifEncoder::new(BufWriter::new(File::create(path)?));
+        encoder.set_repeat(Repeat::Infinite)?;
+        encoder.encode_frames(frames)?;
+        Ok(())
+    }
+
+    /// The strip as truecolor terminal text: a line per member, a character per frame.
+    pub fn ansi_strip(&self) -> String {
+        let width = self.members.iter().map(|m| m.chars().count()).max().unwrap_or(0);
+
+        let mut out = String::new();
+        for (m, name) in self.members.iter().enumerate() {
+            let _ = write!(out, "{:<width$} ", name, width = width);
+            for row in self.frames.iter() {
+                out.push_str(&block(row[m], " "));
+            }
+            out.push_str(RESET);
+            out.push('\n');
+        }
+
+        out
+    }
+
+    /// Plays the preview in a truecolor terminal in real time, the members side by side on one
+    /// line that's redrawn every step.
+    pub fn play_ansi(&self, out: &mut impl Write) -> io::Result<()> {
+        for row in self.frames.iter() {
+            let line: String = row.iter().map(|c| block(*c, "    ")).collect();
+            write!(out, "\r{}{}", line, RESET)?;
+            out.flush()?;
+            std::thread::sleep(self.step);
+        }
+
+        writeln!(out)
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+
+fn block(c: Color, text: &str) -> String {
+    format!("\x1b[48;2;{};{};{}m{}", c.r, c.g, c.b, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use surrealdb::sql::Id;
+
+    use crate::effect::Timeline;
+    use crate::registry::{GraphStore, Group};
+
+    use super::*;
+
+    fn unreachable_group() -> Group {
+        let members: Vec<Box<dyn GraphStore>> = vec![
+            Box::new(Bulb::new("127.0.0.1".parse().unwrap(), "left".to_string(), 1)),
+            Box::new(Bulb::new("127.0.0.1".parse().unwrap(), "right".to_string(), 2)),
+        ];
+        Group::new(Id::from(1), "pair".to_string(), members)
+    }
+
+    #[rstest]
+    fn test_effect_preview_matches_looks() {
+        let group = unreachable_group();
+        let police = Effect::police();
+
+        let preview = Preview::effect(&police, &group, Duration::from_secs(1), Duration::from_millis(100));
+
+        assert_eq!(preview.members, vec!["left", "right"]);
+        assert_eq!(preview.frames.len(), 11);
+        assert_eq!(preview.frames[0], vec![Color::rgb(255, 0, 0), Color::rgb(0, 0, 255)]);
+        assert_eq!(preview.frames[3], vec![Color::BLACK, Color::BLACK]);
+    }
+
+    #[rstest]
+    fn test_timeline_preview_fades() {
+        let timeline = Timeline::from_yaml(
+            "tracks:\n  - target: { bulb: left }\n    keyframes:\n      - { at: 0, color: red }\n      - { at: 1, state: false }\n",
+        )
+        .unwrap();
+        let left = unreachable_group().bulbs()[0].clone();
+
+        let preview = Preview::timeline(&timeline, |_| Some(vec![left.clone()]), Duration::from_millis(500)).unwrap();
+
+        assert_eq!(preview.frames.len(), 3);
+        assert_eq!(preview.frames[0][0], Color::rgb(255, 0, 0));
+        assert!(preview.frames[1][0].r < 255 && preview.frames[1][0].r > 0);
+        assert_eq!(preview.frames[2][0], Color::BLACK);
+    }
+
+    #[rstest]
+    fn test_outputs() {
+        let preview = Preview::effect(&Effect::police(), &unreachable_group(), Duration::from_secs(1), Duration::from_millis(100));
+
+        let strip = preview.strip(4);
+        assert_eq!(strip.dimensions(), (44, 8));
+        assert_eq!(strip.get_pixel(0, 5), &Rgb([0, 0, 255]));
+
+        let text = preview.ansi_strip();
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.starts_with("left  \x1b[48;2;255;0;0m "));
+
+        let path = std::env::temp_dir().join(format!("wiz-preview-{}.gif", std::process::id()));
+        preview.save_gif(&path, 4).unwrap();
+        assert!(image::open(&path).is_ok());
+        std::fs::remove_file(path).unwrap();
+    }
+}
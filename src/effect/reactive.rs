@@ -0,0 +1,161 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::audio::{Analyzer, Features, Signal};
+use crate::bulb::{Bulb, Delivery, SetPilot};
+use crate::color::{Color, Hsv};
+use crate::effect::{tick, Clock, Look, Outcome, Status};
+
+/// How music drives the lights: bass sets brightness and every beat flashes to full and moves
+/// the hue on, mids push the hue further and treble washes the color out toward white.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Reactive {
+    /// Brightness level during silence.
+    pub min_level: f64,
+    /// Degrees the hue moves on each beat.
+    pub hue_step: f64,
+    /// Degrees between neighbouring members' hues.
+    pub spread: f64,
+    /// Samples analysed per frame.
+    pub window: usize,
+}
+
+impl Default for Reactive {
+    fn default() -> Self {
+        Reactive {
+            min_level: 0.1,
+            hue_step: 40.0,
+            spread: 30.0,
+            window: 1024,
+        }
+    }
+}
+
+impl Reactive {
+    /// What `member` of `members` shows for `features`, with the beat-driven hue at `hue`.
+    pub fn look(&self, features: &Features, hue: f64, member: usize, members: usize) -> Look {
+        let bands = features.bands;
+        let level = if features.beat { 1.0 } else { bands.bass as f64 };
+        let h = hue + bands.mid as f64 * 90.0 + (member % members.max(1)) as f64 * self.spread;
+
+        Look {
+            color: Color::from_hsv(Hsv {
+                h: h.rem_euclid(360.0),
+                s: 1.0 - 0.5 * bands.treble as f64,
+                v: 1.0,
+            }),
+            level: self.min_level + (1.0 - self.min_level) * level.clamp(0.0, 1.0),
+        }
+    }
+}
+
+/// Analyses `signal` once a frame and sends the looks, skipping frames a bulb already shows.
+pub(crate) async fn run(
+    bulbs: Vec<Bulb>,
+    leads: Vec<Duration>,
+    mut signal: Box<dyn Signal>,
+    reactive: Reactive,
+    clock: Arc<dyn Clock>,
+    interval: Duration,
+    status: Arc<Status>,
+) -> Outcome {
+    let members = bulbs.len();
+    let mut analyzer = Analyzer::new(signal.sample_rate());
+    let mut hue = 0.0;
+    let mut shown: Vec<Option<SetPilot>> = vec![None; members];
+    // the audio is judged at the moment the furthest bulb will show it
+    let lead = leads.iter().max().copied().unwrap_or_default();
+
+    let (_, stopped) = tick(clock.as_ref(), interval, &status, bulbs, move |bulbs, elapsed| {
+        let window = match signal.window(elapsed + lead, reactive.window) {
+            Some(window) => window,
+            None => return false,
+        };
+        let features = analyzer.analyze(&window);
+        if features.beat {
+            hue += reactive.hue_step;
+        }
+
+        for (i, b) in bulbs.iter_mut().enumerate() {
+            let look = reactive.look(&features, hue, i, members);
+            let p = SetPilot::default().level(look.level, b.dimming_curve()).color(look.color).to_owned();
+            if shown[i].as_ref() == Some(&p) {
+                continue;
+            }
+            if let Err(e) = b.send_pilot(p.clone(), Delivery::FireAndForget) {
+                error!("{} dropped a frame: {}", b.name, e);
+            }
+            shown[i] = Some(p);
+        }
+        true
+    })
+    .await;
+
+    stopped.unwrap_or(Outcome::Completed)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use surrealdb::sql::Id;
+
+    use crate::audio::Wav;
+    use crate::effect::tests::Tap;
+    use crate::effect::{Engine, VirtualClock};
+    use crate::registry::{GraphStore, Group};
+    use crate::sim::VirtualBulb;
+
+    use super::*;
+
+    fn kicks(seconds: f32) -> Wav {
+        let rate = 22050;
+        let samples = (0..(seconds * rate as f32) as usize)
+            .map(|i| {
+                let t = i as f32 / rate as f32;
+                let hiss = 0.01 * (i as f32 * 12.9898).sin().fract();
+                if t % 0.5 < 0.05 { (std::f32::consts::TAU * 60.0 * t).sin() + hiss } else { hiss }
+            })
+            .collect();
+
+        Wav { sample_rate: rate, samples }
+    }
+
+    #[rstest]
+    fn test_beat_flashes_full() {
+        let r = Reactive::default();
+        let quiet = Features::default();
+        let beat = Features { beat: true, ..Default::default() };
+
+        assert_eq!(r.look(&quiet, 0.0, 0, 1).level, 0.1);
+        assert_eq!(r.look(&beat, 0.0, 0, 1).level, 1.0);
+        assert_ne!(r.look(&quiet, 0.0, 0, 2).color, r.look(&quiet, 0.0, 1, 2).color);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_reacts_to_wav_within_rate_limit() {
+        let sims = VirtualBulb::many(2).unwrap();
+        let tap = Arc::new(Tap::default());
+        let mut first = sims[0].bulb("party", 0);
+        first.set_transport(tap.clone());
+        let g = Group::new(
+            Id::from(33),
+            "party".to_string(),
+            vec![Box::new(first) as Box<dyn GraphStore>, Box::new(sims[1].bulb("party", 1))],
+        );
+        let mut engine = Engine::new(Arc::new(VirtualClock::new()));
+        engine.set_frame_rate(100.0);
+
+        let wav = Wav::parse(&kicks(3.0).to_bytes()).unwrap();
+        let outcome = engine.react(&g, wav, &Reactive::default()).join().await;
+
+        assert!(matches!(outcome, Outcome::Completed), "{:?}", outcome);
+        let fired = tap.fired.lock().unwrap();
+        assert!(fired.len() <= 60, "{} frames in 3s", fired.len());
+        assert!(fired.iter().any(|p| p.params.dimming == Some(100)));
+        assert!(fired.iter().any(|p| p.params.dimming < Some(50)));
+    }
+}
@@ -0,0 +1,320 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use log::{error, info};
+
+use crate::bulb::{Bulb, Delivery, DimmingCurve, SetPilotParams};
+use crate::effect::transition::{as_reported, finish, pilot, Fade};
+use crate::effect::{Clock, Outcome, Status};
+
+/// A slow ramp through a list of looks, such as a wake-up sunrise.
+///
+/// Each frame is worked out from the time since the start, so a frame that's lost or sent
+/// late is simply made up by the next one. Before every frame the bulbs are read back, and if
+/// one that the routine left on has been switched off by someone else, the routine ends with
+/// [`Outcome::Interrupted`] instead of turning it back on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Routine {
+    /// Looks at positions along `0.0..=1.0` of the duration, in order.
+    pub waypoints: Vec<(f64, SetPilotParams)>,
+    pub duration: Duration,
+    /// How long to wait before the first frame.
+    pub delay: Duration,
+    /// Time between frames.
+    pub step: Duration,
+}
+
+fn look(dimming: u32, rgb: Option<(u32, u32, u32)>, temp: Option<u32>) -> SetPilotParams {
+    SetPilotParams {
+        state: Some(true),
+        dimming: Some(dimming),
+        temp,
+        r: rgb.map(|c| c.0),
+        g: rgb.map(|c| c.1),
+        b: rgb.map(|c| c.2),
+        ..Default::default()
+    }
+}
+
+const DEEP_RED: (u32, u32, u32) = (255, 24, 0);
+const AMBER: (u32, u32, u32) = (255, 126, 0);
+
+impl Routine {
+    pub fn new(waypoints: Vec<(f64, SetPilotParams)>, duration: Duration) -> Routine {
+        Routine {
+            waypoints,
+            duration,
+            delay: Duration::ZERO,
+            step: Duration::from_secs(1),
+        }
+    }
+
+    /// Deep red at the lowest dimming, through amber and warm white, to 4000K at full.
+    pub fn sunrise(duration: Duration) -> Routine {
+        Routine::new(
+            vec![
+                (0.0, look(10, Some(DEEP_RED), None)),
+                (0.3, look(30, Some(AMBER), None)),
+                (0.6, look(60, None, Some(2700))),
+                (1.0, look(100, None, Some(4000))),
+            ],
+            duration,
+        )
+    }
+
+    /// The sunrise backwards, ending with the light off.
+    pub fn sunset(duration: Duration) -> Routine {
+        Routine::new(
+            vec![
+                (0.0, look(100, None, Some(4000))),
+                (0.4, look(60, None, Some(2700))),
+                (0.7, look(30, Some(AMBER), None)),
+                (0.95, look(10, Some(DEEP_RED), None)),
+                (1.0, SetPilotParams { state: Some(false), ..Default::default() }),
+            ],
+            duration,
+        )
+    }
+
+    pub fn start_in(&mut self, delay: Duration) -> &mut Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Starts at wall-clock time `at`, or straight away if that's already passed.
+    pub fn start_at(&mut self, at: SystemTime) -> &mut Self {
+        self.delay = at.duration_since(SystemTime::now()).unwrap_or_default();
+        self
+    }
+
+    pub fn step(&mut self, step: Duration) -> &mut Self {
+        self.step = step.max(Duration::from_millis(1));
+        self
+    }
+
+    /// The look `t` of the way through, with brightness moving along `curve`.
+    pub fn frame(&self, t: f64, curve: &DimmingCurve) -> SetPilotParams {
+        let (first, last) = match (self.waypoints.first(), self.waypoints.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return SetPilotParams::default(),
+        };
+        if t <= first.0 {
+            return first.1.clone();
+        }
+        if t >= last.0 {
+            return last.1.clone();
+        }
+
+        let i = self.waypoints.iter().position(|w| w.0 > t).unwrap_or(self.waypoints.len() - 1);
+        let (from, to) = (&self.waypoints[i - 1], &self.waypoints[i]);
+        let span = to.0 - from.0;
+        if span <= 0.0 {
+            return to.1.clone();
+        }
+
+        Fade::new(curve.clone(), Some(&as_reported(&from.1)), &to.1).frame((t - from.0) / span)
+    }
+}
+
+/// Whether `b` reports being off; a bulb that doesn't answer is given the benefit of the doubt.
+async fn switched_off(b: &Bulb) -> bool {
+    let b = b.clone();
+    tokio::task::spawn_blocking(move || b.get_pilot())
+        .await
+        .unwrap()
+        .is_ok_and(|r| !r.result.state)
+}
+
+/// Fires frame `p` at `b` from the blocking pool.
+async fn send_frame(b: &mut Bulb, p: SetPilotParams) {
+    let mut sending = b.clone();
+    let (sent, result) = tokio::task::spawn_blocking(move || {
+        let result = sending.send_pilot(pilot(p), Delivery::FireAndForget);
+        (sending, result)
+    })
+    .await
+    .unwrap();
+
+    if let Err(e) = result {
+        error!("{} dropped a frame: {}", sent.name, e);
+    }
+    *b = sent;
+}
+
+/// Waits out the delay, then steps through the routine until its duration is up.
+pub(crate) async fn run(
+    mut bulbs: Vec<Bulb>,
+    routine: Routine,
+    clock: Arc<dyn Clock>,
+    status: Arc<Status>,
+) -> Outcome {
+    let start = clock.now() + routine.delay;
+    while clock.now() < start {
+        if let Some(outcome) = status.stopped() {
+            return outcome;
+        }
+        clock.sleep_until(start.min(clock.now() + routine.step)).await;
+    }
+
+    let mut lit = vec![false; bulbs.len()];
+    loop {
+        if let Some(outcome) = status.stopped() {
+            return outcome;
+        }
+        let elapsed = clock.now().saturating_sub(start);
+        if elapsed >= routine.duration {
+            break;
+        }
+
+        let t = elapsed.as_secs_f64() / routine.duration.as_secs_f64();
+        for (i, b) in bulbs.iter_mut().enumerate() {
+            if lit[i] && switched_off(b).await {
+                info!("{} was switched off, abandoning the routine", b.name);
+                return Outcome::Interrupted;
+            }
+
+            let p = routine.frame(t, b.dimming_curve());
+            lit[i] = p.state != Some(false);
+            send_frame(b, p).await;
+        }
+
+        let steps = (elapsed.as_nanos() / routine.step.as_nanos()) as u32 + 1;
+        clock.sleep_until(start + routine.step * steps).await;
+    }
+
+    for (i, b) in bulbs.iter().enumerate() {
+        if lit[i] && switched_off(b).await {
+            info!("{} was switched off, leaving it off", b.name);
+            return Outcome::Interrupted;
+        }
+    }
+
+    let last = routine.frame(1.0, &DimmingCurve::Linear);
+    finish(bulbs.into_iter().map(|b| (b, last.clone())).collect()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use rstest::rstest;
+
+    use crate::bulb::transport::{Datagram, Transport, Udp};
+    use crate::effect::{Engine, SystemClock, VirtualClock};
+    use crate::sim::{FaultProfile, VirtualBulb};
+
+    use super::*;
+
+    /// Plain UDP, except that right after the `after`th frame fired the bulb is switched off
+    /// as if by hand.
+    #[derive(Debug)]
+    struct SwitchOffAfter {
+        after: usize,
+        fired: AtomicUsize,
+    }
+
+    impl Transport for SwitchOffAfter {
+        fn exchange(&self, target: IpAddr, message: &[u8]) -> anyhow::Result<Datagram> {
+            Udp.exchange(target, message)
+        }
+
+        fn fire(&self, target: IpAddr, message: &[u8]) -> anyhow::Result<()> {
+            Udp.fire(target, message)?;
+            if self.fired.fetch_add(1, Ordering::SeqCst) + 1 == self.after {
+                Udp.exchange(target, br#"{"method":"setPilot","params":{"state":false}}"#)?;
+            }
+            Ok(())
+        }
+
+        fn poll(&self, target: IpAddr, message: &[u8]) -> anyhow::Result<Vec<Datagram>> {
+            Udp.poll(target, message)
+        }
+    }
+
+    #[rstest]
+    fn test_sunrise_ends() {
+        let sunrise = Routine::sunrise(Duration::from_secs(600));
+
+        let first = sunrise.frame(0.0, &DimmingCurve::Linear);
+        assert_eq!((first.dimming, first.r, first.g), (Some(10), Some(255), Some(24)));
+
+        let last = sunrise.frame(1.0, &DimmingCurve::Linear);
+        assert_eq!((last.dimming, last.temp, last.r), (Some(100), Some(4000), None));
+    }
+
+    #[rstest]
+    fn test_sunrise_passes_through_amber_in_color() {
+        let frame = Routine::sunrise(Duration::from_secs(600)).frame(0.45, &DimmingCurve::Linear);
+
+        assert_eq!(frame.dimming, Some(45));
+        assert!(frame.r.is_some() && frame.temp.is_none());
+    }
+
+    #[rstest]
+    fn test_sunset_ends_off() {
+        let sunset = Routine::sunset(Duration::from_secs(600));
+
+        assert_eq!(sunset.frame(1.0, &DimmingCurve::Linear).state, Some(false));
+        assert_eq!(sunset.frame(0.5, &DimmingCurve::Linear).state, Some(true));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_sunrise_survives_lost_frames() {
+        let sim = VirtualBulb::new().unwrap();
+        sim.set_faults(FaultProfile { seed: 4, loss: 0.3, ..Default::default() });
+        let mut b = sim.bulb("foo", 1);
+        b.set_delivery(Delivery::Verified { retries: 10 });
+        let engine = Engine::new(Arc::new(VirtualClock::new()));
+
+        let outcome = engine.run_routine(&b, &Routine::sunrise(Duration::from_secs(4))).join().await;
+
+        assert!(matches!(outcome, Outcome::Completed), "{:?}", outcome);
+        let state = sim.state();
+        assert_eq!((state.state, state.dimming, state.temp), (true, 100, Some(4000)));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_switching_off_interrupts() {
+        let sim = VirtualBulb::new().unwrap();
+        let engine = Engine::new(Arc::new(SystemClock::new()));
+        let sunrise = Routine::sunrise(Duration::from_secs(5)).step(Duration::from_millis(50)).to_owned();
+
+        let handle = engine.run_routine(&sim.bulb("foo", 1), &sunrise);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        sim.update(|s| s.state = false);
+
+        assert!(matches!(handle.join().await, Outcome::Interrupted));
+        assert!(!sim.state().state);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_switching_off_during_last_step_interrupts() {
+        let sim = VirtualBulb::new().unwrap();
+        let mut b = sim.bulb("foo", 1);
+        // two frames, at 0s and 1s, before the final one at 2s
+        b.set_transport(Arc::new(SwitchOffAfter { after: 2, fired: AtomicUsize::new(0) }));
+        let engine = Engine::new(Arc::new(VirtualClock::new()));
+
+        let outcome = engine.run_routine(&b, &Routine::sunrise(Duration::from_secs(2))).join().await;
+
+        assert!(matches!(outcome, Outcome::Interrupted), "{:?}", outcome);
+        assert!(!sim.state().state);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_delay() {
+        let sim = VirtualBulb::new().unwrap();
+        let clock = Arc::new(VirtualClock::new());
+        let engine = Engine::new(clock.clone());
+        let sunrise = Routine::sunrise(Duration::from_secs(2)).start_in(Duration::from_secs(60)).to_owned();
+
+        engine.run_routine(&sim.bulb("foo", 1), &sunrise).join().await;
+
+        assert!(clock.now() >= Duration::from_secs(62));
+    }
+}
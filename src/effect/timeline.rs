@@ -0,0 +1,473 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::bulb::curve::{MAX_DIMMING, MIN_DIMMING};
+use crate::bulb::{Bulb, DimmingCurve, SetPilotParams};
+use crate::color::{Color, MAX_KELVIN, MIN_KELVIN};
+use crate::effect::transition::{as_reported, Fade};
+use crate::effect::Easing;
+
+const MAX_SCENE: u32 = 32;
+const MIN_SPEED: u32 = 20;
+const MAX_SPEED: u32 = 200;
+
+/// A light show written as data: tracks of keyframes, one track per bulb or group.
+///
+/// ```yaml
+/// name: dusk
+/// repeat: 2
+/// tracks:
+///   - target: { group: living room }
+///     keyframes:
+///       - { at: 0, color: "#ff3000", dimming: 100 }
+///       - { at: 4, temp: 2700, dimming: 40, easing: ease_in_out }
+///       - { at: 6, scene: 6 }
+/// ```
+///
+/// Between keyframes brightness, white and color fade the way a [`super::Transition`] does,
+/// paced by the easing of the keyframe being faded to. Scenes can't be faded, so a track
+/// switches into or out of a scene when it reaches the keyframe. Each keyframe only needs the
+/// fields that change; the rest carry over from the ones before it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Timeline {
+    #[serde(default)]
+    pub name: String,
+    /// Seconds in one pass; by default it ends at the last keyframe of the longest track.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    /// How many passes to play, `0` for forever.
+    #[serde(default = "one")]
+    pub repeat: u32,
+    pub tracks: Vec<Track>,
+}
+
+fn one() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Track {
+    pub target: TrackTarget,
+    pub keyframes: Vec<Keyframe>,
+}
+
+/// What a track drives, by name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackTarget {
+    Bulb(String),
+    Group(String),
+}
+
+impl fmt::Display for TrackTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TrackTarget::Bulb(name) => write!(f, "bulb {:?}", name),
+            TrackTarget::Group(name) => write!(f, "group {:?}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe {
+    /// Seconds from the start of the pass.
+    pub at: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<Color>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temp: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimming: Option<u32>,
+    /// One of the bulb's built-in scenes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scene: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<u32>,
+    /// How the fade into this keyframe is paced.
+    #[serde(default)]
+    pub easing: Easing,
+}
+
+impl Keyframe {
+    fn check(&self) -> Result<(), String> {
+        if !self.at.is_finite() || self.at < 0.0 {
+            return Err(format!("`at` must be a time in seconds from 0, not {}", self.at));
+        }
+        if let Some(dimming) = self.dimming {
+            if !(MIN_DIMMING..=MAX_DIMMING).contains(&dimming) {
+                return Err(format!("dimming {} is outside {}..={}", dimming, MIN_DIMMING, MAX_DIMMING));
+            }
+        }
+        if let Some(temp) = self.temp {
+            if !(MIN_KELVIN..=MAX_KELVIN).contains(&temp) {
+                return Err(format!("temp {} is outside {}..={}", temp, MIN_KELVIN, MAX_KELVIN));
+            }
+        }
+        if let Some(scene) = self.scene {
+            if !(1..=MAX_SCENE).contains(&scene) {
+                return Err(format!("scene {} is outside 1..={}", scene, MAX_SCENE));
+            }
+        }
+        if let Some(speed) = self.speed {
+            if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+                return Err(format!("speed {} is outside {}..={}", speed, MIN_SPEED, MAX_SPEED));
+            }
+        }
+        if self.color.is_some() && self.temp.is_some() {
+            return Err("sets both color and temp".to_string());
+        }
+        if self.scene.is_some() && (self.color.is_some() || self.temp.is_some()) {
+            return Err("sets a scene together with a color or temp".to_string());
+        }
+        if self.state.is_none()
+            && self.color.is_none()
+            && self.temp.is_none()
+            && self.dimming.is_none()
+            && self.scene.is_none()
+            && self.speed.is_none()
+        {
+            return Err("sets nothing".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// `self` laid over the look the track had reached before it.
+    fn over(&self, before: &SetPilotParams) -> SetPilotParams {
+        let mut p = before.clone();
+        if self.color.is_some() || self.temp.is_some() || self.scene.is_some() {
+            (p.r, p.g, p.b, p.temp, p.scene_id, p.speed) = (None, None, None, None, None, None);
+        }
+        if let Some(c) = self.color {
+            (p.r, p.g, p.b) = (Some(c.r as u32), Some(c.g as u32), Some(c.b as u32));
+        }
+        p.temp = self.temp.or(p.temp);
+        p.scene_id = self.scene.or(p.scene_id);
+        p.speed = self.speed.or(p.speed);
+        p.dimming = self.dimming.or(p.dimming);
+        p.state = match self.state {
+            Some(state) => Some(state),
+            None if self.differs_from_off() => Some(true),
+            None => p.state,
+        };
+
+        p
+    }
+
+    /// Whether the keyframe sets a look, which only makes sense with the light on.
+    fn differs_from_off(&self) -> bool {
+        self.color.is_some() || self.temp.is_some() || self.dimming.is_some() || self.scene.is_some()
+    }
+}
+
+impl Track {
+    /// The look each keyframe leaves the track in.
+    fn looks(&self) -> Vec<SetPilotParams> {
+        let mut before = SetPilotParams::default();
+        self.keyframes
+            .iter()
+            .map(|k| {
+                before = k.over(&before);
+                before.clone()
+            })
+            .collect()
+    }
+
+    /// The look `at` seconds into a pass, with brightness moving along `curve`.
+    pub fn sample(&self, at: f64, curve: &DimmingCurve) -> SetPilotParams {
+        let looks = self.looks();
+        if looks.is_empty() {
+            return SetPilotParams::default();
+        }
+        let i = self.keyframes.iter().position(|k| k.at > at).unwrap_or(self.keyframes.len());
+        if i == 0 || i == self.keyframes.len() {
+            return looks[i.saturating_sub(1)].clone();
+        }
+
+        let (from, to) = (&looks[i - 1], &looks[i]);
+        if from.scene_id.is_some() || to.scene_id.is_some() {
+            return from.clone();
+        }
+        let span = self.keyframes[i].at - self.keyframes[i - 1].at;
+        let t = self.keyframes[i].easing.apply((at - self.keyframes[i - 1].at) / span);
+
+        Fade::new(curve.clone(), Some(&as_reported(from)), to).frame(t)
+    }
+
+    fn end(&self) -> f64 {
+        self.keyframes.last().map_or(0.0, |k| k.at)
+    }
+}
+
+impl Timeline {
+    /// Parses and validates a timeline written in JSON.
+    pub fn from_json(text: &str) -> Result<Timeline, TimelineError> {
+        let value = serde_json::from_str(text).map_err(|e| TimelineError::new("", e))?;
+        Timeline::from_value(value)
+    }
+
+    /// Parses and validates a timeline written in YAML.
+    pub fn from_yaml(text: &str) -> Result<Timeline, TimelineError> {
+        let value = serde_yaml::from_str(text).map_err(|e| TimelineError::new("", e))?;
+        Timeline::from_value(value)
+    }
+
+    /// Reads a `.yaml`/`.yml` or JSON file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Timeline, TimelineError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| TimelineError::new("", e))?;
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => Timeline::from_yaml(&text),
+            _ => Timeline::from_json(&text),
+        }
+    }
+
+    /// Deserializes a piece at a time so that an error can say which keyframe it's in.
+    fn from_value(value: Value) -> Result<Timeline, TimelineError> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Outline {
+            #[serde(default)]
+            name: String,
+            #[serde(default)]
+            duration: Option<f64>,
+            #[serde(default = "one")]
+            repeat: u32,
+            tracks: Vec<Value>,
+        }
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct TrackOutline {
+            target: Value,
+            keyframes: Vec<Value>,
+        }
+
+        let outline: Outline = serde_json::from_value(value).map_err(|e| TimelineError::new("", e))?;
+        let mut tracks = vec![];
+        for (i, track) in outline.tracks.into_iter().enumerate() {
+            let at = |field: &str| format!("tracks[{}]{}", i, field);
+            let track: TrackOutline = serde_json::from_value(track).map_err(|e| TimelineError::new(at(""), e))?;
+            let target = serde_json::from_value(track.target).map_err(|e| TimelineError::new(at(".target"), e))?;
+
+            let mut keyframes = vec![];
+            for (j, keyframe) in track.keyframes.into_iter().enumerate() {
+                let keyframe = serde_json::from_value(keyframe)
+                    .map_err(|e| TimelineError::new(at(&format!(".keyframes[{}]", j)), e))?;
+                keyframes.push(keyframe);
+            }
+            tracks.push(Track { target, keyframes });
+        }
+
+        let timeline = Timeline {
+            name: outline.name,
+            duration: outline.duration,
+            repeat: outline.repeat,
+            tracks,
+        };
+        timeline.validate()?;
+
+        Ok(timeline)
+    }
+
+    /// Checks everything the parsers check, for timelines built in code.
+    pub fn validate(&self) -> Result<(), TimelineError> {
+        if self.tracks.is_empty() {
+            return Err(TimelineError::new("tracks", "no tracks"));
+        }
+        for (i, track) in self.tracks.iter().enumerate() {
+            if track.keyframes.is_empty() {
+                return Err(TimelineError::new(format!("tracks[{}].keyframes", i), "no keyframes"));
+            }
+            for (j, keyframe) in track.keyframes.iter().enumerate() {
+                let path = || format!("tracks[{}].keyframes[{}]", i, j);
+                keyframe.check().map_err(|e| TimelineError::new(path(), e))?;
+
+                if let Some(previous) = j.checked_sub(1).map(|j| &track.keyframes[j]) {
+                    if keyframe.at <= previous.at {
+                        let message = format!("at {}s is not after the keyframe before it at {}s", keyframe.at, previous.at);
+                        return Err(TimelineError::new(path(), message));
+                    }
+                }
+            }
+        }
+
+        let end = self.tracks.iter().map(Track::end).fold(0.0, f64::max);
+        match self.duration {
+            Some(duration) if !duration.is_finite() || duration <= 0.0 => {
+                Err(TimelineError::new("duration", format!("{} is not a length of time", duration)))
+            }
+            Some(duration) if duration < end => Err(TimelineError::new(
+                "duration",
+                format!("{}s ends before the last keyframe at {}s", duration, end),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Every bulb the tracks name, paired with the index of its track, after checking the
+    /// timeline is valid.
+    pub(crate) fn members(
+        &self,
+        resolve: impl Fn(&TrackTarget) -> Option<Vec<Bulb>>,
+    ) -> Result<Vec<(Bulb, usize)>, TimelineError> {
+        self.validate()?;
+
+        let mut bulbs = vec![];
+        for (i, track) in self.tracks.iter().enumerate() {
+            let members = resolve(&track.target).ok_or_else(|| {
+                TimelineError::new(format!("tracks[{}].target", i), format!("no {} to play on", track.target))
+            })?;
+            bulbs.extend(members.into_iter().map(|b| (b, i)));
+        }
+
+        Ok(bulbs)
+    }
+
+    /// Seconds in one pass.
+    pub fn length(&self) -> f64 {
+        self.duration
+            .unwrap_or_else(|| self.tracks.iter().map(Track::end).fold(0.0, f64::max))
+    }
+
+    /// Where `at` seconds from the start falls within a pass, or `None` once every pass has
+    /// played.
+    pub fn pass_position(&self, at: f64) -> Option<f64> {
+        let length = self.length();
+        if self.repeat != 0 && at >= length * self.repeat as f64 {
+            return None;
+        }
+        if length <= 0.0 {
+            return Some(0.0);
+        }
+
+        Some(at % length)
+    }
+}
+
+/// A timeline that couldn't be read, and where in it the problem is, e.g.
+/// `tracks[0].keyframes[2]: dimming 120 is outside 10..=100`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimelineError {
+    pub path: String,
+    pub message: String,
+}
+
+impl TimelineError {
+    pub fn new(path: impl Into<String>, message: impl fmt::Display) -> TimelineError {
+        TimelineError {
+            path: path.into(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for TimelineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+impl Error for TimelineError {}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    const DUSK: &str = r##"
+name: dusk
+repeat: 2
+tracks:
+  - target: { group: living room }
+    keyframes:
+      - { at: 0, color: "#ff0000", dimming: 100 }
+      - { at: 2, dimming: 50 }
+      - { at: 4, temp: 2700, easing: ease_in }
+      - { at: 6, scene: 6, speed: 120 }
+"##;
+
+    fn dusk() -> Timeline {
+        Timeline::from_yaml(DUSK).unwrap()
+    }
+
+    #[rstest]
+    fn test_yaml_and_json_agree() {
+        let json = serde_json::to_string(&dusk()).unwrap();
+
+        assert_eq!(Timeline::from_json(&json).unwrap(), dusk());
+        assert_eq!(dusk().tracks[0].target, TrackTarget::Group("living room".to_string()));
+        assert_eq!(dusk().length(), 6.0);
+    }
+
+    #[rstest]
+    #[case(r#"{ at: 1, dimming: 120 }"#, "tracks[0].keyframes[1]: dimming 120 is outside 10..=100")]
+    #[case(r#"{ at: 0, dimming: 50 }"#, "tracks[0].keyframes[1]: at 0s is not after the keyframe before it at 0s")]
+    #[case(r#"{ at: 1, color: red, temp: 3000 }"#, "tracks[0].keyframes[1]: sets both color and temp")]
+    #[case(r#"{ at: 1, colour: red }"#, "tracks[0].keyframes[1]: unknown field `colour`")]
+    #[case(r#"{ at: 1, color: "not a color" }"#, "tracks[0].keyframes[1]")]
+    #[case(r#"{ at: 1 }"#, "tracks[0].keyframes[1]: sets nothing")]
+    fn test_errors_point_at_keyframe(#[case] keyframe: &str, #[case] expected: &str) {
+        let yaml = format!(
+            "tracks:\n  - target: {{ bulb: desk }}\n    keyframes:\n      - {{ at: 0, state: true }}\n      - {}\n",
+            keyframe
+        );
+
+        let error = Timeline::from_yaml(&yaml).unwrap_err().to_string();
+        assert!(error.starts_with(expected), "{}", error);
+    }
+
+    #[rstest]
+    fn test_duration_shorter_than_keyframes() {
+        let mut timeline = dusk();
+        timeline.duration = Some(5.0);
+
+        assert_eq!(timeline.validate().unwrap_err().path, "duration");
+    }
+
+    #[rstest]
+    fn test_sample_carries_earlier_fields() {
+        let track = &dusk().tracks[0];
+
+        let halfway = track.sample(1.0, &DimmingCurve::Linear);
+        assert_eq!((halfway.r, halfway.g, halfway.b), (Some(255), Some(0), Some(0)));
+        assert_eq!(halfway.dimming, Some(75));
+
+        let held = track.sample(2.0, &DimmingCurve::Linear);
+        assert_eq!((held.r, held.dimming), (Some(255), Some(50)));
+    }
+
+    #[rstest]
+    fn test_scene_switches_on_its_keyframe() {
+        let track = &dusk().tracks[0];
+
+        let before = track.sample(5.9, &DimmingCurve::Linear);
+        assert_eq!((before.temp, before.scene_id), (Some(2700), None));
+
+        let after = track.sample(6.0, &DimmingCurve::Linear);
+        assert_eq!((after.temp, after.scene_id, after.speed, after.dimming), (None, Some(6), Some(120), Some(50)));
+    }
+
+    #[rstest]
+    #[case(3.0, Some(3.0))]
+    #[case(7.0, Some(1.0))]
+    #[case(12.0, None)]
+    fn test_pass_position(#[case] at: f64, #[case] expected: Option<f64>) {
+        assert_eq!(dusk().pass_position(at), expected);
+    }
+}
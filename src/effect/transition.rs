@@ -0,0 +1,326 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{error, info};
+
+use crate::bulb::response::{ErrorResponse, GetPilotResult};
+use crate::bulb::{Bulb, Delivery, DimmingCurve, SetPilot, SetPilotParams};
+use crate::color::{kelvin_to_rgb, Color, MAX_KELVIN, MIN_KELVIN};
+use crate::effect::{tick, Clock, Easing, Outcome, Status};
+
+/// A fade to `to` over `duration`.
+///
+/// Brightness moves evenly along each bulb's [`DimmingCurve`], white temperature in mireds
+/// and color in Oklab, so the midpoints look like midpoints. Going between white and color
+/// mode fades through color and lands on the exact white at the end.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    pub to: SetPilotParams,
+    pub duration: Duration,
+    pub easing: Easing,
+}
+
+impl Transition {
+    pub fn new(to: SetPilotParams, duration: Duration) -> Transition {
+        Transition {
+            to,
+            duration,
+            easing: Easing::default(),
+        }
+    }
+
+    pub fn easing(&mut self, easing: Easing) -> &mut Self {
+        self.easing = easing;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Mode {
+    /// Only brightness changes; the bulb keeps its color or white.
+    Dimming,
+    /// White temperature, in mireds.
+    Temp(f64, f64),
+    Color(Color, Color),
+}
+
+/// One bulb's path from its observed state to the target.
+#[derive(Debug, Clone)]
+pub(crate) struct Fade {
+    curve: DimmingCurve,
+    level: (f64, f64),
+    mode: Mode,
+    channels: Option<((f64, f64), (f64, f64))>,
+    last: SetPilotParams,
+}
+
+impl Fade {
+    /// A fade starting from `start`, or a jump straight to `to` if the bulb couldn't be read.
+    pub(crate) fn new(curve: DimmingCurve, start: Option<&GetPilotResult>, to: &SetPilotParams) -> Fade {
+        let mut last = to.clone();
+        last.state.get_or_insert(true);
+
+        let start = match start {
+            Some(start) => start,
+            None => {
+                let level = curve.level(to.dimming.unwrap_or(100));
+                let level = if last.state == Some(true) { level } else { 0.0 };
+                return Fade { curve, level: (level, level), mode: Mode::Dimming, channels: None, last };
+            }
+        };
+
+        let remembered = curve.level(start.dimming.unwrap_or(100));
+        let from_level = if start.state { remembered } else { 0.0 };
+        let to_level = match (to.state, to.dimming) {
+            (Some(false), _) => 0.0,
+            (_, Some(dimming)) => curve.level(dimming),
+            _ => remembered,
+        };
+
+        let from_color = start.color().or_else(|| start.temp.map(kelvin_to_rgb));
+        let mode = match (target_color(to), to.temp) {
+            (Some(color), _) => Mode::Color(from_color.unwrap_or(color), color),
+            (None, Some(kelvin)) => match (start.temp, from_color) {
+                (Some(from), _) => Mode::Temp(mireds(from), mireds(kelvin)),
+                (None, Some(from)) => Mode::Color(from, kelvin_to_rgb(kelvin)),
+                (None, None) => Mode::Temp(mireds(kelvin), mireds(kelvin)),
+            },
+            (None, None) => Mode::Dimming,
+        };
+
+        let channels = if to.c.is_some() || to.w.is_some() {
+            let from = (start.c.unwrap_or(0) as f64, start.w.unwrap_or(0) as f64);
+            Some((from, (to.c.map_or(from.0, |c| c as f64), to.w.map_or(from.1, |w| w as f64))))
+        } else {
+            None
+        };
+
+        Fade { curve, level: (from_level, to_level), mode, channels, last }
+    }
+
+    /// The frame `t` of the way through, where `t` has already been eased.
+    pub(crate) fn frame(&self, t: f64) -> SetPilotParams {
+        let level = lerp(self.level, t);
+        let mut p = SetPilot::default().level(level, &self.curve).to_owned().params;
+        if p.state == Some(false) {
+            return p;
+        }
+
+        match self.mode {
+            Mode::Dimming => {}
+            Mode::Temp(from, to) => {
+                let kelvin = (1_000_000.0 / lerp((from, to), t)).round() as u32;
+                p.temp = Some(kelvin.clamp(MIN_KELVIN, MAX_KELVIN));
+            }
+            Mode::Color(from, to) => {
+                let color = from.mix(to, t);
+                p.r = Some(color.r as u32);
+                p.g = Some(color.g as u32);
+                p.b = Some(color.b as u32);
+            }
+        }
+        if let Some(((c0, w0), (c1, w1))) = self.channels {
+            p.c = Some(lerp((c0, c1), t).round() as u32);
+            p.w = Some(lerp((w0, w1), t).round() as u32);
+        }
+
+        p
+    }
+
+    /// The exact target, sent once the time is up.
+    pub(crate) fn last(&self) -> SetPilotParams {
+        self.last.clone()
+    }
+}
+
+fn target_color(p: &SetPilotParams) -> Option<Color> {
+    match (p.r, p.g, p.b) {
+        (Some(r), Some(g), Some(b)) => Some(Color::rgb(r.min(255) as u8, g.min(255) as u8, b.min(255) as u8)),
+        _ => None,
+    }
+}
+
+fn mireds(kelvin: u32) -> f64 {
+    1_000_000.0 / kelvin.max(1) as f64
+}
+
+fn lerp((from, to): (f64, f64), t: f64) -> f64 {
+    from + (to - from) * t
+}
+
+/// `p` as if a bulb had reported it.
+pub(crate) fn as_reported(p: &SetPilotParams) -> GetPilotResult {
+    GetPilotResult {
+        state: p.state.unwrap_or(true),
+        dimming: p.dimming,
+        temp: p.temp,
+        r: p.r,
+        g: p.g,
+        b: p.b,
+        c: p.c,
+        w: p.w,
+        ..Default::default()
+    }
+}
+
+pub(crate) fn pilot(params: SetPilotParams) -> SetPilot {
+    SetPilot { params, ..Default::default() }
+}
+
+/// Reads where every bulb starts, then streams frames until `transition.duration` is up.
+pub(crate) async fn run(
+    bulbs: Vec<Bulb>,
+    transition: Transition,
+    clock: Arc<dyn Clock>,
+    interval: Duration,
+    status: Arc<Status>,
+) -> Outcome {
+    let mut fades = vec![];
+    for b in bulbs {
+        if let Some(outcome) = status.stopped() {
+            return outcome;
+        }
+        let (b, start) = tokio::task::spawn_blocking(move || {
+            let start = b.get_pilot();
+            (b, start)
+        })
+        .await
+        .unwrap();
+
+        let start = match start {
+            Ok(r) => Some(r.result),
+            Err(e) => {
+                info!("{} did not report its state, jumping to the target: {}", b.name, e);
+                None
+            }
+        };
+        let fade = Fade::new(b.dimming_curve().clone(), start.as_ref(), &transition.to);
+        fades.push((b, fade));
+    }
+
+    let (fades, stopped) = tick(clock.as_ref(), interval, &status, fades, move |fades, elapsed| {
+        if elapsed >= transition.duration {
+            return false;
+        }
+
+        let t = transition.easing.apply(elapsed.as_secs_f64() / transition.duration.as_secs_f64());
+        for (b, fade) in fades.iter_mut() {
+            if let Err(e) = b.send_pilot(pilot(fade.frame(t)), Delivery::FireAndForget) {
+                error!("{} dropped a frame: {}", b.name, e);
+            }
+        }
+        true
+    })
+    .await;
+
+    match stopped {
+        Some(outcome) => outcome,
+        None => finish(fades.into_iter().map(|(b, fade)| (b, fade.last())).collect()).await,
+    }
+}
+
+/// Sends every bulb its exact target with that bulb's own delivery.
+pub(crate) async fn finish(targets: Vec<(Bulb, SetPilotParams)>) -> Outcome {
+    let mut failure: Option<ErrorResponse> = None;
+    for (mut b, last) in targets {
+        let result = tokio::task::spawn_blocking(move || {
+            let delivery = b.delivery();
+            b.send_pilot(pilot(last), delivery)
+        })
+        .await
+        .unwrap();
+
+        if let Err(e) = result {
+            error!("final frame failed: {}", e);
+            failure = Some(e);
+        }
+    }
+
+    match failure {
+        Some(e) => Outcome::Failed(e),
+        None => Outcome::Completed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::effect::tests::Tap;
+    use crate::effect::{Engine, VirtualClock};
+    use crate::sim::VirtualBulb;
+
+    use super::*;
+
+    fn observed(state: bool, dimming: u32, temp: Option<u32>, rgb: Option<(u32, u32, u32)>) -> GetPilotResult {
+        GetPilotResult {
+            state,
+            dimming: Some(dimming),
+            temp,
+            r: rgb.map(|c| c.0),
+            g: rgb.map(|c| c.1),
+            b: rgb.map(|c| c.2),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    fn test_fade_in_from_off() {
+        let start = observed(false, 100, Some(2700), None);
+        let to = SetPilotParams { state: Some(true), dimming: Some(100), ..Default::default() };
+        let fade = Fade::new(DimmingCurve::Linear, Some(&start), &to);
+
+        assert_eq!(fade.frame(0.0).state, Some(false));
+        assert_eq!(fade.frame(0.5).dimming, Some(55));
+        assert_eq!(fade.frame(1.0).dimming, Some(100));
+    }
+
+    #[rstest]
+    fn test_temp_moves_in_mireds() {
+        let start = observed(true, 100, Some(2500), None);
+        let to = SetPilotParams { temp: Some(5000), ..Default::default() };
+
+        // halfway between 400 and 200 mireds
+        assert_eq!(Fade::new(DimmingCurve::Linear, Some(&start), &to).frame(0.5).temp, Some(3333));
+    }
+
+    #[rstest]
+    fn test_white_to_color_fades_through_color() {
+        let start = observed(true, 100, Some(2700), None);
+        let to = SetPilotParams { r: Some(0), g: Some(0), b: Some(255), ..Default::default() };
+        let fade = Fade::new(DimmingCurve::Linear, Some(&start), &to);
+
+        let first = fade.frame(0.0);
+        assert_eq!(target_color(&first), Some(kelvin_to_rgb(2700)));
+        assert_eq!(first.temp, None);
+        assert_eq!(target_color(&fade.frame(1.0)), Some(Color::rgb(0, 0, 255)));
+    }
+
+    #[rstest]
+    fn test_unknown_start_jumps() {
+        let to = SetPilotParams { dimming: Some(40), ..Default::default() };
+        let fade = Fade::new(DimmingCurve::Linear, None, &to);
+
+        assert_eq!(fade.frame(0.0), fade.frame(1.0));
+        assert_eq!(fade.last().state, Some(true));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_frame_rate() {
+        let sim = VirtualBulb::new().unwrap();
+        let tap = Arc::new(Tap::default());
+        let mut b = sim.bulb("foo", 1);
+        b.set_transport(tap.clone());
+        let mut engine = Engine::new(Arc::new(VirtualClock::new()));
+        engine.set_frame_rate(10.0);
+
+        let to = SetPilotParams { state: Some(true), dimming: Some(10), ..Default::default() };
+        engine.transition(&b, &Transition::new(to, Duration::from_secs(2))).join().await;
+
+        let fired = tap.fired.lock().unwrap();
+        assert_eq!(fired.len(), 20);
+        assert!(fired.windows(2).all(|w| w[0].params.dimming >= w[1].params.dimming));
+        assert_eq!(sim.state().dimming, 10);
+    }
+}
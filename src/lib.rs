@@ -1,4 +1,9 @@
+pub mod audio;
 pub mod bulb;
+pub mod circadian;
+pub mod color;
+pub mod effect;
 pub mod registry;
+pub mod sim;
 mod utils;
 mod function;
@@ -42,6 +42,10 @@ impl GraphStore for Bulb {
         self
     }
 
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
     fn eq(&self, etc: &dyn GraphStore) -> bool {
         match etc.as_any().downcast_ref::<Bulb>() {
             Some(other) => self == other,
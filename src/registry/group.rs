@@ -1,16 +1,22 @@
 use std::any::Any;
 use std::future::Future;
+use std::net::Ipv4Addr;
 use std::pin::Pin;
+use std::sync::Arc;
 
 use async_trait::async_trait;
+use log::error;
 use serde::{Deserialize, Serialize};
 use surrealdb::error::Db as SDb;
 use surrealdb::engine::any;
 use surrealdb::sql::Id;
 use surrealdb::Surreal;
 
-use crate::bulb::Bulb;
+use crate::bulb::{Bulb, Delivery, DimmingCurve, Snapshot};
+use crate::bulb::method::SetPilot;
 use crate::bulb::response::ErrorResponse;
+use crate::bulb::transport::Transport;
+use crate::color::{Color, Gradient};
 use crate::function::{Off, On};
 use crate::registry::Out;
 use crate::registry::surreal::{GraphStore, GraphLink};
@@ -20,6 +26,8 @@ pub struct Group {
     pub _id: Id,
     name: String,
     collects: Vec<Box<dyn GraphStore>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    broadcast: Option<Ipv4Addr>,
 }
 
 impl Group {
@@ -28,6 +36,213 @@ impl Group {
             _id: id,
             name,
             collects,
+            broadcast: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Send group commands as a single packet to `address` (e.g. `192.168.68.255`) instead of
+    /// one unicast per member. Every bulb on that subnet will follow the command, so this only
+    /// makes sense for groups that cover it.
+    pub fn set_broadcast(&mut self, address: Option<Ipv4Addr>) -> &mut Self {
+        self.broadcast = address;
+        self
+    }
+
+    /// Routes every member's traffic through `transport`. Broadcasts go through the first
+    /// member's transport, so this is how a broadcast group gets recorded too.
+    pub fn set_transport(&mut self, transport: Arc<dyn Transport>) -> &mut Self {
+        for b in self.bulbs_mut() {
+            b.set_transport(transport.clone());
+        }
+        self
+    }
+
+    /// Every bulb in the group, with nested groups flattened in order.
+    pub fn bulbs(&self) -> Vec<&Bulb> {
+        let mut bulbs = vec![];
+        for c in self.collects.iter() {
+            if let Some(b) = c.as_any().downcast_ref::<Bulb>() {
+                bulbs.push(b);
+            } else if let Some(g) = c.as_any().downcast_ref::<Group>() {
+                bulbs.extend(g.bulbs());
+            }
+        }
+
+        bulbs
+    }
+
+    pub fn bulbs_mut(&mut self) -> Vec<&mut Bulb> {
+        let mut bulbs = vec![];
+        for c in self.collects.iter_mut() {
+            let any = c.as_any_mut();
+            if any.is::<Bulb>() {
+                bulbs.push(any.downcast_mut::<Bulb>().unwrap());
+            } else if let Some(g) = any.downcast_mut::<Group>() {
+                bulbs.extend(g.bulbs_mut());
+            }
+        }
+
+        bulbs
+    }
+
+    /// Sends `p` to every member, waiting on each as `delivery` asks. A member that fails
+    /// doesn't stop the rest; the last error is returned once all have been tried. In
+    /// broadcast mode a fire-and-forget command is the single broadcast packet and nothing else.
+    pub fn send_pilot(&mut self, p: SetPilot, delivery: Delivery) -> Result<bool, ErrorResponse> {
+        self.send_pilot_with(p, |_| delivery)
+    }
+
+    /// Like [`Group::send_pilot`], but waits on each member as that bulb's own delivery asks.
+    pub fn send_pilot_each(&mut self, p: SetPilot) -> Result<bool, ErrorResponse> {
+        self.send_pilot_with(p, Bulb::delivery)
+    }
+
+    fn send_pilot_with(&mut self, p: SetPilot, delivery: impl Fn(&Bulb) -> Delivery) -> Result<bool, ErrorResponse> {
+        if let Some(address) = self.broadcast {
+            return self.broadcast_pilot(address, p, delivery);
+        }
+
+        let mut result = Ok(true);
+        for b in self.bulbs_mut() {
+            match b.send_pilot(p.clone(), delivery(b)) {
+                Ok(success) => result = result.map(|s| s && success),
+                Err(e) => {
+                    error!("{} did not take the command: {}", b.name, e);
+                    result = Err(e);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Gives every member the same dimming curve, e.g. [`DimmingCurve::for_module_name`] for a
+    /// group of one model.
+    pub fn set_dimming_curve(&mut self, curve: DimmingCurve) -> &mut Self {
+        for b in self.bulbs_mut() {
+            b.set_dimming_curve(curve.clone());
+        }
+        self
+    }
+
+    /// Sets every member to `level` through its own dimming curve. A broadcast group whose
+    /// members all share a curve sends the one packet instead. Otherwise a member that fails
+    /// doesn't stop the rest; the last error is returned once all have been tried.
+    pub fn set_level(&mut self, level: f64) -> Result<bool, ErrorResponse> {
+        let bulbs = self.bulbs();
+        let shared = bulbs.first().map(|b| b.dimming_curve().clone());
+        if let (Some(_), Some(curve)) = (self.broadcast, shared) {
+            if bulbs.iter().all(|b| *b.dimming_curve() == curve) {
+                let p = SetPilot::default().level(level, &curve).to_owned();
+                return self.send_pilot(p, Delivery::Acknowledged);
+            }
+        }
+
+        let mut result = Ok(true);
+        for b in self.bulbs_mut() {
+            match b.set_level(level) {
+                Ok(success) => result = result.map(|s| s && success),
+                Err(e) => {
+                    error!("{} did not take the level: {}", b.name, e);
+                    result = Err(e);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Spreads `gradient` from the first member to the last, in the order of [`Group::bulbs`],
+    /// sending each member its own color with that bulb's delivery. A member that fails doesn't
+    /// stop the rest; the last error is returned once all have been tried.
+    pub fn apply_gradient(&mut self, gradient: &Gradient) -> Result<bool, ErrorResponse> {
+        let colors = gradient.sample(self.bulbs().len());
+        self.apply_colors(colors)
+    }
+
+    /// Gives the members the colors of `palette` in turn, starting over when it runs out.
+    pub fn apply_palette(&mut self, palette: &[Color]) -> Result<bool, ErrorResponse> {
+        if palette.is_empty() {
+            return Ok(true);
+        }
+
+        let colors = (0..self.bulbs().len()).map(|i| palette[i % palette.len()]).collect();
+        self.apply_colors(colors)
+    }
+
+    fn apply_colors(&mut self, colors: Vec<Color>) -> Result<bool, ErrorResponse> {
+        let mut result = Ok(true);
+        for (b, color) in self.bulbs_mut().into_iter().zip(colors) {
+            let delivery = b.delivery();
+            match b.send_pilot(SetPilot::default().state(true).color(color).to_owned(), delivery) {
+                Ok(success) => result = result.map(|s| s && success),
+                Err(e) => {
+                    error!("{} did not take its color: {}", b.name, e);
+                    result = Err(e);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Reads back every member. One that doesn't answer is logged and left out, so it won't be
+    /// touched on restore; this only fails if none of them answer.
+    pub fn snapshot(&self) -> Result<Snapshot, ErrorResponse> {
+        Snapshot::capture(self.bulbs())
+    }
+
+    /// Puts every member that's in `snapshot` back how it was, each with its own delivery.
+    /// Carries on past a member that fails and returns the last error once the rest are done.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<bool, ErrorResponse> {
+        let mut result = Ok(true);
+        for b in self.bulbs_mut() {
+            match b.restore(snapshot) {
+                Ok(success) => result = result.map(|s| s && success),
+                Err(e) => {
+                    error!("{} could not be restored: {}", b.name, e);
+                    result = Err(e);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Broadcasts `p` once, then reads back every member whose `delivery` waits for an answer
+    /// and resends it to the ones that missed it. Returns the last error if a straggler
+    /// couldn't be brought in line.
+    fn broadcast_pilot(
+        &mut self,
+        address: Ipv4Addr,
+        p: SetPilot,
+        delivery: impl Fn(&Bulb) -> Delivery,
+    ) -> Result<bool, ErrorResponse> {
+        self.fire_broadcast(address, &p)?;
+
+        let mut result = Ok(true);
+        for b in self.bulbs_mut() {
+            let delivery = delivery(b);
+            if delivery == Delivery::FireAndForget {
+                continue;
+            }
+            if let Err(e) = b.confirm_pilot(&p, delivery) {
+                error!("{} did not follow broadcast: {}", b.name, e);
+                result = Err(e);
+            }
+        }
+
+        result
+    }
+
+    fn fire_broadcast(&self, address: Ipv4Addr, p: &SetPilot) -> Result<(), ErrorResponse> {
+        match self.bulbs().first() {
+            Some(b) => Bulb::broadcast_pilot_via(b.transport(), address, p),
+            None => Bulb::broadcast_pilot(address, p),
         }
     }
 
@@ -56,14 +271,18 @@ impl Group {
 
             let mut b = db.query(format!("SELECT * FROM group:{_id}", _id=group_id).as_str()).await?;
             let c: Option<String> = b.take((0, "name"))?;
+            let broadcast: Option<String> = b.take((0, "broadcast"))?;
 
             dbg!(&b);
             dbg!(&c);
-            Ok(Group::new(
+            let mut group = Group::new(
                 group_id,
                 c.unwrap(),
                 collected,
-            ))
+            );
+            group.set_broadcast(broadcast.and_then(|a| a.parse().ok()));
+
+            Ok(group)
         })
     }
 
@@ -77,7 +296,7 @@ impl PartialEq for Group {
         if self.name != other.name {
             return false
         }
-        else if self._id != other._id {
+        else if self._id != other._id || self.broadcast != other.broadcast {
             return false
         }
 
@@ -94,6 +313,10 @@ impl PartialEq for Group {
 
 impl On for Group {
     fn on(&mut self) -> Result<bool, ErrorResponse> {
+        if let Some(address) = self.broadcast {
+            return self.broadcast_pilot(address, SetPilot::default().state(true).to_owned(), |_| Delivery::Acknowledged);
+        }
+
         for i in self.collects.iter_mut() {
             i.on()?;
         }
@@ -104,6 +327,10 @@ impl On for Group {
 
 impl Off for Group {
     fn off(&mut self) -> Result<bool, ErrorResponse> {
+        if let Some(address) = self.broadcast {
+            return self.broadcast_pilot(address, SetPilot::default().state(false).to_owned(), |_| Delivery::Acknowledged);
+        }
+
         for i in self.collects.iter_mut() {
             i.off()?;
         }
@@ -123,14 +350,26 @@ impl GraphLink for Group {
 #[async_trait]
 #[typetag::serde]
 impl GraphStore for Group {
+    /// Saves the group and links its members. Storing it again replaces the old links rather
+    /// than adding a second set.
     async fn store(&self, db: &Surreal<any::Any>) -> surrealdb::Result<()> {
-        let query = format!(
-            "CREATE {tb_id} SET name = \"{name}\";",
-            tb_id = self.query_id_string(),
-            name = self.name.as_str(),
-        );
+        let query = match self.broadcast {
+            Some(address) => format!(
+                "CREATE {tb_id} SET name = \"{name}\", broadcast = \"{address}\";",
+                tb_id = self.query_id_string(),
+                name = self.name.as_str(),
+            ),
+            None => format!(
+                "CREATE {tb_id} SET name = \"{name}\";",
+                tb_id = self.query_id_string(),
+                name = self.name.as_str(),
+            ),
+        };
 
         let _ = db.query(query.as_str()).await?;
+        let _ = db
+            .query(format!("DELETE collect WHERE in = {tb_id};", tb_id = self.query_id_string()))
+            .await?;
 
         for c in self.collects.iter().clone() {
             let _ = match c.store(db).await {
@@ -158,6 +397,10 @@ impl GraphStore for Group {
         self
     }
 
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
     fn eq(&self, etc: &dyn GraphStore) -> bool {
         match etc.as_any().downcast_ref::<Group>() {
             Some(o) => self == o,
@@ -174,7 +417,8 @@ pub(crate) mod tests {
 
     use crate::bulb::Bulb;
     use crate::registry::tests::connect_to_memory_db;
-    use crate::bulb::tests::test_bulb;
+    use crate::bulb::tests::{test_bulb, test_sim};
+    use crate::sim::{VirtualBulb, VirtualNetwork};
 
     use super::*;
 
@@ -227,6 +471,26 @@ pub(crate) mod tests {
         // dbg!(collected_group);
     }
 
+    #[rstest]
+    #[tokio::test]
+    async fn test_store_group_twice(
+        #[from(test_bulb)]
+        #[with(Ipv4Addr::new(192, 168, 68, 1), 1)]
+        b1: Bulb,
+        #[from(test_bulb)]
+        #[with(Ipv4Addr::new(192, 168, 68, 1), 2)]
+        b2: Bulb,
+    ) {
+        let db = connect_to_memory_db().await;
+        db.use_ns("test").use_db("test").await.unwrap();
+        let test_group = Group::new(Id::from(69), "test_bulb".to_string(), vec!(Box::new(b1), Box::new(b2)));
+
+        test_group.store(&db).await.unwrap();
+        test_group.store(&db).await.unwrap();
+
+        assert_eq!(Group::collect(Id::from(69), &db).await.unwrap().collects.len(), 2);
+    }
+
     #[rstest]
     #[tokio::test]
     async fn test_create_nested_group(
@@ -268,26 +532,174 @@ pub(crate) mod tests {
         assert_eq!(test_group, collected_group)
     }
 
-    #[rstest] fn test_group_off(test_bulb: Bulb) {
+    #[rstest] fn test_group_off(test_sim: VirtualBulb) {
         let mut g = Group::new(
             Id::from(22),
             "deez".to_string(),
-            vec!(Box::new(test_bulb)),
+            vec!(Box::new(test_sim.bulb("test_bulb_0", 0))),
         );
 
         assert!(g.off().unwrap());
+        assert!(!test_sim.state().state);
     }
 
-    #[rstest] fn test_group_on(test_bulb: Bulb) {
+    #[rstest] fn test_group_on(test_sim: VirtualBulb) {
         let mut g = Group::new(
             Id::from(22),
             "deez".to_string(),
-            vec!(Box::new(test_bulb)),
+            vec!(Box::new(test_sim.bulb("test_bulb_0", 0))),
         );
 
         assert!(g.on().unwrap());
+        assert!(test_sim.state().state);
     }
     
+    #[rstest] fn test_group_broadcast_on() {
+        let net = VirtualNetwork::new(3).unwrap();
+        let mut g = VirtualBulb::group(net.bulbs(), Id::from(22), "deez");
+        g.set_broadcast(Some(net.broadcast()));
+
+        assert!(g.on().unwrap());
+
+        assert!(net.bulbs().iter().all(|s| s.state().state));
+        assert!(g.bulbs().iter().all(|b| b.state));
+    }
+
+    #[rstest] fn test_group_send_pilot_fire_and_forget() {
+        let sims = VirtualBulb::many(2).unwrap();
+        let mut g = VirtualBulb::group(&sims, Id::from(22), "deez");
+        let p = SetPilot::default().state(true).brightness(30).to_owned();
+
+        assert!(g.send_pilot(p, Delivery::FireAndForget).unwrap());
+
+        for b in g.bulbs() {
+            assert_eq!(b.get_pilot().unwrap().result.dimming, Some(30));
+        }
+    }
+
+    #[rstest] fn test_group_on_with_offline_member() {
+        let online = VirtualBulb::new().unwrap();
+        let mut offline = VirtualBulb::new().unwrap();
+        offline.go_offline();
+        let mut g = Group::new(
+            Id::from(22),
+            "deez".to_string(),
+            vec!(Box::new(online.bulb("online", 1)), Box::new(offline.bulb("offline", 2))),
+        );
+
+        assert!(g.on().is_err());
+        assert!(online.state().state);
+    }
+
+    #[rstest] fn test_send_pilot_past_offline_member() {
+        let mut sims = VirtualBulb::many(3).unwrap();
+        let mut g = VirtualBulb::group(&sims, Id::from(22), "deez");
+        sims[1].go_offline();
+        let p = SetPilot::default().state(true).brightness(40).to_owned();
+
+        assert!(g.send_pilot(p, Delivery::Acknowledged).is_err());
+
+        assert_eq!(sims[0].state().dimming, 40);
+        assert_eq!(sims[2].state().dimming, 40);
+    }
+
+    #[rstest] fn test_set_level_uses_each_members_curve() {
+        let sims = VirtualBulb::many(2).unwrap();
+        let mut linear = sims[0].bulb("bar", 0);
+        linear.set_dimming_curve(DimmingCurve::Linear);
+        let mut lightness = sims[1].bulb("bar", 1);
+        lightness.set_dimming_curve(DimmingCurve::CieLightness);
+        let mut g = Group::new(Id::from(22), "bar".to_string(), vec![Box::new(linear), Box::new(lightness)]);
+
+        assert!(g.set_level(0.5).unwrap());
+
+        assert_eq!(sims[0].state().dimming, 55);
+        assert_eq!(sims[1].state().dimming, 27);
+        assert!((g.bulbs()[1].get_level().unwrap() - 0.5).abs() < 0.05);
+    }
+
+    #[rstest] fn test_set_level_past_offline_member() {
+        let mut sims = VirtualBulb::many(3).unwrap();
+        let mut g = VirtualBulb::group(&sims, Id::from(22), "bar");
+        sims[0].go_offline();
+
+        assert!(g.set_level(0.5).is_err());
+
+        assert_eq!(sims[1].state().dimming, 55);
+        assert_eq!(sims[2].state().dimming, 55);
+    }
+
+    #[rstest] fn test_apply_gradient() {
+        let sims = VirtualBulb::many(3).unwrap();
+        let mut g = VirtualBulb::group(&sims, Id::from(22), "bar");
+
+        assert!(g.apply_gradient(&Gradient::new(&[Color::rgb(255, 0, 0), Color::rgb(0, 0, 255)])).unwrap());
+
+        assert_eq!(sims[0].state().rgb, Some((255, 0, 0)));
+        assert_eq!(sims[2].state().rgb, Some((0, 0, 255)));
+        let (r, _, b) = sims[1].state().rgb.unwrap();
+        assert!(r > 0 && b > 0);
+    }
+
+    #[rstest] fn test_apply_palette_wraps() {
+        let sims = VirtualBulb::many(3).unwrap();
+        let mut g = VirtualBulb::group(&sims, Id::from(22), "bar");
+
+        assert!(g.apply_palette(&[Color::rgb(255, 0, 0), Color::rgb(0, 255, 0)]).unwrap());
+
+        assert_eq!(sims[0].state().rgb, Some((255, 0, 0)));
+        assert_eq!(sims[1].state().rgb, Some((0, 255, 0)));
+        assert_eq!(sims[2].state().rgb, Some((255, 0, 0)));
+    }
+
+    #[rstest] fn test_apply_gradient_past_offline_member() {
+        let mut sims = VirtualBulb::many(3).unwrap();
+        let mut g = VirtualBulb::group(&sims, Id::from(22), "bar");
+        sims[0].go_offline();
+
+        assert!(g.apply_gradient(&Gradient::new(&[Color::rgb(255, 0, 0), Color::rgb(0, 0, 255)])).is_err());
+
+        assert!(sims[1].state().rgb.is_some());
+        assert_eq!(sims[2].state().rgb, Some((0, 0, 255)));
+    }
+
+    #[rstest] fn test_snapshot_restore() {
+        let sims = VirtualBulb::many(2).unwrap();
+        let mut g = VirtualBulb::group(&sims, Id::from(22), "bar");
+        g.apply_palette(&[Color::rgb(255, 0, 0), Color::rgb(0, 255, 0)]).unwrap();
+        g.bulbs_mut()[1].off().unwrap();
+        let before: Vec<_> = sims.iter().map(|s| s.state()).collect();
+
+        let snapshot = g.snapshot().unwrap();
+        g.send_pilot(SetPilot::default().state(true).color(Color::WHITE).to_owned(), Delivery::Acknowledged).unwrap();
+        assert!(g.restore(&snapshot).unwrap());
+
+        assert_eq!(sims.iter().map(|s| s.state()).collect::<Vec<_>>(), before);
+    }
+
+    #[rstest]
+    fn test_bulbs_flattens_nested_groups(
+        #[from(test_bulb)]
+        #[with(Ipv4Addr::new(192, 168, 68, 1), 1)]
+        b1: Bulb,
+        #[from(test_bulb)]
+        #[with(Ipv4Addr::new(192, 168, 68, 1), 2)]
+        b2: Bulb,
+    ) {
+        let nested_group = Group::new(
+            Id::from(420),
+            "nested_group".to_string(),
+            vec!(Box::new(b2.clone())),
+        );
+        let g = Group::new(
+            Id::from(69),
+            "test_group".to_string(),
+            vec!(Box::new(b1.clone()), Box::new(nested_group)),
+        );
+
+        assert_eq!(g.bulbs(), vec![&b1, &b2]);
+    }
+
     #[rstest]
     fn test_deserialize_group(test_group: Group) {
         println!("{}", serde_json::to_string(&test_group).unwrap());
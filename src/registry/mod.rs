@@ -1,10 +1,15 @@
 mod bulb;
 mod group;
+mod reconcile;
+mod scene;
+mod snapshot;
 mod surreal;
 
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::fmt::Formatter;
+use std::net::{IpAddr, Ipv4Addr};
 
 use serde::{Deserialize, Serialize};
 use surrealdb::engine::any::Any;
@@ -12,10 +17,16 @@ use surrealdb::sql::{Id, Thing};
 use surrealdb::Surreal;
 use url::Url;
 
-use crate::bulb::Bulb;
+use crate::bulb::response::ErrorResponse;
+use crate::bulb::{Bulb, SetPilot, SetPilotParams, Snapshot};
+use crate::color::Color;
+use crate::effect::{Engine, Handle, Player, Routine, Timeline, TimelineError, TrackTarget};
 use crate::function::FunctionError;
 use crate::function::*;
-use group::Group;
+pub use group::Group;
+pub use reconcile::{Action, Desired, Policy, Reconciled, Reconciler};
+pub use scene::Scene;
+use surreal::GraphLink;
 pub use surreal::{connect_to_db, GraphStore};
 
 #[derive(Debug, Clone)]
@@ -41,6 +52,8 @@ pub struct Registry {
     db: Surreal<Any>,
     bulbs: Vec<Bulb>,
     groups: Vec<Group>,
+    /// Desired state by the record id of its bulb or group, e.g. `group:7`.
+    desired: HashMap<String, Desired>,
 }
 
 impl Registry {
@@ -49,8 +62,14 @@ impl Registry {
 
         let bulbs = get_bulbs_from_db(&db).await.unwrap_or(vec![]);
         let groups = get_groups_from_db(&db).await.unwrap_or(vec![]);
-
-        Registry { db, bulbs, groups }
+        let desired = Desired::get_all(&db)
+            .await
+            .unwrap_or(vec![])
+            .into_iter()
+            .map(|d| (d.target.clone(), d))
+            .collect();
+
+        Registry { db, bulbs, groups, desired }
     }
 
     pub async fn new_from_url(url: Url) -> Registry {
@@ -129,9 +148,261 @@ impl Registry {
             MissingElementError { _id: id }.to_string(),
         ))
     }
+
+    /// Spreads `palette` over the members of the group with `id`, e.g. colors taken from a
+    /// photo with [`crate::color::image_palette`], and saves the look as a scene called
+    /// `save_as` if one is given.
+    pub async fn apply_palette_by_id(
+        &mut self,
+        id: Id,
+        palette: &[Color],
+        save_as: Option<&str>,
+    ) -> Result<bool, FunctionError> {
+        let group = match self.groups.iter_mut().find(|g| g._id == id) {
+            Some(g) => g,
+            None => {
+                return Err(FunctionError::new(
+                    "apply_palette_by_id".to_string(),
+                    MissingElementError { _id: id }.to_string(),
+                ))
+            }
+        };
+
+        let success = group
+            .apply_palette(palette)
+            .map_err(|e| FunctionError::new("ApplyPalette".to_string(), e.to_string()))?;
+
+        if let Some(name) = save_as {
+            Scene::from_palette(name.to_string(), group, palette)
+                .store(&self.db)
+                .await
+                .map_err(|e| FunctionError::new("SaveScene".to_string(), e.to_string()))?;
+        }
+
+        Ok(success)
+    }
+
+    pub async fn get_scene(&self, name: &str) -> surrealdb::Result<Option<Scene>> {
+        Scene::get(&self.db, name).await
+    }
+
+    /// Shows the scene saved as `name` on all of its targets at once, returning how each went
+    /// by record id. Fails only if the scene can't be read.
+    pub async fn activate_scene(&self, name: &str) -> Result<Vec<(String, Result<bool, ErrorResponse>)>, FunctionError> {
+        let err = |e: String| FunctionError::new("ActivateScene".to_string(), e);
+        let mut scene = self
+            .get_scene(name)
+            .await
+            .map_err(|e| err(e.to_string()))?
+            .ok_or_else(|| err(format!("no scene called {}", name)))?;
+
+        Ok(tokio::task::spawn_blocking(move || scene.activate()).await.unwrap())
+    }
+
+    /// Captures the bulb or group with `id` as it is now and saves it as `name`, e.g. before a
+    /// doorbell flash, for [`Registry::restore_snapshot`] to put back afterwards.
+    pub async fn snapshot_by_id(&self, id: Id, name: &str) -> Result<Snapshot, FunctionError> {
+        let members: Vec<Bulb> = if let Some(b) = self.bulbs.iter().find(|b| Id::from(b._id as i32) == id) {
+            vec![b.clone()]
+        } else if let Some(g) = self.groups.iter().find(|g| g._id == id) {
+            g.bulbs().into_iter().cloned().collect()
+        } else {
+            return Err(FunctionError::new(
+                "snapshot_by_id".to_string(),
+                MissingElementError { _id: id }.to_string(),
+            ));
+        };
+
+        let snapshot = tokio::task::spawn_blocking(move || Snapshot::capture(&members))
+            .await
+            .unwrap()
+            .map_err(|e| FunctionError::new("Snapshot".to_string(), e.to_string()))?;
+
+        snapshot
+            .store(&self.db, name)
+            .await
+            .map_err(|e| FunctionError::new("SaveSnapshot".to_string(), e.to_string()))?;
+
+        Ok(snapshot)
+    }
+
+    /// Puts every registered bulb in the snapshot saved as `name` back how it was. Bulbs are
+    /// matched by MAC, so one that has moved address since is still found; one that doesn't
+    /// answer only counts as a failure if its address is in the snapshot.
+    pub async fn restore_snapshot(&mut self, name: &str) -> Result<bool, FunctionError> {
+        let err = |e: String| FunctionError::new("RestoreSnapshot".to_string(), e);
+        let snapshot = Snapshot::get(&self.db, name)
+            .await
+            .map_err(|e| err(e.to_string()))?
+            .ok_or_else(|| err(format!("no snapshot called {}", name)))?;
+
+        let (mut bulbs, mut groups) = (std::mem::take(&mut self.bulbs), std::mem::take(&mut self.groups));
+        let (bulbs, groups, result) = tokio::task::spawn_blocking(move || {
+            let mut restored = vec![];
+            let mut result = Ok(true);
+            let members = groups.iter_mut().flat_map(|g| g.bulbs_mut());
+            for b in bulbs.iter_mut().chain(members) {
+                let mac = match b.mac() {
+                    Ok(mac) => mac,
+                    Err(e) => {
+                        if snapshot.bulbs.iter().any(|s| s.ip == b.ip()) {
+                            result = Err(err(e.to_string()));
+                        }
+                        continue;
+                    }
+                };
+                let pilot = match snapshot.pilot(&mac, b.ip()) {
+                    Some(pilot) if !restored.contains(&mac) => pilot,
+                    _ => continue,
+                };
+                match b.restore_pilot(pilot) {
+                    Ok(success) => result = result.map(|s| s && success),
+                    Err(e) => result = Err(err(e.to_string())),
+                }
+                restored.push(mac);
+            }
+            (bulbs, groups, result)
+        })
+        .await
+        .unwrap();
+        (self.bulbs, self.groups) = (bulbs, groups);
+
+        result
+    }
+
+    /// Sends `pilot` to the bulb or group with `id`, each bulb with its own delivery, and keeps
+    /// it as the state a [`Reconciler`] holds it in. The desired state is saved even if the send fails, so an unreachable bulb
+    /// gets it once it's back.
+    pub async fn set_desired_by_id(&mut self, id: Id, pilot: SetPilotParams) -> Result<bool, FunctionError> {
+        let target = self.record_id(&id).ok_or_else(|| {
+            FunctionError::new("set_desired_by_id".to_string(), MissingElementError { _id: id.clone() }.to_string())
+        })?;
+
+        let desired = self.desired.entry(target.clone()).or_insert_with(|| Desired::new(target));
+        desired.pilot = pilot.clone();
+        desired
+            .store(&self.db)
+            .await
+            .map_err(|e| FunctionError::new("SaveDesired".to_string(), e.to_string()))?;
+
+        let p = SetPilot { params: pilot, ..Default::default() };
+        let result = if let Some(i) = self.bulbs.iter().position(|b| Id::from(b._id as i32) == id) {
+            let mut b = self.bulbs.remove(i);
+            let (b, result) = tokio::task::spawn_blocking(move || {
+                let delivery = b.delivery();
+                let result = b.send_pilot(p, delivery);
+                (b, result)
+            })
+            .await
+            .unwrap();
+            self.bulbs.insert(i, b);
+            result
+        } else {
+            let i = self.groups.iter().position(|g| g._id == id).unwrap();
+            let mut g = self.groups.remove(i);
+            let (g, result) = tokio::task::spawn_blocking(move || {
+                let result = g.send_pilot_each(p);
+                (g, result)
+            })
+            .await
+            .unwrap();
+            self.groups.insert(i, g);
+            result
+        };
+
+        result.map_err(|e| FunctionError::new("SetDesired".to_string(), e.to_string()))
+    }
+
+    /// Sets how a [`Reconciler`] treats drift on the bulb or group with `id`.
+    pub async fn set_policy_by_id(&mut self, id: Id, policy: Policy) -> Result<(), FunctionError> {
+        let target = self.record_id(&id).ok_or_else(|| {
+            FunctionError::new("set_policy_by_id".to_string(), MissingElementError { _id: id.clone() }.to_string())
+        })?;
+
+        let desired = self.desired.entry(target.clone()).or_insert_with(|| Desired::new(target));
+        desired.policy = policy;
+        desired
+            .store(&self.db)
+            .await
+            .map_err(|e| FunctionError::new("SaveDesired".to_string(), e.to_string()))
+    }
+
+    pub fn desired_by_id(&self, id: Id) -> Option<&Desired> {
+        self.record_id(&id).and_then(|target| self.desired.get(&target))
+    }
+
+    /// The record id, e.g. `bulb:3`, of the bulb or group with `id`.
+    fn record_id(&self, id: &Id) -> Option<String> {
+        if let Some(b) = self.bulbs.iter().find(|b| Id::from(b._id as i32) == *id) {
+            Some(b.query_id_string())
+        } else {
+            self.groups.iter().find(|g| g._id == *id).map(|g| g.query_id_string())
+        }
+    }
+
+    /// Starts `routine` on the bulb or group with `id` through `engine`, superseding anything
+    /// the engine was already running there.
+    pub fn start_routine_by_id(&self, engine: &Engine, id: Id, routine: &Routine) -> Result<Handle, FunctionError> {
+        if let Some(b) = self.bulbs.iter().find(|b| Id::from(b._id as i32) == id) {
+            return Ok(engine.run_routine(b, routine));
+        }
+        if let Some(g) = self.groups.iter().find(|g| g._id == id) {
+            return Ok(engine.run_routine(g, routine));
+        }
+
+        Err(FunctionError::new(
+            "start_routine_by_id".to_string(),
+            MissingElementError { _id: id }.to_string(),
+        ))
+    }
+
+    /// The bulbs a timeline track names, found by bulb or group name; pass it to
+    /// [`crate::effect::Preview::timeline`] to preview a timeline on the registered bulbs.
+    pub fn track_bulbs(&self, target: &TrackTarget) -> Option<Vec<Bulb>> {
+        match target {
+            TrackTarget::Bulb(name) => self.bulbs.iter().find(|b| &b.name == name).map(|b| vec![b.clone()]),
+            TrackTarget::Group(name) => self
+                .groups
+                .iter()
+                .find(|g| g.name() == name)
+                .map(|g| g.bulbs().into_iter().cloned().collect()),
+        }
+    }
+
+    /// Plays `timeline` through `engine` on the bulbs and groups its tracks name.
+    pub fn play_timeline(&self, engine: &Engine, timeline: &Timeline) -> Result<Player, TimelineError> {
+        engine.play_timeline(timeline, |target| self.track_bulbs(target))
+    }
+
+    /// Addresses answering on `broadcast` that no registered bulb points at.
+    pub fn detect_unknown_bulbs_on_network(&self, broadcast: Ipv4Addr) -> Vec<Ipv4Addr> {
+        Bulb::discover_on(broadcast)
+            .into_iter()
+            .filter(|ip| !self.bulbs.iter().any(|b| b.ip() == IpAddr::V4(*ip)))
+            .collect()
+    }
+
+    /// Adds every unknown bulb answering on `broadcast`, named after its MAC and numbered
+    /// after the highest id already registered.
+    pub async fn enroll_unknown_bulbs(&mut self, broadcast: Ipv4Addr) -> surrealdb::Result<Vec<Bulb>> {
+        let mut next_id = self.bulbs.iter().map(|b| b._id + 1).max().unwrap_or(0);
+        let mut enrolled = vec![];
+
+        for (ip, mac) in Bulb::survey(broadcast) {
+            if self.bulbs.iter().any(|b| b.ip() == IpAddr::V4(ip)) {
+                continue;
+            }
+
+            let b = Bulb::new(IpAddr::V4(ip), format!("wiz_{}", mac), next_id);
+            next_id += 1;
+            self.add(Box::new(b.clone())).await?;
+            enrolled.push(b);
+        }
+
+        Ok(enrolled)
+    }
     // fn add_bulb() -> surrealdb::Result<()> {};
     // fn add_group() -> surrealdb::Result<()> {};
-    // fn detect_unknown_bulbs_on_network() -> Option<Vec<Ipv4Addr>> {};
 }
 
 async fn get_bulbs_from_db(db: &Surreal<Any>) -> surrealdb::Result<Vec<Bulb>> {
@@ -172,17 +443,15 @@ impl Error for GeneralError {}
 pub mod tests {
     // use std::net::{IpAddr, Ipv4Addr};
     use super::*;
-    use crate::bulb::tests::test_bulb;
-    use crate::registry::group::tests::test_group;
+    use crate::bulb::tests::{test_bulb, test_sim};
+    use crate::bulb::{Delivery, SetPilot};
+    use crate::sim::{FaultProfile, VirtualBulb, VirtualNetwork};
     use rstest::rstest;
     use surrealdb::engine::any::Any;
     use surrealdb::Surreal;
 
     pub async fn connect_to_memory_db() -> Surreal<Any> {
-        match surrealdb::engine::any::connect("ws://localhost:8000").await {
-            Ok(db) => db,
-            Err(e) => panic!("Issue connecting to localhost:8000 - {}", e),
-        }
+        create_memory_db().await
     }
 
     // TODO: figure out how to compare small sections to what is in git
@@ -197,7 +466,7 @@ pub mod tests {
     #[rstest]
     #[tokio::test]
     async fn test_create_registry() {
-        let url = Url::parse("ws://localhost:8000").unwrap();
+        let url = Url::parse("mem://").unwrap();
         let t_registry = Registry::new_from_url(url).await;
 
         dbg!(t_registry);
@@ -210,6 +479,7 @@ pub mod tests {
             db: create_memory_db().await,
             bulbs: vec![test_bulb.clone()],
             groups: vec![],
+            desired: HashMap::new(),
         };
 
         assert_eq!(
@@ -222,12 +492,14 @@ pub mod tests {
 
     #[rstest]
     #[tokio::test]
-    async fn test_turn_on_bulb_by_id(test_bulb: Bulb) {
+    async fn test_turn_on_bulb_by_id(test_sim: VirtualBulb) {
+        let test_bulb = test_sim.bulb("test_bulb_0", 0);
         let t_id = Id::from(test_bulb._id.clone() as i32);
         let mut registry = Registry {
             db: create_memory_db().await,
             bulbs: vec![test_bulb],
             groups: vec![],
+            desired: HashMap::new(),
         };
 
         let res = registry.turn_on_by_id(t_id).unwrap();
@@ -237,12 +509,14 @@ pub mod tests {
 
     #[rstest]
     #[tokio::test]
-    async fn test_turn_off_bulb_by_id(test_bulb: Bulb) {
+    async fn test_turn_off_bulb_by_id(test_sim: VirtualBulb) {
+        let test_bulb = test_sim.bulb("test_bulb_0", 0);
         let t_id = Id::from(test_bulb._id.clone() as i32);
         let mut registry = Registry {
             db: create_memory_db().await,
             bulbs: vec![test_bulb],
             groups: vec![],
+            desired: HashMap::new(),
         };
 
         let res = registry.turn_off_by_id(t_id).unwrap();
@@ -252,12 +526,178 @@ pub mod tests {
 
     #[rstest]
     #[tokio::test]
-    async fn test_turn_on_group_by_id(test_group: Group) {
+    async fn test_turn_on_flaky_bulb_by_id() {
+        let sim = VirtualBulb::new().unwrap();
+        sim.set_faults(FaultProfile { seed: 1, error_rate: 1.0, ..Default::default() });
+        let mut registry = Registry {
+            db: create_memory_db().await,
+            bulbs: vec![sim.bulb("flaky", 5)],
+            groups: vec![],
+            desired: HashMap::new(),
+        };
+
+        let e = registry.turn_on_by_id(Id::from(5)).unwrap_err();
+
+        assert!(e.to_string().starts_with("An error occurred during WizFunction On"));
+        assert!(!sim.state().state);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_apply_palette_by_id_saves_scene() {
+        let sims = VirtualBulb::many(3).unwrap();
+        let db = create_memory_db().await;
+        db.use_ns("test").use_db("test").await.unwrap();
+        let group = VirtualBulb::group(&sims, Id::from(12), "living");
+        let mut registry = Registry { db, bulbs: vec![], groups: vec![group], desired: HashMap::new() };
+        let palette = [Color::rgb(255, 140, 0), Color::rgb(0, 90, 40)];
+
+        assert!(registry.apply_palette_by_id(Id::from(12), &palette, Some("photo")).await.unwrap());
+
+        assert_eq!(sims[2].state().rgb, Some((255, 140, 0)));
+        let scene = registry.get_scene("photo").await.unwrap().unwrap();
+        assert_eq!(scene.targets().len(), 3);
+        let (target, params) = &scene.targets()[1];
+        assert_eq!(target.query_id_string(), "bulb:1");
+        assert_eq!(params, &SetPilot::default().state(true).color(palette[1]).to_owned().params);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_snapshot_survives_in_db() {
+        let sims = VirtualBulb::many(2).unwrap();
+        let db = create_memory_db().await;
+        db.use_ns("test").use_db("test").await.unwrap();
+        let group = VirtualBulb::group(&sims, Id::from(12), "hall");
+        let mut registry = Registry { db, bulbs: vec![], groups: vec![group], desired: HashMap::new() };
+        registry.apply_palette_by_id(Id::from(12), &[Color::rgb(0, 40, 255)], None).await.unwrap();
+        let before = sims[1].state();
+
+        registry.snapshot_by_id(Id::from(12), "doorbell").await.unwrap();
+        registry.turn_off_by_id(Id::from(12)).unwrap();
+        assert!(registry.restore_snapshot("doorbell").await.unwrap());
+
+        assert_eq!(sims[1].state(), before);
+        assert_eq!(registry.groups.len(), 1);
+        assert!(registry.restore_snapshot("missing").await.is_err());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_restore_snapshot_after_bulb_moves() {
+        let mut sims = VirtualBulb::many(2).unwrap();
+        let db = create_memory_db().await;
+        db.use_ns("test").use_db("test").await.unwrap();
+        let group = VirtualBulb::group(&sims, Id::from(12), "hall");
+        let mut registry = Registry { db, bulbs: vec![], groups: vec![group], desired: HashMap::new() };
+        registry.apply_palette_by_id(Id::from(12), &[Color::rgb(0, 40, 255)], None).await.unwrap();
+        let before = sims[1].state();
+
+        registry.snapshot_by_id(Id::from(12), "doorbell").await.unwrap();
+        registry.turn_off_by_id(Id::from(12)).unwrap();
+        sims[1].come_back(true).unwrap();
+        registry.groups[0] = VirtualBulb::group(&sims, Id::from(12), "hall");
+
+        assert!(registry.restore_snapshot("doorbell").await.unwrap());
+        assert_eq!(sims[1].state(), before);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_set_desired_uses_member_delivery() {
+        let sims = VirtualBulb::many(2).unwrap();
+        sims[1].set_faults(FaultProfile { loss: 1.0, ..Default::default() });
+        let db = create_memory_db().await;
+        db.use_ns("test").use_db("test").await.unwrap();
+        let mut group = VirtualBulb::group(&sims, Id::from(12), "hall");
+        group.bulbs_mut()[1].set_delivery(Delivery::FireAndForget);
+        let mut registry = Registry { db, bulbs: vec![], groups: vec![group], desired: HashMap::new() };
+        let pilot = SetPilotParams { state: Some(true), dimming: Some(30), ..Default::default() };
+
+        assert!(registry.set_desired_by_id(Id::from(12), pilot).await.unwrap());
+        assert_eq!(sims[0].state().dimming, 30);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_activate_scene() {
+        let sims = VirtualBulb::many(3).unwrap();
+        let db = create_memory_db().await;
+        db.use_ns("test").use_db("test").await.unwrap();
+        let group = Group::new(
+            Id::from(7),
+            "shelf".to_string(),
+            vec![Box::new(sims[1].bulb("left", 1)), Box::new(sims[2].bulb("right", 2))],
+        );
+        let warm = SetPilot::default().state(true).temperature(2700).to_owned().params;
+        let blue = SetPilot::default().state(true).color(Color::rgb(0, 0, 255)).to_owned().params;
+        let mut registry = Registry { db, bulbs: vec![], groups: vec![], desired: HashMap::new() };
+        registry
+            .add(Box::new(Scene::new(
+                "movie".to_string(),
+                vec![(Box::new(sims[0].bulb("ceiling", 0)), warm), (Box::new(group), blue)],
+            )))
+            .await
+            .unwrap();
+
+        let results = registry.activate_scene("movie").await.unwrap();
+
+        let ids: Vec<_> = results.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, vec!["bulb:0", "group:7"]);
+        assert!(results.iter().all(|(_, r)| *r.as_ref().unwrap()));
+        assert_eq!(sims[0].state().temp, Some(2700));
+        assert_eq!(sims[2].state().rgb, Some((0, 0, 255)));
+        assert!(registry.activate_scene("nope").await.is_err());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_start_routine_by_id() {
+        let sim = VirtualBulb::new().unwrap();
+        let registry = Registry {
+            db: create_memory_db().await,
+            bulbs: vec![sim.bulb("bedroom", 3)],
+            groups: vec![],
+            desired: HashMap::new(),
+        };
+        let engine = Engine::new(std::sync::Arc::new(crate::effect::VirtualClock::new()));
+        let sunrise = Routine::sunrise(std::time::Duration::from_secs(30));
+
+        assert!(registry.start_routine_by_id(&engine, Id::from(4), &sunrise).is_err());
+        registry.start_routine_by_id(&engine, Id::from(3), &sunrise).unwrap().join().await;
+
+        assert_eq!(sim.state().temp, Some(4000));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_enroll_unknown_bulbs(test_bulb: Bulb) {
+        let net = VirtualNetwork::new(3).unwrap();
+        let mut registry = Registry::new(create_memory_db().await).await;
+        registry.add(Box::new(test_bulb)).await.unwrap();
+        registry.add(Box::new(net.bulbs()[0].bulb("known", 7))).await.unwrap();
+
+        let unknown = registry.detect_unknown_bulbs_on_network(net.broadcast());
+        assert_eq!(unknown.len(), 2);
+
+        let enrolled = registry.enroll_unknown_bulbs(net.broadcast()).await.unwrap();
+        let mut ids: Vec<_> = enrolled.iter().map(|b| b._id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![8, 9]);
+        assert_eq!(registry.bulbs.len(), 4);
+        assert!(registry.detect_unknown_bulbs_on_network(net.broadcast()).is_empty());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_turn_on_group_by_id(test_sim: VirtualBulb) {
+        let test_group = Group::new(Id::from(69), "test_group_69".to_string(), vec![Box::new(test_sim.bulb("test_bulb_0", 0))]);
         let t_id = test_group._id.clone();
         let mut registry = Registry {
             db: create_memory_db().await,
             bulbs: vec![],
             groups: vec![test_group],
+            desired: HashMap::new(),
         };
 
         let res = registry.turn_on_by_id(t_id).unwrap();
@@ -267,12 +707,14 @@ pub mod tests {
 
     #[rstest]
     #[tokio::test]
-    async fn test_turn_off_group_by_id(test_group: Group) {
+    async fn test_turn_off_group_by_id(test_sim: VirtualBulb) {
+        let test_group = Group::new(Id::from(69), "test_group_69".to_string(), vec![Box::new(test_sim.bulb("test_bulb_0", 0))]);
         let t_id = test_group._id.clone();
         let mut registry = Registry {
             db: create_memory_db().await,
             bulbs: vec![],
             groups: vec![test_group],
+            desired: HashMap::new(),
         };
 
         let res = registry.turn_off_by_id(t_id).unwrap();
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use surrealdb::engine::any;
+use surrealdb::Surreal;
+
+use crate::bulb::response::ErrorResponse;
+use crate::bulb::{Bulb, SetPilot, SetPilotParams};
+use crate::effect::Clock;
+use crate::registry::surreal::GraphLink;
+use crate::registry::Registry;
+
+/// What the [`Reconciler`] does about bulbs that don't show their desired state.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Policy {
+    /// Puts them back whenever they drift, whoever changed them.
+    #[default]
+    Enforce,
+    /// Only puts back bulbs that went unreachable and came back, as after a power cut, and
+    /// leaves changes made at the wall switch or in the app alone.
+    Reappear,
+    /// Reports drift without touching anything.
+    Observe,
+}
+
+/// The state a bulb or group should be in, and how hard to hold it there.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Desired {
+    /// Record id of the bulb or group, e.g. `group:7`.
+    pub target: String,
+    pub pilot: SetPilotParams,
+    #[serde(default)]
+    pub policy: Policy,
+}
+
+impl Desired {
+    pub fn new(target: String) -> Desired {
+        Desired {
+            target,
+            pilot: SetPilotParams::default(),
+            policy: Policy::default(),
+        }
+    }
+
+    pub async fn store(&self, db: &Surreal<any::Any>) -> surrealdb::Result<()> {
+        let _: Option<Desired> = db.update(("desired", self.target.as_str())).content(self).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_all(db: &Surreal<any::Any>) -> surrealdb::Result<Vec<Desired>> {
+        db.select("desired").await
+    }
+}
+
+/// What a reconcile pass did about one bulb.
+#[derive(Debug)]
+pub enum Action {
+    InSync,
+    /// Didn't answer; it's re-checked next pass.
+    Unreachable,
+    /// Had drifted in these fields and was sent them again.
+    Reapplied(Vec<&'static str>),
+    /// Has drifted in these fields and was left as it is under the target's policy.
+    Drifted(Vec<&'static str>),
+    /// Had drifted, but sending the desired state failed.
+    Failed(ErrorResponse),
+}
+
+#[derive(Debug)]
+pub struct Reconciled {
+    /// Record id of the bulb or group whose desired state applies.
+    pub target: String,
+    pub bulb: String,
+    pub action: Action,
+}
+
+/// Keeps the bulbs in a [`Registry`] in their desired state by polling each with `getPilot`
+/// and re-sending that state, as its target's [`Policy`] allows, when a bulb has drifted or
+/// comes back after being unreachable.
+#[derive(Debug, Default)]
+pub struct Reconciler {
+    /// Whether each bulb answered on the last pass.
+    reachable: HashMap<IpAddr, bool>,
+}
+
+impl Reconciler {
+    pub fn new() -> Reconciler {
+        Reconciler::default()
+    }
+
+    /// Checks every bulb with a desired state once. A bulb's own desired state takes
+    /// precedence over that of a group it's in.
+    pub fn step(&mut self, registry: &mut Registry) -> Vec<Reconciled> {
+        let Registry { bulbs, groups, desired, .. } = registry;
+        let mut covered = vec![];
+        let mut results = vec![];
+
+        for b in bulbs.iter_mut() {
+            if let Some(d) = desired.get(&b.query_id_string()) {
+                covered.push(b.ip());
+                results.push(self.check(b, d));
+            }
+        }
+        for g in groups.iter_mut() {
+            let d = match desired.get(&g.query_id_string()) {
+                Some(d) => d,
+                None => continue,
+            };
+            for b in g.bulbs_mut() {
+                if !covered.contains(&b.ip()) {
+                    covered.push(b.ip());
+                    results.push(self.check(b, d));
+                }
+            }
+        }
+
+        results
+    }
+
+    fn check(&mut self, b: &mut Bulb, desired: &Desired) -> Reconciled {
+        let observed = b.get_pilot();
+        let was_reachable = self.reachable.insert(b.ip(), observed.is_ok());
+
+        let action = match observed {
+            Err(_) => Action::Unreachable,
+            Ok(observed) => {
+                let drifted = observed.result.differing_fields(&desired.pilot);
+                let reappeared = was_reachable == Some(false);
+
+                match desired.policy {
+                    _ if drifted.is_empty() => Action::InSync,
+                    Policy::Enforce => reapply(b, desired, drifted),
+                    Policy::Reappear if reappeared => reapply(b, desired, drifted),
+                    Policy::Reappear | Policy::Observe => Action::Drifted(drifted),
+                }
+            }
+        };
+
+        Reconciled {
+            target: desired.target.clone(),
+            bulb: b.name.clone(),
+            action,
+        }
+    }
+
+    /// Calls [`Reconciler::step`] every `every` until the task is dropped or aborted, taking
+    /// the registry's lock for each pass.
+    pub async fn run(mut self, registry: Arc<Mutex<Registry>>, clock: Arc<dyn Clock>, every: Duration) {
+        loop {
+            let shared = registry.clone();
+            self = tokio::task::spawn_blocking(move || {
+                self.step(&mut shared.lock().unwrap());
+                self
+            })
+            .await
+            .unwrap();
+
+            clock.sleep_until(clock.now() + every).await;
+        }
+    }
+}
+
+/// Sends `b` the fields of its desired state it has drifted in, along with `state`.
+fn reapply(b: &mut Bulb, desired: &Desired, drifted: Vec<&'static str>) -> Action {
+    let delivery = b.delivery();
+    match b.send_pilot(SetPilot { params: desired.pilot.only(&drifted), ..Default::default() }, delivery) {
+        Ok(_) => {
+            info!("{} had drifted in {:?}, put it back", b.name, drifted);
+            Action::Reapplied(drifted)
+        }
+        Err(e) => {
+            warn!("{} had drifted in {:?} and could not be put back: {}", b.name, drifted, e);
+            Action::Failed(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use surrealdb::sql::Id;
+
+    use crate::bulb::record::{Direction, Record, Recorder};
+    use crate::registry::tests::create_memory_db;
+    use crate::registry::GraphStore;
+    use crate::sim::{SimState, VirtualBulb};
+
+    use super::*;
+
+    fn warm_half() -> SetPilotParams {
+        SetPilotParams { state: Some(true), dimming: Some(50), temp: Some(2700), ..Default::default() }
+    }
+
+    async fn registry_with_group(sims: &[VirtualBulb], policy: Policy) -> Registry {
+        let db = create_memory_db().await;
+        db.use_ns("test").use_db("test").await.unwrap();
+        let group = VirtualBulb::group(sims, Id::from(7), "hall");
+        let mut registry = Registry { db, bulbs: vec![], groups: vec![group], desired: HashMap::new() };
+        registry.set_policy_by_id(Id::from(7), policy).await.unwrap();
+        registry.set_desired_by_id(Id::from(7), warm_half()).await.unwrap();
+
+        registry
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_enforce_puts_back_manual_change() {
+        let sims = VirtualBulb::many(2).unwrap();
+        let mut registry = registry_with_group(&sims, Policy::Enforce).await;
+        let mut reconciler = Reconciler::new();
+        sims[1].update(|s| s.state = false);
+
+        let results = reconciler.step(&mut registry);
+
+        assert!(matches!(results[0].action, Action::InSync));
+        assert!(matches!(&results[1].action, Action::Reapplied(fields) if fields == &vec!["state"]));
+        assert!(sims[1].state().state);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_enforce_sends_only_drifted_fields() {
+        let sims = VirtualBulb::many(2).unwrap();
+        let mut registry = registry_with_group(&sims, Policy::Enforce).await;
+        let path = std::env::temp_dir().join(format!("wiz-reapply-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        registry.groups[0].bulbs_mut()[1].set_transport(Arc::new(Recorder::udp(&path).unwrap()));
+        sims[1].update(|s| s.dimming = 90);
+
+        Reconciler::new().step(&mut registry);
+
+        let sent: Vec<SetPilot> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Record>(line).unwrap())
+            .filter(|r| r.direction == Direction::Request && r.text.contains("setPilot"))
+            .map(|r| serde_json::from_str(&r.text).unwrap())
+            .collect();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].params, SetPilotParams { state: Some(true), dimming: Some(50), ..Default::default() });
+        assert_eq!(sims[1].state().dimming, 50);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_reappear_leaves_manual_change_but_fixes_power_cycle() {
+        let mut sims = VirtualBulb::many(2).unwrap();
+        let mut registry = registry_with_group(&sims, Policy::Reappear).await;
+        let mut reconciler = Reconciler::new();
+
+        sims[0].update(|s| s.dimming = 100);
+        sims[1].go_offline();
+        let results = reconciler.step(&mut registry);
+        assert!(matches!(&results[0].action, Action::Drifted(fields) if fields == &vec!["dimming"]));
+        assert!(matches!(results[1].action, Action::Unreachable));
+
+        sims[1].update(|s| *s = SimState { state: true, mac: s.mac.clone(), ..Default::default() });
+        sims[1].come_back(false).unwrap();
+        let results = reconciler.step(&mut registry);
+
+        assert!(matches!(results[0].action, Action::Drifted(_)));
+        assert!(matches!(results[1].action, Action::Reapplied(_)));
+        assert_eq!(sims[0].state().dimming, 100);
+        assert_eq!(sims[1].state().dimming, 50);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_observe_and_bulb_precedence() {
+        let sims = VirtualBulb::many(2).unwrap();
+        let mut registry = registry_with_group(&sims, Policy::Observe).await;
+        registry.bulbs.push(sims[0].bulb("hall", 0));
+        registry.set_desired_by_id(Id::from(0), SetPilotParams { dimming: Some(80), ..Default::default() }).await.unwrap();
+        sims[1].update(|s| s.temp = Some(6500));
+
+        let results = Reconciler::new().step(&mut registry);
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].target, "bulb:0");
+        assert!(matches!(results[0].action, Action::InSync));
+        assert!(matches!(&results[1].action, Action::Drifted(fields) if fields == &vec!["temp"]));
+        assert_eq!(sims[1].state().temp, Some(6500));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_desired_state_survives_reload() {
+        let sims = VirtualBulb::many(1).unwrap();
+        let registry = registry_with_group(&sims, Policy::Reappear).await;
+        registry.groups[0].store(&registry.db).await.unwrap();
+
+        let reloaded = Registry::new(registry.db.clone()).await;
+
+        let desired = reloaded.desired_by_id(Id::from(7)).unwrap();
+        assert_eq!(desired.pilot, warm_half());
+        assert_eq!(desired.policy, Policy::Reappear);
+    }
+}
@@ -0,0 +1,313 @@
+use std::any::Any;
+
+use async_trait::async_trait;
+use log::error;
+use serde::{Deserialize, Serialize};
+use surrealdb::engine::any;
+use surrealdb::error::Db as SDb;
+use surrealdb::sql::Thing;
+use surrealdb::Surreal;
+
+use crate::bulb::method::SetPilot;
+use crate::bulb::response::ErrorResponse;
+use crate::bulb::{Bulb, SetPilotParams};
+use crate::color::Color;
+use crate::function::{Off, On};
+use crate::registry::surreal::{GraphLink, GraphStore};
+use crate::registry::Group;
+
+/// A saved look: the pilot each of its targets, single bulbs or whole groups, should be sent.
+///
+/// It's stored as a `scene` record with a `sets` edge to every target, and the edge carries
+/// that target's params and its place in the list.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Scene {
+    pub name: String,
+    targets: Vec<(Box<dyn GraphStore>, SetPilotParams)>,
+}
+
+/// One `sets` edge as read back from the database.
+#[derive(Deserialize, Debug)]
+struct Sets {
+    out: Thing,
+    params: SetPilotParams,
+}
+
+impl Scene {
+    pub fn new(name: String, targets: Vec<(Box<dyn GraphStore>, SetPilotParams)>) -> Scene {
+        Scene { name, targets }
+    }
+
+    pub fn targets(&self) -> &[(Box<dyn GraphStore>, SetPilotParams)] {
+        &self.targets
+    }
+
+    /// The look [`Group::apply_palette`] gives `group` with `palette`, one target per member.
+    pub fn from_palette(name: String, group: &Group, palette: &[Color]) -> Scene {
+        let targets = match palette.len() {
+            0 => vec![],
+            n => group
+                .bulbs()
+                .into_iter()
+                .enumerate()
+                .map(|(i, b)| {
+                    let params = SetPilot::default().state(true).color(palette[i % n]).to_owned().params;
+                    (Box::new(b.clone()) as Box<dyn GraphStore>, params)
+                })
+                .collect(),
+        };
+
+        Scene { name, targets }
+    }
+
+    /// Sends every target its params at the same time, a thread each, and reports how each
+    /// went by its record id, e.g. `bulb:3`. Every bulb, on its own or in a group, is sent
+    /// with its own delivery; a group set up for it broadcasts once.
+    pub fn activate(&mut self) -> Vec<(String, Result<bool, ErrorResponse>)> {
+        std::thread::scope(|s| {
+            let running: Vec<_> = self
+                .targets
+                .iter_mut()
+                .map(|(target, params)| {
+                    let id = target.query_id_string();
+                    let p = SetPilot { params: params.clone(), ..Default::default() };
+                    (id, s.spawn(move || apply(target.as_mut(), p)))
+                })
+                .collect();
+
+            running
+                .into_iter()
+                .map(|(id, handle)| {
+                    let result = handle.join().unwrap();
+                    if let Err(e) = &result {
+                        error!("{} did not take the scene: {}", id, e);
+                    }
+                    (id, result)
+                })
+                .collect()
+        })
+    }
+
+    /// Reads back the scene called `name` with all of its targets.
+    pub async fn get(db: &Surreal<any::Any>, name: &str) -> surrealdb::Result<Option<Scene>> {
+        let mut q = db
+            .query(
+                "SELECT name FROM type::thing('scene', $name); \
+                 SELECT out, params, position FROM sets WHERE in = type::thing('scene', $name) ORDER BY position;",
+            )
+            .bind(("name", name))
+            .await?;
+
+        let found: Option<String> = q.take((0, "name"))?;
+        if found.is_none() {
+            return Ok(None);
+        }
+        let edges: Vec<Sets> = q.take(1)?;
+
+        let mut targets: Vec<(Box<dyn GraphStore>, SetPilotParams)> = vec![];
+        for edge in edges {
+            if edge.out.tb == "bulb" {
+                targets.push((Box::new(Bulb::get(db, edge.out.id).await?), edge.params));
+            } else if edge.out.tb == "group" {
+                targets.push((Box::new(Group::collect(edge.out.id, db).await?), edge.params));
+            }
+        }
+
+        Ok(Some(Scene::new(name.to_string(), targets)))
+    }
+}
+
+fn apply(target: &mut dyn GraphStore, p: SetPilot) -> Result<bool, ErrorResponse> {
+    let any = target.as_any_mut();
+    if let Some(b) = any.downcast_mut::<Bulb>() {
+        let delivery = b.delivery();
+        b.send_pilot(p, delivery)
+    } else if let Some(g) = any.downcast_mut::<Group>() {
+        g.send_pilot_each(p)
+    } else {
+        Err(ErrorResponse::default())
+    }
+}
+
+impl PartialEq for Scene {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.targets.len() == other.targets.len()
+            && self
+                .targets
+                .iter()
+                .all(|(t, p)| other.targets.iter().any(|(o, q)| GraphStore::eq(t.as_ref(), o.as_ref()) && p == q))
+    }
+}
+
+/// Turning a scene on shows it.
+impl On for Scene {
+    fn on(&mut self) -> Result<bool, ErrorResponse> {
+        let mut success = true;
+        for (_, result) in self.activate() {
+            success &= result?;
+        }
+
+        Ok(success)
+    }
+}
+
+impl Off for Scene {
+    fn off(&mut self) -> Result<bool, ErrorResponse> {
+        for (target, _) in self.targets.iter_mut() {
+            target.off()?;
+        }
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+#[typetag::serde]
+impl GraphLink for Scene {
+    fn query_id_string(&self) -> String {
+        Thing::from(("scene", self.name.as_str())).to_string()
+    }
+}
+
+#[async_trait]
+#[typetag::serde]
+impl GraphStore for Scene {
+    /// Saves the scene and its targets, replacing whatever was saved under its name before.
+    async fn store(&self, db: &Surreal<any::Any>) -> surrealdb::Result<()> {
+        db.query("DELETE sets WHERE in = type::thing('scene', $name); UPDATE type::thing('scene', $name) SET name = $name;")
+            .bind(("name", self.name.as_str()))
+            .await?
+            .check()?;
+
+        for (position, (target, params)) in self.targets.iter().enumerate() {
+            match target.store(db).await {
+                Err(surrealdb::Error::Db(SDb::RecordExists { .. })) | Ok(_) => {}
+                Err(e) => return Err(e),
+            }
+
+            db.query(format!(
+                "RELATE $scene->sets->{target} CONTENT {{ params: $params, position: $position }};",
+                target = target.query_id_string(),
+            ))
+            .bind(("scene", Thing::from(("scene", self.name.as_str()))))
+            .bind(("params", params.clone()))
+            .bind(("position", position))
+            .await?
+            .check()?;
+        }
+
+        Ok(())
+    }
+
+    fn upcast(&self) -> &dyn GraphLink {
+        self
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn eq(&self, etc: &dyn GraphStore) -> bool {
+        match etc.as_any().downcast_ref::<Scene>() {
+            Some(other) => self == other,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use surrealdb::sql::Id;
+
+    use crate::bulb::Delivery;
+    use crate::registry::tests::create_memory_db;
+    use crate::sim::{FaultProfile, VirtualBulb};
+
+    use super::*;
+
+    fn look(dimming: u32) -> SetPilotParams {
+        SetPilotParams { state: Some(true), dimming: Some(dimming), ..Default::default() }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_store_get_scene() {
+        let sims = VirtualBulb::many(3).unwrap();
+        let db = create_memory_db().await;
+        db.use_ns("test").use_db("test").await.unwrap();
+        let group = Group::new(
+            Id::from(5),
+            "desk".to_string(),
+            vec![Box::new(sims[1].bulb("lamp", 1)), Box::new(sims[2].bulb("strip", 2))],
+        );
+        let scene = Scene::new(
+            "reading corner".to_string(),
+            vec![(Box::new(sims[0].bulb("ceiling", 0)), look(80)), (Box::new(group), look(30))],
+        );
+
+        scene.store(&db).await.unwrap();
+        scene.store(&db).await.unwrap();
+
+        let stored = Scene::get(&db, "reading corner").await.unwrap().unwrap();
+        assert_eq!(stored, scene);
+        assert_eq!(stored.targets().len(), 2);
+        let desk = stored.targets()[1].0.as_any().downcast_ref::<Group>().unwrap();
+        assert_eq!(desk.bulbs().len(), 2);
+        assert!(Scene::get(&db, "nothing").await.unwrap().is_none());
+    }
+
+    #[rstest]
+    #[case("foo\\")]
+    #[case("a⟩; DELETE bulb; --")]
+    #[tokio::test]
+    async fn test_awkward_scene_names(#[case] name: &str) {
+        let sim = VirtualBulb::new().unwrap();
+        let db = create_memory_db().await;
+        db.use_ns("test").use_db("test").await.unwrap();
+        let scene = Scene::new(name.to_string(), vec![(Box::new(sim.bulb("lamp", 1)), look(60))]);
+
+        scene.store(&db).await.unwrap();
+
+        assert_eq!(Scene::get(&db, name).await.unwrap().unwrap(), scene);
+        assert!(Bulb::get(&db, Id::from(1)).await.is_ok());
+    }
+
+    #[rstest]
+    fn test_activate_reports_each_target() {
+        let sims = VirtualBulb::many(2).unwrap();
+        sims[1].set_faults(FaultProfile { loss: 1.0, ..Default::default() });
+        let mut scene = Scene::new(
+            "evening".to_string(),
+            vec![(Box::new(sims[0].bulb("lamp", 1)), look(40)), (Box::new(sims[1].bulb("dead", 2)), look(40))],
+        );
+
+        let results = scene.activate();
+
+        assert_eq!(results[0].0, "bulb:1");
+        assert!(results[0].1.as_ref().unwrap());
+        assert_eq!(results[1].0, "bulb:2");
+        assert!(results[1].1.is_err());
+        assert_eq!(sims[0].state().dimming, 40);
+    }
+
+    #[rstest]
+    fn test_activate_group_uses_member_delivery() {
+        let sims = VirtualBulb::many(2).unwrap();
+        sims[1].set_faults(FaultProfile { loss: 1.0, ..Default::default() });
+        let mut g = VirtualBulb::group(&sims, Id::from(3), "shelf");
+        g.bulbs_mut()[1].set_delivery(Delivery::FireAndForget);
+        let mut scene = Scene::new("evening".to_string(), vec![(Box::new(g), look(40))]);
+
+        let results = scene.activate();
+
+        assert_eq!(results[0].0, "group:3");
+        assert!(results[0].1.as_ref().unwrap());
+        assert_eq!(sims[0].state().dimming, 40);
+    }
+}
@@ -0,0 +1,17 @@
+use surrealdb::engine::any;
+use surrealdb::Surreal;
+
+use crate::bulb::Snapshot;
+
+impl Snapshot {
+    /// Saves the snapshot as `name`, replacing any saved under that name before.
+    pub async fn store(&self, db: &Surreal<any::Any>, name: &str) -> surrealdb::Result<()> {
+        let _: Option<Snapshot> = db.update(("snapshot", name)).content(self).await?;
+
+        Ok(())
+    }
+
+    pub async fn get(db: &Surreal<any::Any>, name: &str) -> surrealdb::Result<Option<Snapshot>> {
+        db.select(("snapshot", name)).await
+    }
+}
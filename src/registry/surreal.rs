@@ -40,6 +40,7 @@ pub trait GraphStore: GraphLink + Debug + Any + Send {
     async fn store(&self, db: &Surreal<any::Any>) -> surrealdb::Result<()>;
     fn upcast(&self) -> &dyn GraphLink;
     fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
     fn eq(&self, etc: &dyn GraphStore) -> bool;
 }
 
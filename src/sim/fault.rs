@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+/// How a virtual bulb misbehaves. Every probability is in `0.0..=1.0`, and the same `seed`
+/// always produces the same sequence of faults for the same sequence of packets.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FaultProfile {
+    pub seed: u64,
+    /// Requests dropped before the bulb sees them.
+    pub loss: f64,
+    /// Replies dropped after the bulb has already applied the request.
+    pub reply_loss: f64,
+    pub latency: Latency,
+    /// Replies sent twice.
+    pub duplication: f64,
+    /// Replies cut short so they are no longer valid JSON.
+    pub corruption: f64,
+    /// Requests answered with a random JSON-RPC error instead of being applied.
+    pub error_rate: f64,
+}
+
+/// Delay before each reply goes out. Delays are drawn per reply, so variable latency also
+/// reorders replies to back-to-back requests.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Latency {
+    #[default]
+    None,
+    Fixed(Duration),
+    Uniform(Duration, Duration),
+    /// Exponentially distributed around the given mean, i.e. mostly quick with a long tail.
+    Exponential(Duration),
+}
+
+/// What to do with one request, decided up front.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Fate {
+    pub drop_request: bool,
+    pub error: Option<(i32, &'static str)>,
+    pub drop_reply: bool,
+    pub delay: Duration,
+    pub copies: usize,
+    pub truncate_at: Option<f64>,
+}
+
+const ERRORS: [(i32, &str); 5] = [
+    (-32700, "Parse error"),
+    (-32600, "Invalid Request"),
+    (-32601, "Method not found"),
+    (-32602, "Invalid params"),
+    (-32603, "Internal error"),
+];
+
+/// A [`FaultProfile`] together with the random stream it draws from.
+#[derive(Debug, Clone)]
+pub(crate) struct Chaos {
+    profile: FaultProfile,
+    rng: SplitMix64,
+}
+
+impl Chaos {
+    pub fn new(profile: FaultProfile) -> Chaos {
+        Chaos {
+            rng: SplitMix64(profile.seed),
+            profile,
+        }
+    }
+
+    pub fn fate(&mut self) -> Fate {
+        // always draw every value so one knob doesn't shift the stream for the others
+        let drop_request = self.rng.chance(self.profile.loss);
+        let error = self.rng.chance(self.profile.error_rate);
+        let error_pick = self.rng.next_f64();
+        let drop_reply = self.rng.chance(self.profile.reply_loss);
+        let delay = self.delay();
+        let duplicate = self.rng.chance(self.profile.duplication);
+        let corrupt = self.rng.chance(self.profile.corruption);
+        let cut = self.rng.next_f64();
+
+        Fate {
+            drop_request,
+            error: error.then(|| ERRORS[(error_pick * ERRORS.len() as f64) as usize % ERRORS.len()]),
+            drop_reply,
+            delay,
+            copies: if duplicate { 2 } else { 1 },
+            truncate_at: corrupt.then_some(cut),
+        }
+    }
+
+    fn delay(&mut self) -> Duration {
+        let x = self.rng.next_f64();
+        match self.profile.latency {
+            Latency::None => Duration::ZERO,
+            Latency::Fixed(d) => d,
+            Latency::Uniform(min, max) => min + (max.saturating_sub(min)).mul_f64(x),
+            Latency::Exponential(mean) => mean.mul_f64(-(1.0 - x).ln()),
+        }
+    }
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Chaos::new(FaultProfile::default())
+    }
+}
+
+/// Small, stable PRNG so a seed means the same thing across platforms and releases.
+#[derive(Debug, Clone)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        self.next_f64() < p
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_same_seed_same_fates() {
+        let profile = FaultProfile {
+            seed: 42,
+            loss: 0.3,
+            duplication: 0.2,
+            error_rate: 0.1,
+            latency: Latency::Uniform(Duration::from_millis(5), Duration::from_millis(80)),
+            ..Default::default()
+        };
+        let mut a = Chaos::new(profile.clone());
+        let mut b = Chaos::new(profile);
+
+        for _ in 0..100 {
+            assert_eq!(a.fate(), b.fate());
+        }
+    }
+
+    #[rstest]
+    fn test_loss_rate_is_roughly_honoured() {
+        let mut chaos = Chaos::new(FaultProfile {
+            seed: 7,
+            loss: 0.25,
+            ..Default::default()
+        });
+
+        let dropped = (0..10_000).filter(|_| chaos.fate().drop_request).count();
+
+        assert!((2_300..2_700).contains(&dropped), "dropped {}", dropped);
+    }
+
+    #[rstest]
+    fn test_default_profile_is_well_behaved() {
+        let mut chaos = Chaos::default();
+
+        assert_eq!(
+            chaos.fate(),
+            Fate {
+                drop_request: false,
+                error: None,
+                drop_reply: false,
+                delay: Duration::ZERO,
+                copies: 1,
+                truncate_at: None,
+            }
+        );
+    }
+}
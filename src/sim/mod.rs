@@ -0,0 +1,469 @@
+//! Virtual WiZ bulbs for tests and demos.
+//!
+//! Each [`VirtualBulb`] answers the bulb protocol on its own loopback address at the usual
+//! port, so a plain `Bulb::new(sim.ip(), ..)` talks to it like real hardware. Addresses are
+//! handed out from `127.77.0.0/16`, which relies on the whole `127.0.0.0/8` block routing to
+//! loopback as it does on Linux.
+//!
+//! A [`FaultProfile`] makes a bulb lossy, slow or broken in a reproducible way, and
+//! [`VirtualBulb::go_offline`]/[`VirtualBulb::come_back`] mimic it dropping off the network.
+//! A [`VirtualNetwork`] puts several bulbs behind one broadcast address for discovery.
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log::{error, info};
+
+use surrealdb::sql::Id;
+
+use crate::bulb::Bulb;
+use crate::registry::{GraphStore, Group};
+use fault::Chaos;
+pub use fault::{FaultProfile, Latency};
+pub use network::VirtualNetwork;
+pub use state::SimState;
+
+mod fault;
+mod network;
+mod state;
+
+const WIZ_PORT: u16 = 38899;
+
+static NEXT_ADDRESS: AtomicU32 = AtomicU32::new(1);
+
+fn bind_at(ip: Ipv4Addr) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(ip), WIZ_PORT))?;
+    socket.set_read_timeout(Some(Duration::from_millis(50)))?;
+
+    Ok(socket)
+}
+
+/// Binds a fresh loopback address that no other simulator in this process is using.
+fn bind_loopback() -> io::Result<(Ipv4Addr, UdpSocket)> {
+    loop {
+        let n = NEXT_ADDRESS.fetch_add(1, Ordering::Relaxed);
+        let [_, _, hi, lo] = n.to_be_bytes();
+        if lo == 0 || lo == 255 {
+            continue;
+        }
+        if n > 0xffff {
+            return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, "ran out of simulator addresses"));
+        }
+
+        let ip = Ipv4Addr::new(127, 77, hi, lo);
+        match bind_at(ip) {
+            Ok(socket) => return Ok((ip, socket)),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A single simulated bulb, served from a background thread until dropped.
+#[derive(Debug)]
+pub struct VirtualBulb {
+    ip: Ipv4Addr,
+    endpoint: Arc<Endpoint>,
+    server: Option<Server>,
+}
+
+impl VirtualBulb {
+    pub fn new() -> io::Result<VirtualBulb> {
+        VirtualBulb::with_state(SimState::default())
+    }
+
+    /// Starts a bulb from `state`. The MAC is replaced with one derived from the bulb's
+    /// address when left at the default, so several simulators stay distinguishable.
+    pub fn with_state(mut state: SimState) -> io::Result<VirtualBulb> {
+        let (ip, socket) = bind_loopback()?;
+
+        if state.mac == SimState::default().mac {
+            let [_, _, hi, lo] = ip.octets();
+            state.mac = format!("a8bb50ff{:02x}{:02x}", hi, lo);
+        }
+
+        let endpoint = Arc::new(Endpoint {
+            state: Mutex::new(state),
+            chaos: Mutex::new(Chaos::default()),
+            socket: Mutex::new(None),
+        });
+        let server = endpoint.serve(socket);
+
+        Ok(VirtualBulb {
+            ip,
+            endpoint,
+            server: Some(server),
+        })
+    }
+
+    /// Starts `n` independent bulbs.
+    pub fn many(n: usize) -> io::Result<Vec<VirtualBulb>> {
+        (0..n).map(|_| VirtualBulb::new()).collect()
+    }
+
+    pub fn ip(&self) -> Ipv4Addr {
+        self.ip
+    }
+
+    /// A `Bulb` pointed at this simulator.
+    pub fn bulb(&self, name: &str, id: u32) -> Bulb {
+        Bulb::new(IpAddr::V4(self.ip), name.to_string(), id)
+    }
+
+    /// A `Group` of bulbs pointed at `sims`, each named after the group and numbered in order.
+    pub fn group(sims: &[VirtualBulb], id: Id, name: &str) -> Group {
+        let members = sims.iter().enumerate().map(|(i, s)| Box::new(s.bulb(name, i as u32)) as Box<dyn GraphStore>);
+        Group::new(id, name.to_string(), members.collect())
+    }
+
+    /// A copy of the bulb's current state.
+    pub fn state(&self) -> SimState {
+        self.endpoint.state.lock().unwrap().clone()
+    }
+
+    /// Changes the state behind the protocol's back, e.g. to mimic the wall switch or app.
+    pub fn update(&self, f: impl FnOnce(&mut SimState)) {
+        f(&mut self.endpoint.state.lock().unwrap());
+    }
+
+    /// Applies `profile` to every packet from now on, restarting its random stream.
+    pub fn set_faults(&self, profile: FaultProfile) {
+        *self.endpoint.chaos.lock().unwrap() = Chaos::new(profile);
+    }
+
+    /// Stops answering and releases the address, as a bulb losing power would.
+    pub fn go_offline(&mut self) {
+        *self.endpoint.socket.lock().unwrap() = None;
+        if let Some(server) = self.server.take() {
+            server.stop();
+        }
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.server.is_some()
+    }
+
+    /// Comes back on the network with its state intact, on the old address or, with
+    /// `new_address`, on a fresh one as if DHCP had handed out a different lease.
+    pub fn come_back(&mut self, new_address: bool) -> io::Result<Ipv4Addr> {
+        self.go_offline();
+
+        let socket = if new_address {
+            let (ip, socket) = bind_loopback()?;
+            self.ip = ip;
+            socket
+        } else {
+            bind_at(self.ip)?
+        };
+        self.server = Some(self.endpoint.serve(socket));
+
+        Ok(self.ip)
+    }
+}
+
+impl Drop for VirtualBulb {
+    fn drop(&mut self) {
+        self.go_offline();
+    }
+}
+
+/// The parts of a virtual bulb shared between its own server and any broadcast address
+/// it listens on.
+#[derive(Debug)]
+struct Endpoint {
+    state: Mutex<SimState>,
+    chaos: Mutex<Chaos>,
+    /// Where replies go out from; `None` while offline.
+    socket: Mutex<Option<Arc<UdpSocket>>>,
+}
+
+impl Endpoint {
+    fn serve(self: &Arc<Self>, socket: UdpSocket) -> Server {
+        let socket = Arc::new(socket);
+        *self.socket.lock().unwrap() = Some(socket.clone());
+
+        let endpoint = self.clone();
+        Server::start(socket, move |socket, datagram, from| endpoint.respond(socket, datagram, from))
+    }
+
+    /// Answers a datagram that arrived on a shared broadcast address, from the bulb's own
+    /// address like the real thing.
+    fn respond_broadcast(&self, datagram: &[u8], from: SocketAddr) {
+        let socket = self.socket.lock().unwrap().clone();
+        if let Some(socket) = socket {
+            self.respond(&socket, datagram, from);
+        }
+    }
+
+    fn respond(&self, socket: &UdpSocket, datagram: &[u8], from: SocketAddr) {
+        let fate = self.chaos.lock().unwrap().fate();
+        if fate.drop_request {
+            info!("sim {} dropped request from {}", socket.local_addr().map(|a| a.ip().to_string()).unwrap_or_default(), from);
+            return;
+        }
+
+        let reply = match fate.error {
+            Some((code, message)) => state::error("unknown", code, message),
+            None => self.state.lock().unwrap().handle(datagram),
+        };
+        info!("sim {} -> {}: {}", socket.local_addr().map(|a| a.ip().to_string()).unwrap_or_default(), from, reply);
+        if fate.drop_reply {
+            return;
+        }
+
+        let mut bytes = reply.to_string().into_bytes();
+        if let Some(cut) = fate.truncate_at {
+            bytes.truncate((bytes.len() as f64 * cut) as usize);
+        }
+
+        let send = move |socket: &UdpSocket| {
+            for _ in 0..fate.copies {
+                if let Err(e) = socket.send_to(&bytes, from) {
+                    error!("Simulator failed to reply to {}: {}", from, e);
+                }
+            }
+        };
+        if fate.delay.is_zero() {
+            send(socket);
+        } else {
+            match socket.try_clone() {
+                Ok(delayed) => {
+                    std::thread::spawn(move || {
+                        std::thread::sleep(fate.delay);
+                        send(&delayed);
+                    });
+                }
+                Err(e) => error!("Simulator failed to delay reply: {}", e),
+            }
+        }
+    }
+}
+
+/// A background thread feeding every datagram on a socket to a handler.
+#[derive(Debug)]
+struct Server {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl Server {
+    fn start<F>(socket: Arc<UdpSocket>, mut on_datagram: F) -> Server
+    where
+        F: FnMut(&UdpSocket, &[u8], SocketAddr) + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                let mut buff = [0; 1024];
+
+                while !stop.load(Ordering::Relaxed) {
+                    match socket.recv_from(&mut buff) {
+                        Ok((len, from)) => on_datagram(&socket, &buff[..len], from),
+                        Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+                        Err(e) => {
+                            error!("Simulator socket failed: {}", e);
+                            return;
+                        }
+                    }
+                }
+            })
+        };
+
+        Server { stop, handle }
+    }
+
+    fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.handle.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bulb::method::{SetPilot, SetPilotParams};
+    use crate::bulb::Delivery;
+    use crate::color::Color;
+    use crate::function::{Off, On};
+    use rstest::{fixture, rstest};
+
+    #[fixture]
+    fn sim() -> VirtualBulb {
+        VirtualBulb::new().unwrap()
+    }
+
+    #[rstest]
+    fn test_get_pilot(sim: VirtualBulb) {
+        let pilot = sim.bulb("sim", 0).get_pilot().unwrap();
+
+        assert_eq!(pilot.result.mac, sim.state().mac);
+        assert_eq!(pilot.result.temp, Some(2700));
+        assert!(!pilot.result.state);
+    }
+
+    #[rstest]
+    #[case(
+        SetPilot::default(),
+        r#"{"Err":{"method":"setPilot","error":{"code":-32600,"message":"Invalid Request"}}}"#
+    )]
+    #[case(
+        SetPilot { params: SetPilotParams { dimming: Some(5), ..Default::default() }, ..Default::default() },
+        r#"{"Err":{"method":"setPilot","error":{"code":-32602,"message":"Invalid params"}}}"#
+    )]
+    #[case(SetPilot { params: SetPilotParams { state: Some(true), ..Default::default()}, ..Default::default()}, r#"{"Ok":{"method":"setPilot","result":{"success":true}}}"#)]
+    fn test_set_pilot(sim: VirtualBulb, #[case] method: SetPilot, #[case] expected_message: &str) {
+        let response = sim.bulb("sim", 0).set_pilot(method);
+
+        assert_eq!(serde_json::to_string(&response).unwrap(), expected_message);
+    }
+
+    #[rstest]
+    fn test_color_replaces_temperature(sim: VirtualBulb) {
+        let p = SetPilot::default().color(Color::rgb(255, 136, 0)).brightness(40).to_owned();
+        sim.bulb("sim", 0).set_pilot(p).unwrap();
+
+        let state = sim.state();
+        assert_eq!(state.rgb, Some((255, 136, 0)));
+        assert_eq!(state.temp, None);
+        assert_eq!(state.dimming, 40);
+        assert!(state.state);
+    }
+
+    #[rstest]
+    fn test_on_off(sim: VirtualBulb) {
+        let mut b = sim.bulb("sim", 0);
+
+        assert!(b.on().unwrap());
+        assert!(sim.state().state);
+        assert!(b.get_state().unwrap());
+
+        assert!(b.off().unwrap());
+        assert!(!sim.state().state);
+    }
+
+    #[rstest]
+    #[case(Delivery::FireAndForget)]
+    #[case(Delivery::Acknowledged)]
+    #[case(Delivery::ReadBack)]
+    #[case(Delivery::Verified { retries: 1 })]
+    fn test_send_pilot(sim: VirtualBulb, #[case] delivery: Delivery) {
+        let mut b = sim.bulb("sim", 0);
+        let p = SetPilot::default().state(true).temperature(4000).to_owned();
+
+        assert!(b.send_pilot(p, delivery).unwrap());
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(sim.state().temp, Some(4000));
+    }
+
+    #[rstest]
+    fn test_unknown_method(sim: VirtualBulb) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        socket.send_to(br#"{"method":"reboot","params":{}}"#, (sim.ip(), WIZ_PORT)).unwrap();
+
+        let mut buff = [0; 512];
+        let (len, _) = socket.recv_from(&mut buff).unwrap();
+        let reply: serde_json::Value = serde_json::from_slice(&buff[..len]).unwrap();
+
+        assert_eq!(reply["error"]["code"], -32601);
+    }
+
+    fn raw_exchange(ip: Ipv4Addr, message: &[u8], replies: usize) -> Vec<Vec<u8>> {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        socket.send_to(message, (ip, WIZ_PORT)).unwrap();
+
+        let mut buff = [0; 512];
+        let mut received = vec![];
+        while received.len() < replies {
+            match socket.recv_from(&mut buff) {
+                Ok((len, _)) => received.push(buff[..len].to_vec()),
+                Err(_) => break,
+            }
+        }
+        received
+    }
+
+    #[rstest]
+    fn test_total_loss_times_out(sim: VirtualBulb) {
+        sim.set_faults(FaultProfile { loss: 1.0, ..Default::default() });
+
+        assert!(sim.bulb("sim", 0).get_pilot().is_err());
+    }
+
+    #[rstest]
+    fn test_lost_reply_still_applies(sim: VirtualBulb) {
+        sim.set_faults(FaultProfile { reply_loss: 1.0, ..Default::default() });
+        let mut b = sim.bulb("sim", 0);
+
+        assert!(b.on().is_err());
+        assert!(sim.state().state);
+    }
+
+    #[rstest]
+    fn test_injected_errors(sim: VirtualBulb) {
+        sim.set_faults(FaultProfile { seed: 3, error_rate: 1.0, ..Default::default() });
+
+        let e = sim.bulb("sim", 0).get_pilot().unwrap_err();
+
+        assert!((-32700..=-32600).contains(&e.error.code));
+    }
+
+    #[rstest]
+    fn test_corrupted_reply_is_not_json(sim: VirtualBulb) {
+        sim.set_faults(FaultProfile { corruption: 1.0, ..Default::default() });
+
+        let replies = raw_exchange(sim.ip(), br#"{"method":"getPilot","params":{}}"#, 1);
+
+        assert!(serde_json::from_slice::<serde_json::Value>(&replies[0]).is_err());
+    }
+
+    #[rstest]
+    fn test_duplicated_replies(sim: VirtualBulb) {
+        sim.set_faults(FaultProfile { duplication: 1.0, ..Default::default() });
+
+        let replies = raw_exchange(sim.ip(), br#"{"method":"getPilot","params":{}}"#, 3);
+
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0], replies[1]);
+    }
+
+    #[rstest]
+    fn test_fixed_latency(sim: VirtualBulb) {
+        sim.set_faults(FaultProfile { latency: Latency::Fixed(Duration::from_millis(300)), ..Default::default() });
+
+        let start = std::time::Instant::now();
+        sim.bulb("sim", 0).get_pilot().unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[rstest]
+    fn test_come_back_on_new_address(mut sim: VirtualBulb) {
+        let mut b = sim.bulb("sim", 0);
+        b.on().unwrap();
+
+        sim.go_offline();
+        assert!(!sim.is_online());
+        assert!(b.get_pilot().is_err());
+
+        let old = sim.ip();
+        let new = sim.come_back(true).unwrap();
+        assert_ne!(old, new);
+
+        let pilot = sim.bulb("sim", 0).get_pilot().unwrap();
+        assert!(pilot.result.state);
+    }
+
+    #[rstest]
+    fn test_many_have_distinct_addresses() {
+        let sims = VirtualBulb::many(3).unwrap();
+
+        assert_ne!(sims[0].ip(), sims[1].ip());
+        assert_ne!(sims[1].state().mac, sims[2].state().mac);
+    }
+}
@@ -0,0 +1,150 @@
+use std::io;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+
+use crate::sim::{bind_loopback, Server, SimState, VirtualBulb};
+
+/// Models and firmware handed out in turn to the bulbs of a [`VirtualNetwork`].
+const MODELS: [(&str, &str); 5] = [
+    ("ESP01_SHRGB1C_31", "1.22.0"),
+    ("ESP03_SHRGB3_01ABI", "1.25.0"),
+    ("ESP06_SHDW9_01", "1.21.0"),
+    ("ESP15_SHTW1_01I", "1.24.0"),
+    ("ESP24_SHRGB_01", "1.26.1"),
+];
+
+/// Several virtual bulbs behind a shared broadcast address. Anything sent to
+/// [`VirtualNetwork::broadcast`] reaches every online member, and each answers from its own
+/// address, so `Bulb::discover_on` and broadcast groups behave as they would on a real subnet.
+/// Members that come back on a new address stay on the network.
+#[derive(Debug)]
+pub struct VirtualNetwork {
+    broadcast: Ipv4Addr,
+    bulbs: Vec<VirtualBulb>,
+    server: Option<Server>,
+}
+
+impl VirtualNetwork {
+    /// `n` bulbs with distinct MACs, cycling through a handful of module names and firmware
+    /// versions.
+    pub fn new(n: usize) -> io::Result<VirtualNetwork> {
+        let states = (0..n)
+            .map(|i| {
+                let (module_name, fw_version) = MODELS[i % MODELS.len()];
+                SimState {
+                    module_name: module_name.to_string(),
+                    fw_version: fw_version.to_string(),
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        VirtualNetwork::with_states(states)
+    }
+
+    pub fn with_states(states: Vec<SimState>) -> io::Result<VirtualNetwork> {
+        let bulbs = states
+            .into_iter()
+            .map(VirtualBulb::with_state)
+            .collect::<io::Result<Vec<_>>>()?;
+        let endpoints: Vec<_> = bulbs.iter().map(|b| b.endpoint.clone()).collect();
+
+        let (broadcast, socket) = bind_loopback()?;
+        let server = Server::start(Arc::new(socket), move |_, datagram, from| {
+            for e in endpoints.iter() {
+                e.respond_broadcast(datagram, from);
+            }
+        });
+
+        Ok(VirtualNetwork {
+            broadcast,
+            bulbs,
+            server: Some(server),
+        })
+    }
+
+    /// The address to broadcast to, standing in for e.g. `192.168.68.255`.
+    pub fn broadcast(&self) -> Ipv4Addr {
+        self.broadcast
+    }
+
+    pub fn bulbs(&self) -> &[VirtualBulb] {
+        &self.bulbs
+    }
+
+    pub fn bulbs_mut(&mut self) -> &mut [VirtualBulb] {
+        &mut self.bulbs
+    }
+}
+
+impl Drop for VirtualNetwork {
+    fn drop(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::net::IpAddr;
+
+    use rstest::rstest;
+    use surrealdb::sql::Id;
+
+    use super::*;
+    use crate::bulb::Bulb;
+    use crate::function::On;
+
+    #[rstest]
+    fn test_members_are_distinct() {
+        let net = VirtualNetwork::new(3).unwrap();
+
+        let macs: HashSet<_> = net.bulbs().iter().map(|b| b.state().mac).collect();
+        let models: HashSet<_> = net.bulbs().iter().map(|b| b.state().module_name).collect();
+
+        assert_eq!(macs.len(), 3);
+        assert_eq!(models.len(), 3);
+    }
+
+    #[rstest]
+    fn test_discover_on() {
+        let net = VirtualNetwork::new(4).unwrap();
+
+        let mut found = Bulb::discover_on(net.broadcast());
+        found.sort();
+        let mut expected: Vec<_> = net.bulbs().iter().map(|b| b.ip()).collect();
+        expected.sort();
+
+        assert_eq!(found, expected);
+    }
+
+    #[rstest]
+    fn test_resolve_mac_after_readdress() {
+        let mut net = VirtualNetwork::new(3).unwrap();
+        let broadcast = net.broadcast();
+        let mut b = net.bulbs()[1].bulb("moved", 1);
+        let mac = b.mac().unwrap();
+
+        let new_ip = net.bulbs_mut()[1].come_back(true).unwrap();
+        assert!(b.get_pilot().is_err());
+
+        assert_eq!(b.resolve_mac(&mac, broadcast).unwrap(), IpAddr::V4(new_ip));
+        assert!(b.get_pilot().is_ok());
+    }
+
+    #[rstest]
+    fn test_broadcast_group_on() {
+        let net = VirtualNetwork::new(3).unwrap();
+        net.bulbs()[2].set_faults(crate::sim::FaultProfile { loss: 1.0, ..Default::default() });
+        let mut g = VirtualBulb::group(net.bulbs(), Id::from(1), "subnet");
+        g.set_broadcast(Some(net.broadcast()));
+
+        // the lossy member never hears anything, so the group reports it
+        assert!(g.on().is_err());
+        assert!(net.bulbs()[0].state().state);
+        assert!(net.bulbs()[1].state().state);
+        assert!(!net.bulbs()[2].state().state);
+    }
+}
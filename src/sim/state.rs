@@ -0,0 +1,202 @@
+use serde_json::{json, Map, Value};
+
+use crate::color::{MAX_KELVIN, MIN_KELVIN};
+
+/// Everything a virtual bulb remembers between packets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimState {
+    pub mac: String,
+    pub module_name: String,
+    pub fw_version: String,
+    pub state: bool,
+    pub dimming: u32,
+    pub temp: Option<u32>,
+    pub rgb: Option<(u32, u32, u32)>,
+    pub c: Option<u32>,
+    pub w: Option<u32>,
+    pub scene_id: u32,
+    pub speed: Option<u32>,
+}
+
+impl Default for SimState {
+    fn default() -> Self {
+        SimState {
+            mac: "a8bb50000000".to_string(),
+            module_name: "ESP01_SHRGB1C_31".to_string(),
+            fw_version: "1.22.0".to_string(),
+            state: false,
+            dimming: 100,
+            temp: Some(2700),
+            rgb: None,
+            c: None,
+            w: None,
+            scene_id: 0,
+            speed: None,
+        }
+    }
+}
+
+// JSON-RPC error codes as the bulbs send them
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+impl SimState {
+    /// Handles one datagram the way a bulb would and returns the reply to send back.
+    pub fn handle(&mut self, datagram: &[u8]) -> Value {
+        let request: Value = match serde_json::from_slice(datagram) {
+            Ok(r) => r,
+            Err(_) => return error("unknown", PARSE_ERROR, "Parse error"),
+        };
+        let method = match request["method"].as_str() {
+            Some(m) => m.to_string(),
+            None => return error("unknown", INVALID_REQUEST, "Invalid Request"),
+        };
+        let params = request["params"].as_object().cloned().unwrap_or_default();
+
+        match method.as_str() {
+            "getPilot" => self.get_pilot(),
+            "getSystemConfig" => self.get_system_config(),
+            "setPilot" => self.set_pilot(&params),
+            "pulse" => self.pulse(&params),
+            _ => error(&method, METHOD_NOT_FOUND, "Method not found"),
+        }
+    }
+
+    fn get_pilot(&self) -> Value {
+        let mut result = json!({
+            "mac": self.mac,
+            "rssi": -55,
+            "src": "",
+            "state": self.state,
+            "sceneId": self.scene_id,
+            "dimming": self.dimming,
+        });
+        if let Some(temp) = self.temp {
+            result["temp"] = json!(temp);
+        }
+        if let Some((r, g, b)) = self.rgb {
+            result["r"] = json!(r);
+            result["g"] = json!(g);
+            result["b"] = json!(b);
+        }
+        if let Some(c) = self.c {
+            result["c"] = json!(c);
+        }
+        if let Some(w) = self.w {
+            result["w"] = json!(w);
+        }
+        if let Some(speed) = self.speed {
+            result["speed"] = json!(speed);
+        }
+
+        json!({"method": "getPilot", "env": "pro", "result": result})
+    }
+
+    fn get_system_config(&self) -> Value {
+        json!({
+            "method": "getSystemConfig",
+            "env": "pro",
+            "result": {
+                "mac": self.mac,
+                "homeId": 0,
+                "roomId": 0,
+                "moduleName": self.module_name,
+                "fwVersion": self.fw_version,
+                "groupId": 0,
+                "drvConf": [20, 2],
+                "ping": 0,
+            }
+        })
+    }
+
+    fn set_pilot(&mut self, params: &Map<String, Value>) -> Value {
+        if params.is_empty() {
+            return error("setPilot", INVALID_REQUEST, "Invalid Request");
+        }
+
+        let ranges = [
+            ("dimming", 10, 100),
+            ("temp", MIN_KELVIN as u64, MAX_KELVIN as u64),
+            ("r", 0, 255),
+            ("g", 0, 255),
+            ("b", 0, 255),
+            ("c", 0, 255),
+            ("w", 0, 255),
+            ("sceneId", 1, 1000),
+            ("speed", 20, 200),
+        ];
+        for (key, min, max) in ranges {
+            if let Some(v) = params.get(key) {
+                match v.as_u64() {
+                    Some(v) if (min..=max).contains(&v) => {}
+                    _ => return error("setPilot", INVALID_PARAMS, "Invalid params"),
+                }
+            }
+        }
+        let get = |key: &str| params.get(key).and_then(Value::as_u64).map(|v| v as u32);
+
+        if let Some(state) = params.get("state") {
+            match state.as_bool() {
+                Some(s) => self.state = s,
+                None => return error("setPilot", INVALID_PARAMS, "Invalid params"),
+            }
+        } else {
+            // any other setPilot switches the bulb on, as the real ones do
+            self.state = true;
+        }
+        if let Some(dimming) = get("dimming") {
+            self.dimming = dimming;
+        }
+        if let Some(temp) = get("temp") {
+            self.temp = Some(temp);
+            self.rgb = None;
+            self.c = None;
+            self.w = None;
+            self.scene_id = 0;
+            self.speed = None;
+        }
+        if get("r").is_some() || get("g").is_some() || get("b").is_some() {
+            let (r, g, b) = self.rgb.unwrap_or((0, 0, 0));
+            self.rgb = Some((get("r").unwrap_or(r), get("g").unwrap_or(g), get("b").unwrap_or(b)));
+            self.temp = None;
+            self.scene_id = 0;
+            self.speed = None;
+        }
+        if get("c").is_some() || get("w").is_some() {
+            self.c = get("c").or(self.c);
+            self.w = get("w").or(self.w);
+            self.temp = None;
+            self.scene_id = 0;
+            self.speed = None;
+        }
+        if let Some(scene_id) = get("sceneId") {
+            self.scene_id = scene_id;
+            self.speed = Some(get("speed").unwrap_or(100));
+            self.temp = None;
+            self.rgb = None;
+            self.c = None;
+            self.w = None;
+        } else if let Some(speed) = get("speed") {
+            self.speed = Some(speed);
+        }
+
+        success("setPilot")
+    }
+
+    fn pulse(&mut self, params: &Map<String, Value>) -> Value {
+        match (params.get("delta").and_then(Value::as_i64), params.get("duration").and_then(Value::as_u64)) {
+            (Some(_), Some(_)) => success("pulse"),
+            _ => error("pulse", INVALID_PARAMS, "Invalid params"),
+        }
+    }
+}
+
+fn success(method: &str) -> Value {
+    json!({"method": method, "env": "pro", "result": {"success": true}})
+}
+
+pub(crate) fn error(method: &str, code: i32, message: &str) -> Value {
+    json!({"method": method, "env": "pro", "error": {"code": code, "message": message}})
+}